@@ -236,6 +236,9 @@ chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
         case 'seek':
           if (mediaEl && typeof message.seekTime === 'number') { mediaEl.currentTime = message.seekTime; sendResponse({ success: true }); return true; }
           break;
+        case 'volume':
+          if (mediaEl && typeof message.level === 'number') { mediaEl.volume = Math.max(0, Math.min(1, message.level)); sendResponse({ success: true }); return true; }
+          break;
       }
     } catch (e) { console.log('AppleMusic PLAYBACK_COMMAND failed', e); }
     sendResponse({ success: false });
@@ -1,67 +1,238 @@
 // Background script for Lyryc extension
 let currentTrack = null;
 let isConnectedToApp = false;
+let pairingRequired = false;
+let pairingToken = null;
 
 // WebSocket connection to desktop app
 let websocket = null;
+// Native messaging port, used when no WebSocket port answers (see src-tauri/src/native_messaging.rs)
+const NATIVE_HOST_NAME = 'com.lyryc.host';
+let nativePort = null;
 let reconnectAttempts = 0;
 let pingInterval = null;
 const maxReconnectAttempts = 5;
 const reconnectDelay = 3000; // 3 seconds
 
+// Ports the desktop app falls back through when 8765 is taken (see src-tauri/src/websocket.rs)
+const FALLBACK_PORTS = [8765, 8766, 8767, 8768, 8769, 8770, 8771, 8772, 8773, 8774];
+let portCandidates = FALLBACK_PORTS;
+let probeIndex = 0;
+
+// Try the last port that answered as Lyryc first, then the fallback range
+async function refreshPortCandidates() {
+  const stored = await chrome.storage.local.get('lastPort');
+  const lastPort = stored.lastPort;
+  portCandidates = lastPort
+    ? [lastPort, ...FALLBACK_PORTS.filter((port) => port !== lastPort)]
+    : FALLBACK_PORTS;
+}
+
+// Protocol version spoken with the desktop app (see src-tauri/src/protocol.rs)
+const PROTOCOL_VERSION = 1;
+const EXTENSION_CAPABILITIES = ['track_updates', 'progress_updates', 'playback_control', 'command_ack'];
+
+// Tabs each source is detected on, used to address commands to one player
+const SOURCE_URL_PATTERNS = {
+  'spotify': ['*://open.spotify.com/*'],
+  'youtube-music': ['*://music.youtube.com/*'],
+  'apple-music': ['*://music.apple.com/*'],
+  'soundcloud': ['*://soundcloud.com/*']
+};
+
+// Playback commands each content script can execute; toggle and seek_relative are
+// resolved here into play/pause and seek before reaching the tab
+const SOURCE_CAPABILITIES = {
+  'spotify': ['play', 'pause', 'toggle', 'next', 'previous', 'seek', 'seek_relative', 'volume', 'like', 'shuffle', 'repeat'],
+  'youtube-music': ['play', 'pause', 'toggle', 'next', 'previous', 'seek', 'seek_relative', 'volume', 'like', 'shuffle', 'repeat'],
+  'apple-music': ['play', 'pause', 'toggle', 'next', 'previous', 'seek', 'seek_relative', 'volume'],
+  'soundcloud': ['play', 'pause', 'toggle', 'next', 'previous', 'seek', 'seek_relative', 'volume', 'like', 'shuffle', 'repeat']
+};
+
+function detectBrowser() {
+  const ua = navigator.userAgent;
+  if (ua.includes('Edg/')) return 'edge';
+  if (ua.includes('Firefox/')) return 'firefox';
+  if (ua.includes('OPR/')) return 'opera';
+  return 'chrome';
+}
+
+async function loadPairingToken() {
+  const stored = await chrome.storage.local.get('pairingToken');
+  pairingToken = stored.pairingToken || null;
+  return pairingToken;
+}
+
+async function sendHello() {
+  const token = pairingToken || await loadPairingToken();
+  sendToApp({
+    message_type: 'HELLO',
+    data: {
+      protocol_version: PROTOCOL_VERSION,
+      extension_version: chrome.runtime.getManifest().version,
+      browser: detectBrowser(),
+      capabilities: EXTENSION_CAPABILITIES,
+      source_capabilities: SOURCE_CAPABILITIES,
+      auth_token: token
+    },
+    timestamp: Date.now()
+  });
+}
+
+function isAppChannelOpen() {
+  return !!nativePort || (!!websocket && websocket.readyState === WebSocket.OPEN);
+}
+
+// Send a message over whichever channel is open; returns false when neither is
+function sendToApp(message) {
+  if (nativePort) {
+    nativePort.postMessage(message);
+    return true;
+  }
+  if (websocket && websocket.readyState === WebSocket.OPEN) {
+    websocket.send(JSON.stringify(message));
+    return true;
+  }
+  return false;
+}
+
+function onAppChannelOpen() {
+  reconnectAttempts = 0;
+  sendHello();
+
+  // Send ping to keep connection alive (only create one interval)
+  if (!pingInterval) {
+    pingInterval = setInterval(() => {
+      if (isAppChannelOpen()) {
+        console.log('🟢 [Background] Sending ping to desktop app');
+        sendToApp({
+          message_type: 'ping',
+          data: {},
+          timestamp: Date.now()
+        });
+      }
+    }, 30000); // Every 30 seconds
+  }
+}
+
+function onAppChannelClosed() {
+  isConnectedToApp = false;
+
+  // Clear ping interval
+  if (pingInterval) {
+    clearInterval(pingInterval);
+    pingInterval = null;
+  }
+}
+
+function scheduleReconnect() {
+  if (reconnectAttempts < maxReconnectAttempts) {
+    reconnectAttempts++;
+    console.log(`🟡 [Background] Attempting to reconnect (${reconnectAttempts}/${maxReconnectAttempts})...`);
+    setTimeout(connectToDesktopApp, reconnectDelay * reconnectAttempts);
+  } else {
+    console.log('🔴 [Background] Max reconnect attempts reached, giving up');
+  }
+}
+
+// Handle a message from the desktop app; port is set for WebSocket connections
+function handleAppMessage(message, port) {
+  console.log('🟢 [Background] Received from desktop app:', message);
+
+  // Handle different message types
+  switch (message.message_type) {
+    case 'connected':
+      console.log('🟢 [Background] Desktop app acknowledged connection, protocol', message.data.protocol_version);
+      if (port) {
+        chrome.storage.local.set({ lastPort: port });
+      }
+      break;
+    case 'WELCOME':
+      console.log('🟢 [Background] Handshake complete, app version', message.data.app_version);
+      isConnectedToApp = true;
+      pairingRequired = false;
+      // Re-send the current track so the app picks up where we are
+      if (currentTrack) {
+        sendTrackToApp(currentTrack, currentTrack.isPlaying ? 'TRACK_DETECTED' : 'TRACK_PAUSED');
+      }
+      break;
+    case 'ERROR':
+      console.error('❌ [Background] Desktop app rejected message:', message.data.code, message.data.message);
+      if (message.data.code === 'UNAUTHORIZED') {
+        // Wait for the user to enter the pairing code in the popup
+        pairingRequired = true;
+        reconnectAttempts = maxReconnectAttempts;
+      }
+      break;
+    case 'pong':
+      console.log('🟢 [Background] Received pong from desktop app');
+      break;
+    case 'PLAYBACK_COMMAND':
+      console.log('🟢 [Background] Received PLAYBACK_COMMAND:', message.data);
+      // Forward playback command to content script
+      handlePlaybackCommand(message.data);
+      break;
+    default:
+      console.log('🟡 [Background] Unknown message type:', message.message_type);
+  }
+}
+
+// Reach the app through the native messaging host when localhost ports are blocked;
+// returns false when the browser has no host registered
+function connectNativeHost() {
+  if (!chrome.runtime.connectNative) return false;
+
+  try {
+    console.log(`🟡 [Background] Trying native messaging host ${NATIVE_HOST_NAME}`);
+    nativePort = chrome.runtime.connectNative(NATIVE_HOST_NAME);
+  } catch (error) {
+    console.log('🟡 [Background] Native messaging host unavailable:', error);
+    nativePort = null;
+    return false;
+  }
+
+  nativePort.onMessage.addListener((message) => handleAppMessage(message));
+  nativePort.onDisconnect.addListener(() => {
+    const error = chrome.runtime.lastError;
+    console.log('🔴 [Background] ❌ Native messaging host disconnected', error ? error.message : '');
+    nativePort = null;
+    onAppChannelClosed();
+    scheduleReconnect();
+  });
+
+  // The host relays the app's greeting once it reaches the app
+  onAppChannelOpen();
+  return true;
+}
+
 // Initialize WebSocket connection
-function connectToDesktopApp() {
+async function connectToDesktopApp() {
   // Don't create multiple connections
-  if (websocket && (websocket.readyState === WebSocket.CONNECTING || websocket.readyState === WebSocket.OPEN)) {
-    console.log('🟡 [Background] WebSocket already connecting/connected, skipping');
+  if (nativePort || (websocket && (websocket.readyState === WebSocket.CONNECTING || websocket.readyState === WebSocket.OPEN))) {
+    console.log('🟡 [Background] Already connecting/connected to desktop app, skipping');
     return;
   }
 
   try {
-    console.log('🟡 [Background] Attempting WebSocket connection to ws://localhost:8765');
-    websocket = new WebSocket('ws://localhost:8765');
+    if (probeIndex === 0) {
+      await refreshPortCandidates();
+    }
+    const port = portCandidates[probeIndex];
+    let opened = false;
+
+    console.log(`🟡 [Background] Attempting WebSocket connection to ws://localhost:${port}`);
+    websocket = new WebSocket(`ws://localhost:${port}`);
     
     websocket.onopen = () => {
-      console.log('🟢 [Background] ✅ Connected to desktop app via WebSocket');
-      isConnectedToApp = true;
-      reconnectAttempts = 0;
-      
-      // Send ping to keep connection alive (only create one interval)
-      if (!pingInterval) {
-        pingInterval = setInterval(() => {
-          if (websocket && websocket.readyState === WebSocket.OPEN) {
-            console.log('🟢 [Background] Sending ping to desktop app');
-            websocket.send(JSON.stringify({
-              message_type: 'ping',
-              data: {},
-              timestamp: Date.now()
-            }));
-          }
-        }, 30000); // Every 30 seconds
-      }
+      console.log('🟢 [Background] ✅ Connected to desktop app via WebSocket, sending HELLO');
+      opened = true;
+      probeIndex = 0;
+      onAppChannelOpen();
     };
     
     websocket.onmessage = (event) => {
       try {
-        const message = JSON.parse(event.data);
-        console.log('🟢 [Background] Received from desktop app:', message);
-        
-        // Handle different message types
-        switch (message.message_type) {
-          case 'connected':
-            console.log('🟢 [Background] Desktop app acknowledged connection');
-            break;
-          case 'pong':
-            console.log('🟢 [Background] Received pong from desktop app');
-            break;
-          case 'PLAYBACK_COMMAND':
-            console.log('🟢 [Background] Received PLAYBACK_COMMAND:', message.data);
-            // Forward playback command to content script
-            handlePlaybackCommand(message.data);
-            break;
-          default:
-            console.log('🟡 [Background] Unknown message type:', message.message_type);
-        }
+        handleAppMessage(JSON.parse(event.data), port);
       } catch (error) {
         console.error('❌ [Background] Failed to parse message from desktop app:', error);
       }
@@ -69,24 +240,24 @@ function connectToDesktopApp() {
     
     websocket.onclose = (event) => {
       console.log('🔴 [Background] ❌ Disconnected from desktop app', event.code, event.reason);
-      isConnectedToApp = false;
-      
-      // Clear ping interval
-      if (pingInterval) {
-        clearInterval(pingInterval);
-        pingInterval = null;
-      }
-      
+      onAppChannelClosed();
       websocket = null;
+
+      // Nothing listening on this port: probe the next one right away
+      if (!opened && probeIndex < portCandidates.length - 1) {
+        probeIndex++;
+        connectToDesktopApp();
+        return;
+      }
+      probeIndex = 0;
+
+      // No port answered at all: fall back to native messaging
+      if (!opened && connectNativeHost()) {
+        return;
+      }
       
       // Attempt to reconnect
-      if (reconnectAttempts < maxReconnectAttempts) {
-        reconnectAttempts++;
-        console.log(`🟡 [Background] Attempting to reconnect (${reconnectAttempts}/${maxReconnectAttempts})...`);
-        setTimeout(connectToDesktopApp, reconnectDelay * reconnectAttempts);
-      } else {
-        console.log('🔴 [Background] Max reconnect attempts reached, giving up');
-      }
+      scheduleReconnect();
     };
     
     websocket.onerror = (error) => {
@@ -100,56 +271,112 @@ function connectToDesktopApp() {
   }
 }
 
+// Report the outcome of a command back to the desktop app
+function sendCommandResult(commandId, success, error, state) {
+  if (!commandId) return;
+  sendToApp({
+    message_type: 'COMMAND_RESULT',
+    data: {
+      command_id: commandId,
+      success,
+      error: error || null,
+      state: state || null
+    },
+    timestamp: Date.now()
+  });
+}
+
+// Best known playback state after a command, from the tab's reply or our last track update
+function playbackStateAfter(commandData, response) {
+  if (response && response.state) return response.state;
+  if (!currentTrack) return null;
+
+  let isPlaying = !!currentTrack.isPlaying;
+  let currentTime = currentTrack.currentTime || 0;
+  if (commandData.command === 'play') isPlaying = true;
+  if (commandData.command === 'pause') isPlaying = false;
+  if (commandData.command === 'seek' && typeof commandData.seekTime === 'number') {
+    currentTime = commandData.seekTime;
+  }
+  return { is_playing: isPlaying, currentTime, duration: currentTrack.duration || null };
+}
+
+// Turn commands that depend on the current state into ones the tabs execute directly
+function resolveTabCommand(commandData) {
+  switch (commandData.command) {
+    case 'toggle':
+      return { command: currentTrack && currentTrack.isPlaying ? 'pause' : 'play' };
+    case 'seek_relative': {
+      const position = (currentTrack && currentTrack.currentTime) || 0;
+      return { command: 'seek', seekTime: Math.max(0, position + (commandData.offset || 0)) };
+    }
+    default:
+      return { command: commandData.command, seekTime: commandData.seekTime, level: commandData.level };
+  }
+}
+
 // Handle playback commands from desktop app
 async function handlePlaybackCommand(commandData) {
   console.log('🟢 [Background] Handling playback command:', commandData);
   
   try {
-    // Get all tabs with music sites
-    const tabs = await chrome.tabs.query({
-      url: [
-        '*://open.spotify.com/*',
-        '*://music.youtube.com/*', 
-        '*://music.apple.com/*',
-        '*://soundcloud.com/*'
-      ]
-    });
+    const tabCommand = resolveTabCommand(commandData);
+    // Only the addressed player's tabs, or every music tab for untargeted commands
+    const urlPatterns = commandData.source && SOURCE_URL_PATTERNS[commandData.source]
+      ? SOURCE_URL_PATTERNS[commandData.source]
+      : Object.values(SOURCE_URL_PATTERNS).flat();
+    const tabs = await chrome.tabs.query({ url: urlPatterns });
     
     console.log('🟢 [Background] Found', tabs.length, 'music tabs:', tabs.map(t => t.url));
+    if (tabs.length === 0) {
+      sendCommandResult(commandData.command_id, false, `No ${commandData.source || 'music'} tab is open`);
+      return;
+    }
     
     // Send command to all relevant tabs
-    let anySucceeded = false;
+    let successResponse = null;
+    let lastError = null;
     for (const tab of tabs) {
       try {
-        console.log('🟢 [Background] Sending PLAYBACK_COMMAND to tab', tab.id, ':', {
-          type: 'PLAYBACK_COMMAND',
-          command: commandData.command,
-          seekTime: commandData.seekTime
-        });
+        console.log('🟢 [Background] Sending PLAYBACK_COMMAND to tab', tab.id, ':', tabCommand);
         
         const response = await chrome.tabs.sendMessage(tab.id, {
           type: 'PLAYBACK_COMMAND',
-          command: commandData.command,
-          seekTime: commandData.seekTime
+          ...tabCommand
         });
         
         console.log('🟢 [Background] Response from tab', tab.id, ':', response);
         if (response && response.success) {
-          anySucceeded = true;
+          successResponse = successResponse || response;
+        } else if (response && response.error) {
+          lastError = response.error;
         }
       } catch (error) {
         console.log(`❌ [Background] Could not send command to tab ${tab.id}:`, error);
+        lastError = error.message || String(error);
       }
     }
 
+    if (!successResponse) {
+      sendCommandResult(
+        commandData.command_id,
+        false,
+        lastError || `Command '${tabCommand.command}' could not be executed`
+      );
+      return;
+    }
+
+    sendCommandResult(commandData.command_id, true, null, playbackStateAfter(tabCommand, successResponse));
+
     // Optimistically echo play/pause state to desktop app if a tab handled it
-    if (anySucceeded && currentTrack && (commandData.command === 'play' || commandData.command === 'pause')) {
-      const playing = commandData.command === 'play';
+    if (currentTrack && (tabCommand.command === 'play' || tabCommand.command === 'pause')) {
+      const playing = tabCommand.command === 'play';
       currentTrack = { ...currentTrack, isPlaying: playing };
       sendTrackToApp(currentTrack, playing ? 'TRACK_DETECTED' : 'TRACK_PAUSED');
     }
   } catch (error) {
     console.error('❌ [Background] Error handling playback command:', error);
+    sendCommandResult(commandData.command_id, false, error.message || String(error));
   }
 }
 
@@ -176,12 +403,10 @@ function sendTrackToApp(track, messageType = 'TRACK_DETECTED') {
     timestamp: Date.now()
   };
 
-  if (websocket && websocket.readyState === WebSocket.OPEN) {
-    websocket.send(JSON.stringify(message));
-  } else {
+  if (!isConnectedToApp || !sendToApp(message)) {
     // Store in extension storage as fallback
     chrome.storage.local.set({ currentTrack: track });
-    console.log('WebSocket not connected, stored track locally');
+    console.log('Desktop app not connected, stored track locally');
   }
 }
 
@@ -282,17 +507,43 @@ connectToDesktopApp();
 
 // Periodically check connection and reconnect if needed
 setInterval(() => {
-  if (!isConnectedToApp || !websocket || websocket.readyState !== WebSocket.OPEN) {
+  if (pairingRequired) {
+    console.log('🟡 [Background] Connection check - waiting for pairing code');
+  } else if (!isConnectedToApp || !isAppChannelOpen()) {
     console.log('🟡 [Background] Connection check - not connected, attempting reconnect');
     connectToDesktopApp();
   } else {
-    console.log('🟢 [Background] Connection check - WebSocket is healthy');
+    console.log(`🟢 [Background] Connection check - ${nativePort ? 'native messaging' : 'WebSocket'} is healthy`);
   }
 }, 10000); // Check every 10 seconds
 
 // Export current track for popup
 chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
   if (message.type === 'GET_CURRENT_TRACK') {
-    sendResponse({ track: currentTrack, connected: isConnectedToApp });
+    sendResponse({
+      track: currentTrack,
+      connected: isConnectedToApp,
+      pairingRequired,
+      hasPairingToken: !!pairingToken
+    });
+  } else if (message.type === 'SET_PAIRING_TOKEN') {
+    pairingToken = message.token.trim();
+    chrome.storage.local.set({ pairingToken }).then(() => {
+      pairingRequired = false;
+      reconnectAttempts = 0;
+      if (websocket) {
+        websocket.close();
+      } else if (nativePort) {
+        // disconnect() does not fire onDisconnect for our own side
+        nativePort.disconnect();
+        nativePort = null;
+        onAppChannelClosed();
+        connectToDesktopApp();
+      } else {
+        connectToDesktopApp();
+      }
+      sendResponse({ success: true });
+    });
+    return true;
   }
 });
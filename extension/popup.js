@@ -8,6 +8,17 @@ document.addEventListener('DOMContentLoaded', async () => {
   const trackArtist = document.getElementById('trackArtist');
   const trackSource = document.getElementById('trackSource');
   const openAppBtn = document.getElementById('openAppBtn');
+  const pairing = document.getElementById('pairing');
+  const pairingMessage = document.getElementById('pairingMessage');
+  const pairingToken = document.getElementById('pairingToken');
+  const pairBtn = document.getElementById('pairBtn');
+
+  pairBtn.addEventListener('click', async () => {
+    const token = pairingToken.value.trim();
+    if (!token) return;
+    await chrome.runtime.sendMessage({ type: 'SET_PAIRING_TOKEN', token });
+    pairingMessage.textContent = 'Pairing code saved, connecting...';
+  });
 
   // Get current track from background script
   try {
@@ -18,7 +29,16 @@ document.addEventListener('DOMContentLoaded', async () => {
       statusText.textContent = 'Connected to Lyryc App';
     } else {
       statusDot.classList.add('disconnected');
-      statusText.textContent = 'Desktop app not connected';
+      statusText.textContent = response.pairingRequired
+        ? 'Pairing required'
+        : 'Desktop app not connected';
+    }
+
+    if (response.pairingRequired) {
+      pairing.style.display = 'block';
+      if (response.hasPairingToken) {
+        pairingMessage.textContent = 'The pairing code was rejected. Enter the code shown in the Lyryc app';
+      }
     }
 
     if (response.track) {
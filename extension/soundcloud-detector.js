@@ -237,6 +237,33 @@ chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
         case 'seek':
           if (mediaEl && typeof message.seekTime === 'number') { mediaEl.currentTime = message.seekTime; sendResponse({ success: true }); return true; }
           break;
+        case 'volume':
+          if (mediaEl && typeof message.level === 'number') { mediaEl.volume = Math.max(0, Math.min(1, message.level)); sendResponse({ success: true }); return true; }
+          break;
+        case 'like': {
+          const selectors = [
+            '.playbackSoundBadge__like',
+            '.playControls .sc-button-like'
+          ];
+          for (const sel of selectors) { const btn = document.querySelector(sel); if (btn) { btn.click(); sendResponse({ success: true }); return true; } }
+          break;
+        }
+        case 'shuffle': {
+          const selectors = [
+            '.shuffleControl',
+            '.playControls__shuffle button'
+          ];
+          for (const sel of selectors) { const btn = document.querySelector(sel); if (btn) { btn.click(); sendResponse({ success: true }); return true; } }
+          break;
+        }
+        case 'repeat': {
+          const selectors = [
+            '.repeatControl',
+            '.playControls__repeat button'
+          ];
+          for (const sel of selectors) { const btn = document.querySelector(sel); if (btn) { btn.click(); sendResponse({ success: true }); return true; } }
+          break;
+        }
       }
     } catch (e) { console.log('SoundCloud PLAYBACK_COMMAND failed', e); }
     sendResponse({ success: false });
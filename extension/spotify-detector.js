@@ -259,33 +259,57 @@ class SpotifyDetector {
     return 0;
   }
 
-  // Handle playback commands from desktop app
-  handlePlaybackCommand(command, seekTime) {
+  // Handle playback commands from desktop app; returns whether the command was carried out
+  handlePlaybackCommand(command, seekTime, level) {
     try {
       switch (command) {
         case 'play':
-          this.clickPlayButton(false); // false = play
-          break;
+          return this.clickPlayButton(false); // false = play
         case 'pause':
-          this.clickPlayButton(true); // true = pause
-          break;
+          return this.clickPlayButton(true); // true = pause
         case 'next':
-          this.clickSkip(true);
-          break;
+          return this.clickSkip(true);
         case 'previous':
-          this.clickSkip(false);
-          break;
+          return this.clickSkip(false);
         case 'seek':
           this.seekToTime(seekTime);
-          break;
+          return true;
+        case 'volume':
+          return this.setVolume(level);
+        case 'like':
+          return this.clickControl([
+            '[data-testid="now-playing-widget"] [data-testid="add-button"]',
+            '.now-playing [aria-label*="Library" i]'
+          ]);
+        case 'shuffle':
+          return this.clickControl(['[data-testid="control-button-shuffle"]']);
+        case 'repeat':
+          return this.clickControl(['[data-testid="control-button-repeat"]']);
         default:
           console.log('Unknown playback command:', command);
+          return false;
       }
     } catch (error) {
       console.error('Error handling playback command:', error);
+      return false;
     }
   }
 
+  clickControl(selectors) {
+    for (const sel of selectors) {
+      const btn = document.querySelector(sel);
+      if (btn) { btn.click(); console.log('✅ Clicked', sel); return true; }
+    }
+    return false;
+  }
+
+  setVolume(level) {
+    const mediaEl = document.querySelector('audio, video');
+    if (!mediaEl || typeof level !== 'number') return false;
+    mediaEl.volume = Math.max(0, Math.min(1, level));
+    return true;
+  }
+
   clickPlayButton(shouldPause) {
     const playButtons = [
       '[data-testid="control-button-playpause"]',
@@ -302,8 +326,8 @@ class SpotifyDetector {
         if ((shouldPause && currentlyPlaying) || (!shouldPause && !currentlyPlaying)) {
           playButton.click();
           console.log(`${shouldPause ? 'Paused' : 'Played'} via button click`);
-          return true;
         }
+        return true; // already in the requested state otherwise
       }
     }
     return false;
@@ -410,8 +434,10 @@ if (document.readyState === 'loading') {
 // Listen for playback commands from background script
 chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
   if (message.type === 'PLAYBACK_COMMAND' && spotifyDetector) {
-    spotifyDetector.handlePlaybackCommand(message.command, message.seekTime);
-    sendResponse({ success: true });
+    const success = spotifyDetector.handlePlaybackCommand(message.command, message.seekTime, message.level);
+    sendResponse(success
+      ? { success: true }
+      : { success: false, error: `Command '${message.command}' could not be executed` });
   }
   return true;
 });
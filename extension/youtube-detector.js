@@ -674,6 +674,35 @@ chrome.runtime.onMessage.addListener(async (message, sender, sendResponse) => {
           }
         }
         break;
+
+      case 'volume': {
+        const video = document.querySelector('video');
+        if (video && typeof message.level === 'number') {
+          video.volume = Math.max(0, Math.min(1, message.level));
+          console.log('✅ Set volume to', video.volume);
+          sendResponse({ success: true });
+          return true;
+        }
+        break;
+      }
+
+      case 'like':
+      case 'shuffle':
+      case 'repeat': {
+        const toggleSelectors = {
+          like: [
+            'ytmusic-player-bar ytmusic-like-button-renderer #button-shape-like button',
+            'ytmusic-player-bar .like'
+          ],
+          shuffle: ['ytmusic-player-bar .shuffle', 'ytmusic-player-bar [aria-label*="huffle" i]'],
+          repeat: ['ytmusic-player-bar .repeat', 'ytmusic-player-bar [aria-label*="epeat" i]']
+        }[message.command];
+        for (const sel of toggleSelectors) {
+          const btn = document.querySelector(sel);
+          if (btn) { btn.click(); console.log(`✅ Clicked ${message.command} via`, sel); sendResponse({ success: true }); return true; }
+        }
+        break;
+      }
     }
 
     console.log('❌ [Content] All playback command attempts failed for:', message.command);
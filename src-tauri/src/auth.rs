@@ -0,0 +1,325 @@
+use crate::types::{AuthState, WebSocketState};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::{Manager, State};
+use uuid::Uuid;
+
+/// Firefox puts a random per-install UUID in extension origins, not the extension ID
+const FIREFOX_EXTENSION_SCHEME: &str = "moz-extension://";
+
+/// Origins browsers use for extension pages and service workers
+const EXTENSION_SCHEMES: &[&str] = &["chrome-extension://", "safari-web-extension://"];
+
+/// Credentials the local WebSocket server and HTTP API check before accepting a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    pub pairing_token: String,
+    /// Bearer token for the HTTP API, kept apart from pairing so re-pairing an
+    /// extension doesn't lock out scripts
+    #[serde(default)]
+    pub api_token: String,
+    /// Read-only token for the streaming overlay URL, which ends up in OBS scenes and
+    /// screenshots; it can only read the current line and the event stream
+    #[serde(default)]
+    pub overlay_token: String,
+    /// Extension IDs allowed to connect; empty allows any extension origin. Firefox
+    /// origins cannot be matched against these and rely on the pairing token alone
+    #[serde(default)]
+    pub allowed_extension_ids: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn generate() -> Self {
+        Self {
+            pairing_token: generate_pairing_token(),
+            api_token: generate_api_token(),
+            overlay_token: generate_api_token(),
+            allowed_extension_ids: Vec::new(),
+        }
+    }
+
+    /// Load the stored credentials, creating a fresh pairing token on first run
+    pub fn load_or_create(path: &Path) -> Self {
+        if let Ok(contents) = std::fs::read_to_string(path) {
+            match serde_json::from_str::<AuthConfig>(&contents) {
+                Ok(mut config) if !config.pairing_token.trim().is_empty() => {
+                    // Files written before the HTTP API and overlay had their own tokens
+                    if config.fill_missing_tokens() {
+                        if let Err(e) = config.save(path) {
+                            warn!("Failed to persist HTTP API tokens: {}", e);
+                        }
+                        info!("Generated new HTTP API tokens");
+                    }
+                    return config;
+                }
+                Ok(_) => warn!("Stored pairing token is empty, generating a new one"),
+                Err(e) => warn!("Failed to parse {}: {}", path.display(), e),
+            }
+        }
+
+        let config = Self::generate();
+        if let Err(e) = config.save(path) {
+            warn!("Failed to persist pairing token: {}", e);
+        }
+        info!("Generated new pairing token");
+        config
+    }
+
+    /// Returns whether any token was generated
+    fn fill_missing_tokens(&mut self) -> bool {
+        let mut filled = false;
+        for token in [&mut self.api_token, &mut self.overlay_token] {
+            if token.trim().is_empty() {
+                *token = generate_api_token();
+                filled = true;
+            }
+        }
+        filled
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Reject web pages and unknown extensions; clients without an Origin header
+    /// (native processes) are let through and must still present the pairing token.
+    /// So are Firefox extensions: their origin carries a per-install UUID that never
+    /// equals the gecko ID in `allowed_extension_ids`.
+    pub fn check_origin(&self, origin: Option<&str>) -> Result<(), String> {
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return Ok(()),
+        };
+        if origin.starts_with(FIREFOX_EXTENSION_SCHEME) {
+            return Ok(());
+        }
+
+        let extension_id = EXTENSION_SCHEMES
+            .iter()
+            .find_map(|scheme| origin.strip_prefix(scheme))
+            .map(|rest| rest.trim_end_matches('/'))
+            .ok_or_else(|| format!("Origin '{}' is not a browser extension", origin))?;
+
+        if self.allowed_extension_ids.is_empty()
+            || self
+                .allowed_extension_ids
+                .iter()
+                .any(|allowed| allowed == extension_id)
+        {
+            Ok(())
+        } else {
+            Err(format!("Extension '{}' is not allowed", extension_id))
+        }
+    }
+
+    pub fn verify_token(&self, token: Option<&str>) -> bool {
+        tokens_match(token, &self.pairing_token)
+    }
+
+    pub fn verify_api_token(&self, token: Option<&str>) -> bool {
+        tokens_match(token, &self.api_token)
+    }
+
+    pub fn verify_overlay_token(&self, token: Option<&str>) -> bool {
+        tokens_match(token, &self.overlay_token)
+    }
+}
+
+fn tokens_match(given: Option<&str>, expected: &str) -> bool {
+    match given {
+        Some(given) => normalize_token(given) == normalize_token(expected),
+        None => false,
+    }
+}
+
+/// Tokens are shown grouped for readability; accept them with or without dashes and in any case
+fn normalize_token(token: &str) -> String {
+    token
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn generate_pairing_token() -> String {
+    let raw = Uuid::new_v4().simple().to_string().to_uppercase();
+    format!("{}-{}-{}", &raw[0..4], &raw[4..8], &raw[8..12])
+}
+
+/// Scripts paste this once and keep it, so it is longer than the pairing token
+fn generate_api_token() -> String {
+    Uuid::new_v4().simple().to_string().to_uppercase()
+}
+
+pub fn auth_file_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app_handle
+        .path()
+        .app_config_dir()
+        .map(|dir| dir.join("auth.json"))
+        .map_err(|e| format!("Failed to resolve config directory: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_pairing_token(auth_state: State<'_, AuthState>) -> Result<String, String> {
+    let auth = auth_state.read().map_err(|e| e.to_string())?;
+    Ok(auth.pairing_token.clone())
+}
+
+#[tauri::command]
+pub async fn regenerate_pairing_token(
+    auth_state: State<'_, AuthState>,
+    ws_state: State<'_, WebSocketState>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    let token = {
+        let mut auth = auth_state.write().map_err(|e| e.to_string())?;
+        auth.pairing_token = generate_pairing_token();
+        auth.save(&auth_file_path(&app_handle)?)?;
+        auth.pairing_token.clone()
+    };
+    info!("Pairing token regenerated, disconnecting paired clients");
+
+    // Clients paired with the old token must pair again
+    let server_guard = ws_state.lock().await;
+    if let Some(ref server) = *server_guard {
+        server.disconnect_all("pairing token changed").await;
+    }
+
+    Ok(token)
+}
+
+#[tauri::command]
+pub async fn get_api_token(auth_state: State<'_, AuthState>) -> Result<String, String> {
+    let auth = auth_state.read().map_err(|e| e.to_string())?;
+    Ok(auth.api_token.clone())
+}
+
+/// Revokes the HTTP API token; extension pairing is left alone
+#[tauri::command]
+pub async fn regenerate_api_token(
+    auth_state: State<'_, AuthState>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    let mut auth = auth_state.write().map_err(|e| e.to_string())?;
+    auth.api_token = generate_api_token();
+    auth.save(&auth_file_path(&app_handle)?)?;
+    info!("HTTP API token regenerated");
+    Ok(auth.api_token.clone())
+}
+
+/// Revokes the overlay URL; browser sources need the new one from `get_overlay_url`
+#[tauri::command]
+pub async fn regenerate_overlay_token(
+    auth_state: State<'_, AuthState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let mut auth = auth_state.write().map_err(|e| e.to_string())?;
+    auth.overlay_token = generate_api_token();
+    auth.save(&auth_file_path(&app_handle)?)?;
+    info!("Overlay token regenerated");
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_allowed_extension_ids(
+    extension_ids: Vec<String>,
+    auth_state: State<'_, AuthState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let mut auth = auth_state.write().map_err(|e| e.to_string())?;
+    auth.allowed_extension_ids = extension_ids
+        .into_iter()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect();
+    info!("Allowed extension IDs: {:?}", auth.allowed_extension_ids);
+    auth.save(&auth_file_path(&app_handle)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(allowed: &[&str]) -> AuthConfig {
+        AuthConfig {
+            pairing_token: "ABCD-1234-EF56".to_string(),
+            api_token: "0123456789ABCDEF0123456789ABCDEF".to_string(),
+            overlay_token: "FEDCBA9876543210FEDCBA9876543210".to_string(),
+            allowed_extension_ids: allowed.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn rejects_web_page_origins() {
+        let auth = config(&[]);
+        assert!(auth.check_origin(Some("https://evil.example")).is_err());
+        assert!(auth.check_origin(Some("null")).is_err());
+        assert!(auth.check_origin(Some("chrome-extension://anyid")).is_ok());
+        assert!(auth.check_origin(None).is_ok());
+    }
+
+    #[test]
+    fn restricts_to_allowed_extension_ids() {
+        let auth = config(&["goodid"]);
+        assert!(auth
+            .check_origin(Some("chrome-extension://goodid/"))
+            .is_ok());
+        assert!(auth
+            .check_origin(Some("chrome-extension://otherid"))
+            .is_err());
+    }
+
+    #[test]
+    fn firefox_origins_skip_the_id_check() {
+        let auth = config(&["lyryc@example.com"]);
+        assert!(auth
+            .check_origin(Some("moz-extension://7c9e6679-7425-40de-944b-e07fc1f90ae7"))
+            .is_ok());
+        assert!(auth
+            .check_origin(Some("https://moz-extension.example"))
+            .is_err());
+    }
+
+    #[test]
+    fn token_comparison_ignores_grouping_and_case() {
+        let auth = config(&[]);
+        assert!(auth.verify_token(Some("abcd1234ef56")));
+        assert!(auth.verify_token(Some(" ABCD-1234-EF56 ")));
+        assert!(!auth.verify_token(Some("ABCD-1234-EF57")));
+        assert!(!auth.verify_token(None));
+    }
+
+    #[test]
+    fn api_and_pairing_tokens_are_not_interchangeable() {
+        let auth = config(&[]);
+        assert!(auth.verify_api_token(Some("0123456789abcdef0123456789abcdef")));
+        assert!(!auth.verify_api_token(Some("ABCD-1234-EF56")));
+        assert!(!auth.verify_token(Some("0123456789ABCDEF0123456789ABCDEF")));
+    }
+
+    #[test]
+    fn older_auth_files_get_api_and_overlay_tokens() {
+        let path = std::env::temp_dir().join(format!("lyryc-auth-{}.json", Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{"pairing_token":"ABCD-1234-EF56","allowed_extension_ids":[]}"#,
+        )
+        .unwrap();
+        let auth = AuthConfig::load_or_create(&path);
+        assert_eq!(auth.pairing_token, "ABCD-1234-EF56");
+        assert_eq!(auth.api_token.len(), 32);
+        assert_eq!(auth.overlay_token.len(), 32);
+        assert_ne!(auth.api_token, auth.overlay_token);
+        let reloaded = AuthConfig::load_or_create(&path);
+        assert_eq!(reloaded.api_token, auth.api_token);
+        assert_eq!(reloaded.overlay_token, auth.overlay_token);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! Hides the lyrics bar when nothing has been playing for a while.
+//!
+//! Playback counts as idle while the active source is paused or no source is connected.
+//! After `window.autoHideSecs` of it the bar is hidden, or faded by the frontend, and it
+//! comes back as soon as something plays again. Showing or hiding the bar by hand turns
+//! the policy off until the next track starts.
+
+use crate::settings::{self, AutoHideMode};
+use crate::types::{AutoHideState, SourceState};
+use log::{info, warn};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoHideAction {
+    Hide,
+    Restore,
+}
+
+/// Emitted as `auto-hide-changed` whenever the policy hides or restores the bar
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoHideChange {
+    pub hidden: bool,
+    pub mode: AutoHideMode,
+}
+
+#[derive(Debug)]
+pub struct AutoHidePolicy {
+    /// When playback last stopped; `None` while something plays
+    idle_since: Option<Instant>,
+    /// The bar is hidden by this policy, not by hand
+    hidden: bool,
+    /// Shown or hidden by hand since the last track started
+    overridden: bool,
+}
+
+impl AutoHidePolicy {
+    /// Nothing plays until a source connects
+    pub fn new(now: Instant) -> Self {
+        Self {
+            idle_since: Some(now),
+            hidden: false,
+            overridden: false,
+        }
+    }
+
+    pub fn observe(&mut self, playing: bool, sources: usize, now: Instant) {
+        if playing && sources > 0 {
+            self.idle_since = None;
+        } else if self.idle_since.is_none() {
+            self.idle_since = Some(now);
+        }
+    }
+
+    /// Returns whether the bar was hidden by this policy
+    pub fn manual_toggle(&mut self) -> bool {
+        self.overridden = true;
+        std::mem::take(&mut self.hidden)
+    }
+
+    pub fn track_started(&mut self) {
+        self.overridden = false;
+    }
+
+    /// What to do with the bar now; `timeout` is `None` while auto-hide is off
+    pub fn decide(&mut self, now: Instant, timeout: Option<Duration>) -> Option<AutoHideAction> {
+        if self.overridden {
+            return None;
+        }
+        let idle_for = self.idle_since.map(|since| now.duration_since(since));
+        let should_hide =
+            matches!((idle_for, timeout), (Some(idle), Some(timeout)) if idle >= timeout);
+        if should_hide == self.hidden {
+            return None;
+        }
+        self.hidden = should_hide;
+        Some(if should_hide {
+            AutoHideAction::Hide
+        } else {
+            AutoHideAction::Restore
+        })
+    }
+}
+
+/// The bar was shown or hidden by hand; leave it that way until the next track
+pub async fn manual_toggle(app_handle: &tauri::AppHandle) {
+    let was_hidden = app_handle
+        .state::<AutoHideState>()
+        .lock()
+        .await
+        .manual_toggle();
+    if was_hidden {
+        // A faded bar comes back before the toggle applies
+        let mode = settings::current(app_handle).await.window.auto_hide_mode;
+        let change = AutoHideChange {
+            hidden: false,
+            mode,
+        };
+        if let Err(e) = app_handle.emit("auto-hide-changed", change) {
+            warn!("Failed to emit auto-hide-changed event: {}", e);
+        }
+    }
+}
+
+pub async fn track_started(app_handle: &tauri::AppHandle) {
+    app_handle
+        .state::<AutoHideState>()
+        .lock()
+        .await
+        .track_started();
+}
+
+/// Check the playback state every second and hide or restore the bar
+pub async fn run(app_handle: tauri::AppHandle) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let (playing, sources) = {
+            let snapshot = app_handle.state::<SourceState>().lock().await.snapshot();
+            let playing = snapshot
+                .sources
+                .iter()
+                .any(|source| source.active && source.is_playing);
+            (playing, snapshot.sources.len())
+        };
+        let window_settings = settings::current(&app_handle).await.window;
+        let timeout = window_settings.auto_hide_secs.map(Duration::from_secs);
+
+        let action = {
+            let state = app_handle.state::<AutoHideState>();
+            let mut policy = state.lock().await;
+            let now = Instant::now();
+            policy.observe(playing, sources, now);
+            policy.decide(now, timeout)
+        };
+        if let Some(action) = action {
+            if let Err(e) = apply(&app_handle, action, window_settings.auto_hide_mode) {
+                warn!("Failed to auto-hide the window: {}", e);
+            }
+        }
+    }
+}
+
+fn apply(
+    app_handle: &tauri::AppHandle,
+    action: AutoHideAction,
+    mode: AutoHideMode,
+) -> Result<(), String> {
+    let window = app_handle
+        .get_webview_window("main")
+        .ok_or_else(|| "Main window not found".to_string())?;
+    let hidden = action == AutoHideAction::Hide;
+    info!("Auto-hide: {:?} the window ({:?})", action, mode);
+    if hidden && mode == AutoHideMode::Hide {
+        window
+            .hide()
+            .map_err(|e| format!("Failed to hide window: {}", e))?;
+    } else if !hidden {
+        // `show` activates the window on Windows and most X11 window managers, so a
+        // hidden bar takes focus from the player when playback resumes. Fade mode
+        // never hides the window and avoids that.
+        window
+            .show()
+            .map_err(|e| format!("Failed to show window: {}", e))?;
+    }
+    app_handle
+        .emit("auto-hide-changed", AutoHideChange { hidden, mode })
+        .map_err(|e| format!("Failed to emit auto-hide-changed event: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(60));
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn hides_after_idle_timeout_and_restores_on_playback() {
+        let start = Instant::now();
+        let mut policy = AutoHidePolicy::new(start);
+        policy.observe(true, 1, start);
+        assert_eq!(policy.decide(secs(start, 120), TIMEOUT), None);
+
+        policy.observe(false, 1, secs(start, 120));
+        assert_eq!(policy.decide(secs(start, 150), TIMEOUT), None);
+        assert_eq!(
+            policy.decide(secs(start, 180), TIMEOUT),
+            Some(AutoHideAction::Hide)
+        );
+        assert_eq!(policy.decide(secs(start, 190), TIMEOUT), None);
+
+        policy.observe(true, 1, secs(start, 200));
+        assert_eq!(
+            policy.decide(secs(start, 200), TIMEOUT),
+            Some(AutoHideAction::Restore)
+        );
+    }
+
+    #[test]
+    fn playing_without_sources_counts_as_idle() {
+        let start = Instant::now();
+        let mut policy = AutoHidePolicy::new(start);
+        policy.observe(true, 0, start);
+        assert_eq!(
+            policy.decide(secs(start, 60), TIMEOUT),
+            Some(AutoHideAction::Hide)
+        );
+    }
+
+    #[test]
+    fn manual_toggle_overrides_until_the_next_track() {
+        let start = Instant::now();
+        let mut policy = AutoHidePolicy::new(start);
+        assert_eq!(
+            policy.decide(secs(start, 60), TIMEOUT),
+            Some(AutoHideAction::Hide)
+        );
+
+        policy.manual_toggle();
+        assert_eq!(policy.decide(secs(start, 600), TIMEOUT), None);
+
+        // Still paused when the next track starts, so it hides again
+        policy.track_started();
+        assert_eq!(
+            policy.decide(secs(start, 601), TIMEOUT),
+            Some(AutoHideAction::Hide)
+        );
+    }
+
+    #[test]
+    fn turning_it_off_restores_the_window() {
+        let start = Instant::now();
+        let mut policy = AutoHidePolicy::new(start);
+        assert_eq!(
+            policy.decide(secs(start, 60), TIMEOUT),
+            Some(AutoHideAction::Hide)
+        );
+        assert_eq!(
+            policy.decide(secs(start, 61), None),
+            Some(AutoHideAction::Restore)
+        );
+    }
+}
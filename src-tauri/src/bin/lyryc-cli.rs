@@ -0,0 +1,536 @@
+//! Command-line companion for the running Lyryc app.
+//!
+//! Talks to the opt-in HTTP API (see `http_api.rs`), located through the discovery file.
+//! `--follow` prints one line per change, for Waybar, Polybar or tmux status modules.
+
+use lyryc_lib::discovery;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: lyryc-cli [options] <command>
+
+Commands:
+  line                    Current lyric line
+  track                   Current track
+  state                   Playback state and position
+  play | pause | toggle | next | previous | like | shuffle | repeat
+  seek <seconds>          Jump to a position
+  seek-relative <seconds> Skip forward (or back, when negative)
+  volume <0.0-1.0>        Set the player volume
+
+Options:
+  -f, --follow            Keep running and print a line on every change (line, track, state)
+  --format <format>       text (default), json, or waybar
+  --source <source>       Player to control, e.g. spotify
+  --client <id>           Client to control
+  --port <port>           HTTP API port (default: from the discovery file)
+  --token <token>         API token (default: $LYRYC_TOKEN, then the app's auth.json)";
+
+/// How long follow mode waits before reconnecting to an app that went away
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Query {
+    Line,
+    Track,
+    State,
+}
+
+impl Query {
+    fn path(self) -> &'static str {
+        match self {
+            Query::Line => "/lyrics/current-line",
+            Query::Track | Query::State => "/now-playing",
+        }
+    }
+
+    /// Server-sent events after which the output may have changed
+    fn wants(self, event: &str) -> bool {
+        match self {
+            Query::Line => matches!(event, "lyric-line-changed" | "track-updated"),
+            Query::Track => matches!(event, "track-updated" | "playback-state"),
+            Query::State => matches!(
+                event,
+                "track-updated" | "playback-state" | "track-time-update"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Waybar,
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Show {
+        query: Query,
+        follow: bool,
+    },
+    /// JSON body for `POST /playback`
+    Playback(Value),
+}
+
+#[derive(Debug)]
+struct Options {
+    action: Action,
+    format: Format,
+    port: Option<u16>,
+    token: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut follow = false;
+    let mut format = Format::Text;
+    let mut port = None;
+    let mut token = None;
+    let mut source = None;
+    let mut client = None;
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match arg.as_str() {
+            "-f" | "--follow" => follow = true,
+            "--format" => {
+                format = match value("--format")?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "waybar" => Format::Waybar,
+                    other => return Err(format!("Unknown format '{}'", other)),
+                }
+            }
+            "--port" => {
+                let raw = value("--port")?;
+                port = Some(raw.parse().map_err(|_| format!("Invalid port '{}'", raw))?);
+            }
+            "--token" => token = Some(value("--token")?),
+            "--source" => source = Some(value("--source")?),
+            "--client" => client = Some(value("--client")?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let (command, rest) = positional.split_first().ok_or_else(|| USAGE.to_string())?;
+    let number = |name: &str| -> Result<f64, String> {
+        let raw = rest
+            .first()
+            .ok_or_else(|| format!("{} needs a value", name))?;
+        raw.parse()
+            .map_err(|_| format!("Invalid {} value '{}'", name, raw))
+    };
+
+    let show = |query| Action::Show { query, follow };
+    let mut body = match command.as_str() {
+        "line" | "track" | "state" => {
+            let action = match command.as_str() {
+                "line" => show(Query::Line),
+                "track" => show(Query::Track),
+                _ => show(Query::State),
+            };
+            return Ok(Options {
+                action,
+                format,
+                port,
+                token,
+            });
+        }
+        "play" | "pause" | "toggle" | "next" | "previous" | "like" | "shuffle" | "repeat" => {
+            json!({ "command": command })
+        }
+        "seek" => json!({ "command": "seek", "seekTime": number("seek")? }),
+        "seek-relative" | "seek_relative" => {
+            json!({ "command": "seek_relative", "offset": number("seek-relative")? })
+        }
+        "volume" => json!({ "command": "volume", "level": number("volume")? }),
+        other => return Err(format!("Unknown command '{}'\n\n{}", other, USAGE)),
+    };
+
+    if let Some(source) = source {
+        body["source"] = json!(source);
+    }
+    if let Some(client) = client {
+        body["clientId"] = json!(client);
+    }
+    Ok(Options {
+        action: Action::Playback(body),
+        format,
+        port,
+        token,
+    })
+}
+
+/// `<config dir>/com.lyryc.app/auth.json`, where the app keeps its API token
+fn auth_file_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let config_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home.map(|home| home.join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".config")))
+    };
+    config_dir.map(|dir| dir.join("com.lyryc.app").join("auth.json"))
+}
+
+fn resolve_token(explicit: Option<&str>) -> Result<String, String> {
+    if let Some(token) = explicit {
+        return Ok(token.to_string());
+    }
+    if let Ok(token) = std::env::var("LYRYC_TOKEN") {
+        return Ok(token);
+    }
+    let path = auth_file_path().ok_or("Could not locate the app's config directory")?;
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let auth: Value = serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+    auth["api_token"]
+        .as_str()
+        .map(|token| token.to_string())
+        .ok_or_else(|| format!("No API token in {}", path.display()))
+}
+
+struct Api {
+    base: String,
+    token: String,
+    http: reqwest::Client,
+}
+
+impl Api {
+    /// Find the running app's HTTP API; re-read on every reconnect since the port can change
+    fn locate(options: &Options) -> Result<Self, String> {
+        let port = options
+            .port
+            .or_else(|| discovery::read_discovery().and_then(|d| d.http_port))
+            .ok_or("Lyryc's HTTP API is not running (start the app with LYRYC_HTTP_PORT set)")?;
+        Ok(Self {
+            base: format!("http://127.0.0.1:{}", port),
+            token: resolve_token(options.token.as_deref())?,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+        let response = request
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach Lyryc: {}", e))?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        Err(match body["error"].as_str() {
+            Some(error) => error.to_string(),
+            None => format!("Lyryc answered {}", status),
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Value, String> {
+        let response = self
+            .send(self.http.get(format!("{}{}", self.base, path)))
+            .await?;
+        response.json().await.map_err(|e| e.to_string())
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<Value, String> {
+        let request = self.http.post(format!("{}{}", self.base, path)).json(body);
+        let response = self.send(request).await?;
+        response.json().await.map_err(|e| e.to_string())
+    }
+}
+
+/// One rendering of a query, in the shape Waybar's `return-type: json` expects
+#[derive(Debug, Clone, PartialEq)]
+struct Output {
+    text: String,
+    tooltip: String,
+    class: &'static str,
+    raw: Value,
+}
+
+impl Output {
+    /// What a status bar shows while the app is not reachable
+    fn offline() -> Self {
+        Self {
+            text: String::new(),
+            tooltip: "Lyryc is not running".to_string(),
+            class: "offline",
+            raw: Value::Null,
+        }
+    }
+
+    fn render(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.text.clone(),
+            Format::Json => self.raw.to_string(),
+            Format::Waybar => json!({
+                "text": self.text,
+                "tooltip": self.tooltip,
+                "class": self.class,
+            })
+            .to_string(),
+        }
+    }
+}
+
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn playback_class(now_playing: &Value) -> &'static str {
+    if now_playing["track"].is_null() {
+        "stopped"
+    } else if now_playing["isPlaying"].as_bool().unwrap_or(false) {
+        "playing"
+    } else {
+        "paused"
+    }
+}
+
+fn describe(query: Query, raw: Value) -> Output {
+    match query {
+        Query::Line => Output {
+            text: raw["line"]["text"].as_str().unwrap_or_default().to_string(),
+            tooltip: raw["nextLine"]["text"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            class: if raw["line"].is_object() {
+                "line"
+            } else {
+                "empty"
+            },
+            raw,
+        },
+        Query::Track => {
+            let track = &raw["track"];
+            let text = match (track["artist"].as_str(), track["title"].as_str()) {
+                (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+                _ => String::new(),
+            };
+            Output {
+                text,
+                tooltip: raw["source"]["source"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                class: playback_class(&raw),
+                raw,
+            }
+        }
+        Query::State => {
+            let class = playback_class(&raw);
+            let text = match (
+                raw["currentTime"].as_f64(),
+                raw["track"]["duration"].as_f64(),
+            ) {
+                _ if class == "stopped" => class.to_string(),
+                (Some(current), Some(duration)) => format!(
+                    "{} {}/{}",
+                    class,
+                    format_time(current),
+                    format_time(duration)
+                ),
+                (Some(current), None) => format!("{} {}", class, format_time(current)),
+                (None, _) => class.to_string(),
+            };
+            Output {
+                text,
+                tooltip: String::new(),
+                class,
+                raw,
+            }
+        }
+    }
+}
+
+/// Splits a Server-Sent Events byte stream into event names; payloads are not needed
+/// since every change is answered by re-reading the query
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let block = String::from_utf8_lossy(&block);
+            let name = block
+                .lines()
+                .find_map(|line| line.strip_prefix("event:"))
+                .map(|name| name.trim().to_string());
+            // Keep-alive comments carry no event name
+            if let Some(name) = name {
+                events.push(name);
+            }
+        }
+        events
+    }
+}
+
+async fn show(options: &Options, query: Query) -> Result<(), String> {
+    let api = Api::locate(options)?;
+    let output = describe(query, api.get(query.path()).await?);
+    println!("{}", output.render(options.format));
+    Ok(())
+}
+
+/// Print the query whenever it changes, reconnecting until the process is killed
+async fn follow(options: &Options, query: Query) {
+    let mut last: Option<String> = None;
+    let mut print = |output: Output| {
+        let rendered = output.render(options.format);
+        if last.as_deref() != Some(rendered.as_str()) {
+            println!("{}", rendered);
+            last = Some(rendered);
+        }
+    };
+
+    loop {
+        match follow_once(options, query, &mut print).await {
+            Ok(()) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+        print(Output::offline());
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+async fn follow_once<F>(options: &Options, query: Query, print: &mut F) -> Result<(), String>
+where
+    F: FnMut(Output),
+{
+    let api = Api::locate(options)?;
+    let mut events = api
+        .send(api.http.get(format!("{}/events", api.base)))
+        .await?;
+    print(describe(query, api.get(query.path()).await?));
+
+    let mut parser = SseParser::default();
+    while let Some(chunk) = events.chunk().await.map_err(|e| e.to_string())? {
+        if parser.push(&chunk).iter().any(|event| query.wants(event)) {
+            print(describe(query, api.get(query.path()).await?));
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    let result = match &options.action {
+        Action::Show {
+            query,
+            follow: true,
+        } => {
+            follow(&options, *query).await;
+            Ok(())
+        }
+        Action::Show { query, .. } => show(&options, *query).await,
+        Action::Playback(body) => match Api::locate(&options) {
+            Ok(api) => api.post("/playback", body).await.map(|outcome| {
+                if options.format == Format::Text {
+                    let acknowledged = outcome["acknowledged"].as_bool().unwrap_or(false);
+                    println!("{}", if acknowledged { "ok" } else { "sent" });
+                } else {
+                    println!("{}", outcome);
+                }
+            }),
+            Err(e) => Err(e),
+        },
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_queries_and_commands() {
+        let options = parse_args(&args(&["line", "--follow", "--format", "waybar"])).unwrap();
+        assert_eq!(
+            options.action,
+            Action::Show {
+                query: Query::Line,
+                follow: true
+            }
+        );
+        assert_eq!(options.format, Format::Waybar);
+
+        let options = parse_args(&args(&["seek", "42", "--source", "spotify"])).unwrap();
+        assert_eq!(
+            options.action,
+            Action::Playback(json!({ "command": "seek", "seekTime": 42.0, "source": "spotify" }))
+        );
+
+        assert!(parse_args(&args(&["volume"])).is_err());
+        assert!(parse_args(&args(&["rewind"])).is_err());
+    }
+
+    #[test]
+    fn describes_playback_state() {
+        let now_playing = json!({
+            "track": { "title": "Song", "artist": "Band", "duration": 225.0 },
+            "isPlaying": true,
+            "currentTime": 83.4,
+            "source": { "client_id": "c1", "source": "spotify" }
+        });
+        assert_eq!(
+            describe(Query::State, now_playing.clone()).text,
+            "playing 1:23/3:45"
+        );
+        let track = describe(Query::Track, now_playing);
+        assert_eq!(track.text, "Band - Song");
+        assert_eq!(track.tooltip, "spotify");
+
+        let idle = describe(Query::State, json!({ "track": null, "isPlaying": false }));
+        assert_eq!(idle.text, "stopped");
+    }
+
+    #[test]
+    fn splits_event_stream_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"event: lyric-line").is_empty());
+        assert_eq!(
+            parser.push(b"-changed\ndata: {}\n\n:\n\nevent: track-updated\ndata: {}\n\n"),
+            vec!["lyric-line-changed", "track-updated"]
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Bounded outbound queue for one WebSocket client.
+//!
+//! A stalled extension must not make the app buffer frames forever. Each client gets
+//! a queue of at most `capacity` frames: frames sent with `send_latest` replace an
+//! older queued frame with the same key (the newest state snapshot wins), and when
+//! the queue is full the oldest such snapshot is evicted before new frames are
+//! refused. Close frames are always accepted so a connection can still be ended.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Frames a client may have waiting before new ones are refused
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// What happened to a frame handed to the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Queued,
+    /// Replaced an older queued frame with the same key
+    Coalesced,
+    /// The queue was full of frames that cannot be dropped
+    Dropped,
+}
+
+/// The connection's writer is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueClosed;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendStats {
+    /// Frames handed to the socket writer
+    pub sent: u64,
+    pub coalesced: u64,
+    /// Frames refused or evicted because the client was not keeping up
+    pub dropped: u64,
+    /// Frames currently waiting
+    pub queued: usize,
+    /// Most frames ever waiting at once
+    pub high_water: usize,
+}
+
+struct QueuedFrame {
+    key: Option<&'static str>,
+    message: Message,
+}
+
+struct QueueState {
+    frames: VecDeque<QueuedFrame>,
+    stats: SendStats,
+    senders: usize,
+    receiver_gone: bool,
+}
+
+struct Shared {
+    capacity: usize,
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+pub fn client_queue(capacity: usize) -> (QueueSender, QueueReceiver) {
+    let shared = Arc::new(Shared {
+        capacity: capacity.max(1),
+        state: Mutex::new(QueueState {
+            frames: VecDeque::new(),
+            stats: SendStats::default(),
+            senders: 1,
+            receiver_gone: false,
+        }),
+        notify: Notify::new(),
+    });
+    (
+        QueueSender {
+            shared: Arc::clone(&shared),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+pub struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    /// Queue a frame, dropping it if the client is too far behind
+    pub fn send(&self, message: Message) -> Result<Delivery, QueueClosed> {
+        self.push(None, message)
+    }
+
+    /// Queue a state snapshot that supersedes any queued frame with the same `key`
+    pub fn send_latest(
+        &self,
+        key: &'static str,
+        message: Message,
+    ) -> Result<Delivery, QueueClosed> {
+        self.push(Some(key), message)
+    }
+
+    pub fn stats(&self) -> SendStats {
+        let state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        SendStats {
+            queued: state.frames.len(),
+            ..state.stats
+        }
+    }
+
+    fn push(&self, key: Option<&'static str>, message: Message) -> Result<Delivery, QueueClosed> {
+        let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.receiver_gone {
+            return Err(QueueClosed);
+        }
+
+        if let Some(key) = key {
+            if let Some(queued) = state.frames.iter_mut().find(|frame| frame.key == Some(key)) {
+                queued.message = message;
+                state.stats.coalesced += 1;
+                return Ok(Delivery::Coalesced);
+            }
+        }
+
+        let is_close = matches!(message, Message::Close(_));
+        if state.frames.len() >= self.shared.capacity && !is_close {
+            // Stale snapshots are the cheapest thing to lose
+            match state.frames.iter().position(|frame| frame.key.is_some()) {
+                Some(index) => {
+                    state.frames.remove(index);
+                    state.stats.dropped += 1;
+                }
+                None => {
+                    state.stats.dropped += 1;
+                    return Ok(Delivery::Dropped);
+                }
+            }
+        }
+
+        state.frames.push_back(QueuedFrame { key, message });
+        state.stats.high_water = state.stats.high_water.max(state.frames.len());
+        drop(state);
+        self.shared.notify.notify_one();
+        Ok(Delivery::Queued)
+    }
+}
+
+impl Clone for QueueSender {
+    fn clone(&self) -> Self {
+        let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.senders += 1;
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.notify.notify_one();
+        }
+    }
+}
+
+pub struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    /// Next frame to write; `None` once every sender is gone and the queue is drained
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(frame) = state.frames.pop_front() {
+                    state.stats.sent += 1;
+                    return Some(frame.message);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.receiver_gone = true;
+        state.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Message {
+        Message::Text(value.to_string())
+    }
+
+    #[tokio::test]
+    async fn newest_snapshot_wins() {
+        let (sender, mut receiver) = client_queue(8);
+        sender.send(text("command")).unwrap();
+        sender.send_latest("progress", text("1.0")).unwrap();
+        assert_eq!(
+            sender.send_latest("progress", text("2.0")),
+            Ok(Delivery::Coalesced)
+        );
+
+        assert_eq!(receiver.recv().await, Some(text("command")));
+        assert_eq!(receiver.recv().await, Some(text("2.0")));
+        let stats = sender.stats();
+        assert_eq!((stats.sent, stats.coalesced, stats.queued), (2, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn full_queue_evicts_snapshots_before_refusing_frames() {
+        let (sender, mut receiver) = client_queue(2);
+        sender.send_latest("progress", text("snapshot")).unwrap();
+        sender.send(text("a")).unwrap();
+
+        assert_eq!(sender.send(text("b")), Ok(Delivery::Queued));
+        assert_eq!(sender.send(text("c")), Ok(Delivery::Dropped));
+        // Closing still works when the client is not keeping up
+        assert_eq!(sender.send(Message::Close(None)), Ok(Delivery::Queued));
+        assert_eq!(sender.stats().dropped, 2);
+
+        assert_eq!(receiver.recv().await, Some(text("a")));
+        assert_eq!(receiver.recv().await, Some(text("b")));
+        assert_eq!(receiver.recv().await, Some(Message::Close(None)));
+    }
+
+    #[tokio::test]
+    async fn ends_when_either_side_goes_away() {
+        let (sender, mut receiver) = client_queue(4);
+        let clone = sender.clone();
+        sender.send(text("last")).unwrap();
+        drop(sender);
+        drop(clone);
+        assert_eq!(receiver.recv().await, Some(text("last")));
+        assert_eq!(receiver.recv().await, None);
+
+        let (sender, receiver) = client_queue(4);
+        drop(receiver);
+        assert_eq!(sender.send(text("late")), Err(QueueClosed));
+    }
+}
@@ -1,5 +1,5 @@
 use crate::track_cleaning::clean_track_name;
-use crate::types::{TrackInfo, TrackState};
+use crate::types::{SettingsState, TrackInfo, TrackState};
 use tauri::State;
 
 #[tauri::command]
@@ -19,6 +19,10 @@ pub async fn set_current_track(
 }
 
 #[tauri::command]
-pub async fn clean_track_name_command(track_name: String) -> Result<String, String> {
-    Ok(clean_track_name(&track_name).await)
+pub async fn clean_track_name_command(
+    track_name: String,
+    settings_state: State<'_, SettingsState>,
+) -> Result<String, String> {
+    let settings = settings_state.lock().await.track_cleaning.clone();
+    Ok(clean_track_name(&track_name, &settings).await)
 }
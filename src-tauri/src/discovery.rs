@@ -0,0 +1,94 @@
+//! Well-known file describing where the running app can be reached.
+//!
+//! Local tools that cannot be told the port up front read this file instead of
+//! guessing. It lives in the per-user runtime directory and is rewritten whenever
+//! a listener binds.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Discovery {
+    pub pid: u32,
+    pub app_version: String,
+    pub websocket_port: Option<u16>,
+    /// Set while the opt-in HTTP API is running
+    #[serde(default)]
+    pub http_port: Option<u16>,
+}
+
+/// `$XDG_RUNTIME_DIR/lyryc`, falling back to a per-user temp directory
+pub fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| PathBuf::from(dir).join("lyryc"))
+        .unwrap_or_else(|| std::env::temp_dir().join(format!("lyryc-{}", user_name())))
+}
+
+pub fn discovery_path() -> PathBuf {
+    runtime_dir().join("discovery.json")
+}
+
+/// Local socket native messaging hosts bridge into the app through
+#[cfg(unix)]
+pub fn bridge_endpoint() -> PathBuf {
+    runtime_dir().join("bridge.sock")
+}
+
+/// Named pipe native messaging hosts bridge into the app through
+#[cfg(windows)]
+pub fn bridge_endpoint() -> String {
+    format!(r"\\.\pipe\lyryc-{}", user_name())
+}
+
+fn user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "default".to_string())
+}
+
+/// Create the runtime directory, readable by the current user only
+pub fn ensure_runtime_dir() -> Result<PathBuf, String> {
+    let dir = runtime_dir();
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700));
+    }
+    Ok(dir)
+}
+
+pub fn read_discovery() -> Option<Discovery> {
+    let contents = std::fs::read_to_string(discovery_path()).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Apply `update` to the current discovery record and write it back
+pub fn publish<F>(update: F) -> Result<(), String>
+where
+    F: FnOnce(&mut Discovery),
+{
+    let path = discovery_path();
+    let mut discovery = read_discovery()
+        .filter(|existing| existing.pid == std::process::id())
+        .unwrap_or_else(|| Discovery {
+            pid: std::process::id(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            ..Default::default()
+        });
+    update(&mut discovery);
+
+    ensure_runtime_dir()?;
+
+    let contents = serde_json::to_string_pretty(&discovery).map_err(|e| e.to_string())?;
+    std::fs::write(&path, contents)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Remove the discovery file if it still belongs to this process
+pub fn withdraw() {
+    if read_discovery().is_some_and(|existing| existing.pid == std::process::id()) {
+        let _ = std::fs::remove_file(discovery_path());
+    }
+}
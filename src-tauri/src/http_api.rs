@@ -0,0 +1,484 @@
+//! Opt-in localhost HTTP API for scripts (status bars, stream decks, home automation).
+//!
+//! Serves the current track and lyrics as JSON, plus a Server-Sent Events stream of the
+//! same events the frontend receives. Every request must carry the HTTP API token, not
+//! the extension pairing token, as `Authorization: Bearer <token>` or as a `token` query
+//! parameter for clients such as `EventSource` that cannot set headers. The streaming
+//! overlay (see `overlay.rs`) is served from here too, and its URL carries a read-only
+//! overlay token instead.
+
+use crate::auth::AuthConfig;
+use crate::discovery;
+use crate::line_tracking::LineChange;
+use crate::overlay;
+use crate::protocol::PlaybackCommand;
+use crate::sources::SourceKey;
+use crate::types::{
+    AuthState, HttpApiState, LyricLine, LyricsState, SourceState, TrackInfo, TrackState,
+    WebSocketState,
+};
+use crate::websocket_commands::dispatch_playback_command;
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{Stream, StreamExt};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use tauri::{AppHandle, EventId, Listener, Manager};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+/// Port used when none is given; outside the WebSocket fallback range
+pub const DEFAULT_HTTP_PORT: u16 = 8780;
+
+/// Events relayed on `/events`, named as the frontend receives them
+const STREAMED_EVENTS: &[&str] = &[
+    "track-updated",
+    "track-started",
+    "track-ended",
+    "track-seeked",
+    "playback-state",
+    "track-time-update",
+    "lyric-line-changed",
+    "lyric-progress",
+    "active-source-changed",
+];
+
+#[derive(Debug, Clone)]
+struct ApiEvent {
+    name: &'static str,
+    data: String,
+}
+
+#[derive(Clone)]
+struct ApiContext {
+    app_handle: AppHandle,
+    events: broadcast::Sender<ApiEvent>,
+    shutdown: CancellationToken,
+}
+
+/// A running HTTP API; `stop` shuts it down and releases the event listeners
+pub struct HttpApiHandle {
+    port: u16,
+    shutdown: CancellationToken,
+    listeners: Vec<EventId>,
+}
+
+impl HttpApiHandle {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn stop(self, app_handle: &AppHandle) {
+        self.shutdown.cancel();
+        for listener in self.listeners {
+            app_handle.unlisten(listener);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpApiStatus {
+    pub running: bool,
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NowPlaying {
+    track: Option<TrackInfo>,
+    is_playing: bool,
+    current_time: Option<f64>,
+    source: Option<SourceKey>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LyricsResponse {
+    lines: Vec<LyricLine>,
+    current_index: Option<usize>,
+}
+
+/// Body of `POST /playback`: a `PlaybackCommand` plus optional addressing
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaybackRequest {
+    #[serde(flatten)]
+    command: PlaybackCommand,
+    client_id: Option<String>,
+    source: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Port requested through `LYRYC_HTTP_PORT`; the API stays off when it is unset
+pub fn port_from_env() -> Option<u16> {
+    let value = std::env::var("LYRYC_HTTP_PORT").ok()?;
+    match value.parse::<u16>() {
+        Ok(port) if port != 0 => Some(port),
+        _ => {
+            warn!("Ignoring invalid LYRYC_HTTP_PORT value: {}", value);
+            None
+        }
+    }
+}
+
+/// Bind `127.0.0.1:port` and serve the API until the returned handle is stopped
+pub async fn start(app_handle: &AppHandle, port: u16) -> Result<HttpApiHandle, String> {
+    let addr = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|e| format!("Failed to bind HTTP API to {}: {}", addr, e))?;
+
+    let (events, _) = broadcast::channel(64);
+    let listeners = STREAMED_EVENTS
+        .iter()
+        .map(|&name| {
+            let events = events.clone();
+            app_handle.listen_any(name, move |event| {
+                // No subscribers is the normal case, not an error
+                let _ = events.send(ApiEvent {
+                    name,
+                    data: event.payload().to_string(),
+                });
+            })
+        })
+        .collect();
+
+    let shutdown = CancellationToken::new();
+    let context = ApiContext {
+        app_handle: app_handle.clone(),
+        events,
+        shutdown: shutdown.clone(),
+    };
+    let router = Router::new()
+        .route("/now-playing", get(now_playing))
+        .route("/lyrics", get(lyrics))
+        .route("/lyrics/current-line", get(current_line))
+        .route("/playback", post(playback))
+        .route("/events", get(events_stream))
+        .route("/overlay", get(overlay::overlay_page))
+        .layer(middleware::from_fn_with_state(
+            context.clone(),
+            require_token,
+        ))
+        .with_state(context);
+
+    let serve_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let result = axum::serve(listener, router)
+            .with_graceful_shutdown(serve_shutdown.cancelled_owned())
+            .await;
+        if let Err(e) = result {
+            error!("HTTP API stopped: {}", e);
+        }
+        // A restart on another port may already have published its own
+        let _ = discovery::publish(|d| {
+            if d.http_port == Some(port) {
+                d.http_port = None;
+            }
+        });
+    });
+
+    if let Err(e) = discovery::publish(|d| d.http_port = Some(port)) {
+        warn!("Failed to write discovery file: {}", e);
+    }
+    info!("HTTP API listening on: http://{}", addr);
+    Ok(HttpApiHandle {
+        port,
+        shutdown,
+        listeners,
+    })
+}
+
+/// Token from the `Authorization` header, falling back to the `token` query parameter
+fn request_token(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_string());
+    bearer.or_else(|| {
+        Query::<TokenQuery>::try_from_uri(uri)
+            .ok()
+            .and_then(|query| query.0.token)
+    })
+}
+
+/// What the overlay page needs; the overlay token grants nothing else
+const OVERLAY_PATHS: &[&str] = &["/overlay", "/lyrics/current-line", "/events"];
+
+/// The API token grants everything, the overlay token only reads what the overlay shows
+fn authorize(auth: &AuthConfig, token: Option<&str>, method: &Method, path: &str) -> bool {
+    auth.verify_api_token(token)
+        || (method == Method::GET
+            && OVERLAY_PATHS.contains(&path)
+            && auth.verify_overlay_token(token))
+}
+
+async fn require_token(
+    State(context): State<ApiContext>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let token = request_token(request.headers(), request.uri());
+    let authorized = context
+        .app_handle
+        .state::<AuthState>()
+        .read()
+        .map(|auth| {
+            authorize(
+                &auth,
+                token.as_deref(),
+                request.method(),
+                request.uri().path(),
+            )
+        })
+        .unwrap_or(false);
+
+    if !authorized {
+        debug!("Rejected HTTP API request to {}", request.uri().path());
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "error": "Missing or invalid API token" })),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+async fn now_playing(State(context): State<ApiContext>) -> Json<NowPlaying> {
+    let track = context
+        .app_handle
+        .state::<TrackState>()
+        .lock()
+        .await
+        .clone();
+    let snapshot = context
+        .app_handle
+        .state::<SourceState>()
+        .lock()
+        .await
+        .snapshot();
+    let active = snapshot.sources.into_iter().find(|source| source.active);
+
+    Json(match active {
+        Some(active) => NowPlaying {
+            track,
+            is_playing: active.is_playing,
+            current_time: active.current_time,
+            source: Some(active.key),
+        },
+        None => NowPlaying {
+            track,
+            is_playing: false,
+            current_time: None,
+            source: None,
+        },
+    })
+}
+
+async fn lyrics(State(context): State<ApiContext>) -> Json<LyricsResponse> {
+    let tracker = context.app_handle.state::<LyricsState>();
+    let tracker = tracker.lock().await;
+    Json(LyricsResponse {
+        lines: tracker.lyrics().to_vec(),
+        current_index: tracker.current().and_then(|change| change.index),
+    })
+}
+
+async fn current_line(State(context): State<ApiContext>) -> Json<Option<LineChange>> {
+    Json(
+        context
+            .app_handle
+            .state::<LyricsState>()
+            .lock()
+            .await
+            .current(),
+    )
+}
+
+async fn playback(
+    State(context): State<ApiContext>,
+    Json(request): Json<PlaybackRequest>,
+) -> Response {
+    info!("Playback control over HTTP: {:?}", request.command);
+    let ws_state = context.app_handle.state::<WebSocketState>();
+    let source_state = context.app_handle.state::<SourceState>();
+    let result = dispatch_playback_command(
+        request.command,
+        request.client_id,
+        request.source,
+        &ws_state,
+        &source_state,
+        &context.app_handle,
+    )
+    .await;
+
+    match result {
+        Ok(outcome) => Json(outcome).into_response(),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            Json(serde_json::json!({ "error": e })),
+        )
+            .into_response(),
+    }
+}
+
+async fn events_stream(
+    State(context): State<ApiContext>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = context.events.subscribe();
+    let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let event = Event::default().event(event.name).data(event.data);
+                    return Some((Ok(event), receiver));
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("SSE subscriber lagged, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    // End open streams on shutdown, otherwise graceful shutdown would wait on them forever
+    let stream = stream.take_until(context.shutdown.cancelled_owned());
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[tauri::command]
+pub async fn start_http_api(
+    port: Option<u16>,
+    http_state: tauri::State<'_, HttpApiState>,
+    app_handle: AppHandle,
+) -> Result<HttpApiStatus, String> {
+    let port = match port {
+        Some(port) => port,
+        None => crate::settings::current(&app_handle).await.http_api.port,
+    };
+    let mut guard = http_state.lock().await;
+    if let Some(running) = guard.as_ref() {
+        if running.port() == port {
+            return Ok(HttpApiStatus {
+                running: true,
+                port: Some(port),
+            });
+        }
+    }
+    if let Some(running) = guard.take() {
+        running.stop(&app_handle);
+    }
+
+    let handle = start(&app_handle, port).await?;
+    let status = HttpApiStatus {
+        running: true,
+        port: Some(handle.port()),
+    };
+    *guard = Some(handle);
+    Ok(status)
+}
+
+#[tauri::command]
+pub async fn stop_http_api(
+    http_state: tauri::State<'_, HttpApiState>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    if let Some(running) = http_state.lock().await.take() {
+        running.stop(&app_handle);
+        info!("HTTP API stopped");
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_http_api_status(
+    http_state: tauri::State<'_, HttpApiState>,
+) -> Result<HttpApiStatus, String> {
+    let guard = http_state.lock().await;
+    Ok(HttpApiStatus {
+        running: guard.is_some(),
+        port: guard.as_ref().map(|handle| handle.port()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn playback_request_flattens_the_command() {
+        let request: PlaybackRequest =
+            serde_json::from_str(r#"{"command":"seek","seekTime":42.5,"source":"spotify"}"#)
+                .unwrap();
+        assert_eq!(request.command, PlaybackCommand::Seek { seek_time: 42.5 });
+        assert_eq!(request.source.as_deref(), Some("spotify"));
+        assert_eq!(request.client_id, None);
+    }
+
+    #[test]
+    fn token_from_header_or_query() {
+        let uri: Uri = "/events?token=ABCD-1234".parse().unwrap();
+        assert_eq!(
+            request_token(&HeaderMap::new(), &uri).as_deref(),
+            Some("ABCD-1234")
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer EF56-7890"),
+        );
+        assert_eq!(request_token(&headers, &uri).as_deref(), Some("EF56-7890"));
+
+        let bare: Uri = "/now-playing".parse().unwrap();
+        assert_eq!(request_token(&HeaderMap::new(), &bare), None);
+    }
+
+    #[test]
+    fn overlay_token_only_reads_what_the_overlay_shows() {
+        let auth = AuthConfig {
+            pairing_token: "ABCD-1234-EF56".to_string(),
+            api_token: "API".to_string(),
+            overlay_token: "OVERLAY".to_string(),
+            allowed_extension_ids: Vec::new(),
+        };
+        for path in OVERLAY_PATHS {
+            assert!(authorize(&auth, Some("OVERLAY"), &Method::GET, path));
+            assert!(authorize(&auth, Some("API"), &Method::GET, path));
+        }
+        assert!(!authorize(
+            &auth,
+            Some("OVERLAY"),
+            &Method::GET,
+            "/now-playing"
+        ));
+        assert!(!authorize(&auth, Some("OVERLAY"), &Method::GET, "/lyrics"));
+        assert!(!authorize(
+            &auth,
+            Some("OVERLAY"),
+            &Method::POST,
+            "/playback"
+        ));
+        assert!(authorize(&auth, Some("API"), &Method::POST, "/playback"));
+        assert!(!authorize(
+            &auth,
+            Some("ABCD-1234-EF56"),
+            &Method::GET,
+            "/events"
+        ));
+    }
+}
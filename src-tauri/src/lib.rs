@@ -1,30 +1,79 @@
 use log::{error, info};
-use tauri::tray::{TrayIconBuilder, TrayIconEvent};
-use tauri::{Emitter, Manager};
+use tauri::Manager;
 use tokio::sync::Mutex;
 
 // Module declarations
+mod auth;
+mod auto_hide;
+mod client_queue;
 mod commands;
+pub mod discovery;
+mod http_api;
+mod line_tracking;
 mod lyrics;
+#[cfg(target_os = "linux")]
+mod mpris;
+mod native_messaging;
+mod overlay;
+mod protocol;
+mod recording;
+mod settings;
+mod shortcuts;
+mod sources;
 mod track_cleaning;
+mod track_identity;
+mod track_pipeline;
+#[cfg(desktop)]
+mod tray;
 mod types;
 mod websocket;
 mod websocket_commands;
+mod window_geometry;
 mod window_management;
 
 // Import types and functions from modules
+use auth::{
+    auth_file_path, get_api_token, get_pairing_token, regenerate_api_token,
+    regenerate_overlay_token, regenerate_pairing_token, set_allowed_extension_ids, AuthConfig,
+};
+use auto_hide::AutoHidePolicy;
 use commands::{clean_track_name_command, get_current_track, set_current_track};
+use http_api::{get_http_api_status, start_http_api, stop_http_api};
+use line_tracking::{
+    clear_active_lyrics, get_active_lyrics, get_current_line, set_active_lyrics, set_lyrics_offset,
+    LineTracker,
+};
 use lyrics::{fetch_lrclib_raw, fetch_lyrics};
-use types::{ClickThroughState, TrackState, WebSocketState};
+use native_messaging::install_native_messaging_host;
+use overlay::get_overlay_url;
+use recording::{replay_session, start_session_recording, stop_session_recording};
+use settings::{get_settings, reset_settings, settings_file_path, update_settings, Settings};
+use shortcuts::{get_shortcuts, set_shortcut};
+use sources::{list_track_sources, select_track_source, set_source_policy, SourceRegistry};
+use track_identity::TrackChangeDetector;
+use track_pipeline::run_track_pipeline;
+use types::{
+    AuthState, AutoHideState, ClickThroughState, HttpApiState, LyricsState, RecorderState,
+    SettingsState, ShortcutRegistryState, SourceState, TrackDetectorState, TrackPipeline,
+    TrackState, WebSocketState, WindowGeometryState,
+};
 use websocket_commands::{
     control_playback, debug_websocket_server, get_websocket_clients_count, get_websocket_status,
-    init_extension_connection, send_playback_command,
+    init_extension_connection, list_extension_clients, restart_websocket_server,
+    send_playback_command, shutdown_extension_server, stop_websocket_server,
+};
+use window_geometry::{
+    geometry_file_path, list_monitors, move_to_monitor, reset_window_geometry, set_window_anchor,
+    WindowGeometries, WindowGeometryStore,
 };
 use window_management::{
-    enable_drag_mode, initialize_window_sizing, minimize_to_tray, quit_app, restore_from_tray,
-    toggle_window_visibility,
+    enable_drag_mode, hide_karaoke_window, initialize_window_sizing, minimize_to_tray, quit_app,
+    restore_from_tray, show_karaoke_window, toggle_karaoke_window, toggle_window_visibility,
 };
 
+/// Native messaging host and host installer modes of the binary
+pub use native_messaging::run_from_args;
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // Initialize logger
@@ -37,99 +86,78 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .setup(|app| {
-            #[cfg(desktop)]
-            {
-                use tauri_plugin_global_shortcut::{
-                    Code, GlobalShortcutExt, Modifiers, Shortcut, ShortcutState,
-                };
+            // Load (or create) the pairing token before the WebSocket server can start
+            let auth_path = auth_file_path(app.handle())?;
+            app.manage(AuthState::new(std::sync::RwLock::new(
+                AuthConfig::load_or_create(&auth_path),
+            )));
+            let settings = Settings::load_or_default(&settings_file_path(app.handle())?);
+            app.manage(SettingsState::new(Mutex::new(settings.clone())));
+
+            // Put the lyrics bar back where it was on this monitor layout
+            let mut geometry =
+                WindowGeometryStore::load(&geometry_file_path(app.handle(), "main")?);
+            let main_window = app.get_webview_window("main");
+            if let Some(window) = &main_window {
+                if let Err(e) = window_geometry::restore(window, &mut geometry, &settings.window) {
+                    error!("Failed to restore window geometry: {}", e);
+                }
+            }
+            let mut geometries = WindowGeometries::default();
+            geometries.insert("main", geometry);
+            app.manage(WindowGeometryState::new(Mutex::new(geometries)));
+            if let Some(window) = &main_window {
+                window_geometry::track(window);
+                tauri::async_runtime::spawn(window_geometry::watch_monitors(app.handle().clone()));
+            }
 
-                // Define shortcuts
-                let ctrl_shift_d =
-                    Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyD);
-                let ctrl_shift_m =
-                    Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyM);
+            // Every track source (extension clients, native players) feeds one ordered pipeline
+            let (pipeline, pipeline_events) = tokio::sync::mpsc::unbounded_channel();
+            app.manage::<TrackPipeline>(pipeline.clone());
+            tauri::async_runtime::spawn(run_track_pipeline(app.handle().clone(), pipeline_events));
+            tauri::async_runtime::spawn(auto_hide::run(app.handle().clone()));
+
+            // The HTTP API is opt-in, through settings or LYRYC_HTTP_PORT for one run
+            app.manage(HttpApiState::new(Mutex::new(None)));
+            let http_port = http_api::port_from_env()
+                .or(settings.http_api.enabled.then_some(settings.http_api.port));
+            if let Some(port) = http_port {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    match http_api::start(&app_handle, port).await {
+                        Ok(handle) => {
+                            *app_handle.state::<HttpApiState>().lock().await = Some(handle)
+                        }
+                        Err(e) => error!("Failed to start HTTP API: {}", e),
+                    }
+                });
+            }
 
-                // Get state for the handlers
-                let click_through_state = app.state::<ClickThroughState>().inner().clone();
+            #[cfg(target_os = "linux")]
+            {
+                app.manage(types::MprisState::new(Mutex::new(None)));
+                mpris::start(app.handle().clone(), pipeline);
+            }
 
-                // Initialize global shortcut plugin with handler
+            #[cfg(desktop)]
+            {
+                use tauri_plugin_global_shortcut::ShortcutState;
+
+                // Bindings come from settings; the handler looks up the pressed shortcut's action
                 app.handle().plugin(
                     tauri_plugin_global_shortcut::Builder::new()
-                        .with_handler(move |app, shortcut, event| {
+                        .with_handler(|app, shortcut, event| {
                             if event.state() == ShortcutState::Pressed {
-                                if shortcut == &ctrl_shift_d {
-                                    info!("Global shortcut Ctrl+Shift+D triggered - toggling click-through");
-                                    let app_handle = app.clone();
-                                    let click_through_state = click_through_state.clone();
-
-                                    tauri::async_runtime::spawn(async move {
-                                        // Get window
-                                        if let Some(window) = app_handle.get_webview_window("main") {
-                                            // Toggle click-through state
-                                            let current_state = {
-                                                let mut state = click_through_state.lock().await;
-                                                *state = !*state; // Toggle the state
-                                                *state
-                                            };
-
-                                            // Apply the new state to the window
-                                            if let Err(e) = window.set_ignore_cursor_events(current_state) {
-                                                error!("Failed to set click-through to {}: {}", current_state, e);
-                                                return;
-                                            }
-
-                                            // Emit event to frontend with new state
-                                            let event_name = if current_state { "click-through-enabled" } else { "click-through-disabled" };
-                                            if let Err(e) = app_handle.emit(event_name, current_state) {
-                                                error!("Failed to emit {} event: {}", event_name, e);
-                                                return;
-                                            }
-
-                                            info!("Click-through toggled to: {} ({})",
-                                                  if current_state { "enabled" } else { "disabled" },
-                                                  if current_state { "click-through active" } else { "draggable/interactive" });
-                                        } else {
-                                            error!("Main window not found for global shortcut handler");
-                                        }
-                                    });
-                                } else if shortcut == &ctrl_shift_m {
-                                    info!("Global shortcut Ctrl+Shift+M triggered - toggling window visibility");
-                                    let app_handle = app.clone();
-
-                                    tauri::async_runtime::spawn(async move {
-                                        if let Err(e) = toggle_window_visibility(app_handle).await {
-                                            error!("Failed to toggle window visibility: {}", e);
-                                        }
-                                    });
-                                }
+                                shortcuts::handle_pressed(app, shortcut.id());
                             }
                         })
                         .build(),
                 )?;
-
-                // Register the shortcuts
-                app.global_shortcut().register(ctrl_shift_d)?;
-                app.global_shortcut().register(ctrl_shift_m)?;
-
-                info!("Global shortcuts registered: Ctrl+Shift+D (toggle click-through), Ctrl+Shift+M (minimize/restore)");
+                shortcuts::register_startup(app.handle(), &settings.shortcuts);
 
                 // Setup system tray
-                let _tray = TrayIconBuilder::with_id("lyryc-tray")
-                    .tooltip("Lyryc - Clean Lyric Viewer")
-                    .icon(app.default_window_icon().unwrap().clone())
-                    .on_tray_icon_event(|tray, event| match event {
-                        TrayIconEvent::Click { .. } => {
-                            info!("Tray icon clicked - toggling window visibility");
-                            let app_handle = tray.app_handle().clone();
-                            tauri::async_runtime::spawn(async move {
-                                if let Err(e) = toggle_window_visibility(app_handle).await {
-                                    error!("Failed to toggle window from tray click: {}", e);
-                                }
-                            });
-                        }
-                        _ => {}
-                    })
-                    .build(app)?;
+                app.manage(types::TrayState::default());
+                tray::build(app)?;
 
                 info!("System tray initialized in backend");
             }
@@ -137,26 +165,81 @@ pub fn run() {
         })
         .manage(TrackState::new(Mutex::new(None)))
         .manage(WebSocketState::new(Mutex::new(None)))
+        .manage(LyricsState::new(Mutex::new(LineTracker::new())))
+        .manage(SourceState::new(Mutex::new(SourceRegistry::new())))
+        .manage(TrackDetectorState::new(Mutex::new(
+            TrackChangeDetector::new(),
+        )))
+        .manage(RecorderState::default())
         .manage(ClickThroughState::new(Mutex::new(true))) // Start with click-through enabled
+        .manage(AutoHideState::new(Mutex::new(AutoHidePolicy::new(
+            std::time::Instant::now(),
+        ))))
+        .manage(ShortcutRegistryState::default())
         .invoke_handler(tauri::generate_handler![
             get_current_track,
             set_current_track,
             clean_track_name_command,
             fetch_lyrics,
             fetch_lrclib_raw,
+            set_active_lyrics,
+            clear_active_lyrics,
+            get_current_line,
+            set_lyrics_offset,
+            get_active_lyrics,
             init_extension_connection,
             get_websocket_status,
             get_websocket_clients_count,
             debug_websocket_server,
+            list_extension_clients,
+            stop_websocket_server,
+            restart_websocket_server,
+            start_session_recording,
+            stop_session_recording,
+            replay_session,
+            list_track_sources,
+            select_track_source,
+            set_source_policy,
+            get_settings,
+            update_settings,
+            reset_settings,
+            get_shortcuts,
+            set_shortcut,
+            get_pairing_token,
+            regenerate_pairing_token,
+            get_api_token,
+            regenerate_api_token,
+            regenerate_overlay_token,
+            set_allowed_extension_ids,
+            install_native_messaging_host,
+            start_http_api,
+            stop_http_api,
+            get_http_api_status,
+            get_overlay_url,
             control_playback,
             send_playback_command,
             initialize_window_sizing,
+            list_monitors,
+            move_to_monitor,
+            set_window_anchor,
+            reset_window_geometry,
             minimize_to_tray,
             restore_from_tray,
             toggle_window_visibility,
+            show_karaoke_window,
+            hide_karaoke_window,
+            toggle_karaoke_window,
             quit_app,
             enable_drag_mode
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Extensions see a close frame instead of a connection reset, and clients
+                // stop finding this instance, however the app was quit
+                tauri::async_runtime::block_on(shutdown_extension_server(app_handle));
+                discovery::withdraw();
+            }
+        });
 }
@@ -0,0 +1,368 @@
+use crate::types::{LyricLine, LyricsState};
+use log::{debug, error, info};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager, State};
+
+/// `lyric-progress` is emitted at most this often while a line is sung
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Position of playback within the active lyrics, emitted as `lyric-line-changed`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineChange {
+    pub index: Option<usize>, // None before the first line
+    pub line: Option<LyricLine>,
+    pub next_line: Option<LyricLine>,
+    pub progress: f64,     // 0.0 - 1.0 within the current line, when it changed
+    pub current_time: f64, // playback time, before the offset
+}
+
+/// How far into the current line playback is, emitted as `lyric-progress`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineProgress {
+    pub index: usize,
+    pub progress: f64, // 0.0 - 1.0
+    pub current_time: f64,
+}
+
+/// Holds the lyrics for the current track and follows the playback clock
+#[derive(Debug, Default)]
+pub struct LineTracker {
+    lyrics: Vec<LyricLine>,
+    current_index: Option<usize>,
+    last_time: Option<f64>,
+    /// Added to the playback time, as the lyrics bar does (track plus global offset)
+    offset: f64,
+    last_progress_at: Option<Instant>,
+}
+
+impl LineTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the active lyrics, returning the line at the last known position
+    pub fn set_lyrics(&mut self, mut lyrics: Vec<LyricLine>) -> Option<LineChange> {
+        lyrics.sort_by(|a, b| a.time.total_cmp(&b.time));
+        self.lyrics = lyrics;
+        self.current_index = None;
+
+        let time = self.last_time?;
+        self.update(time)
+    }
+
+    /// Drop lyrics and position, e.g. when the track changes
+    pub fn clear(&mut self) {
+        self.lyrics.clear();
+        self.current_index = None;
+        self.last_time = None;
+    }
+
+    /// Advance to `time`, returning a change only when the active line differs
+    pub fn update(&mut self, time: f64) -> Option<LineChange> {
+        self.last_time = Some(time);
+
+        if self.lyrics.is_empty() {
+            return None;
+        }
+
+        let index = self.index_at(time + self.offset);
+        if index == self.current_index {
+            return None;
+        }
+
+        self.current_index = index;
+        Some(self.snapshot(time))
+    }
+
+    /// Progress through the current line, at most once per `PROGRESS_INTERVAL`
+    pub fn progress(&mut self, now: Instant) -> Option<LineProgress> {
+        if self
+            .last_progress_at
+            .is_some_and(|at| now.duration_since(at) < PROGRESS_INTERVAL)
+        {
+            return None;
+        }
+        let current = self.current()?;
+        let index = current.index?;
+        self.last_progress_at = Some(now);
+        Some(LineProgress {
+            index,
+            progress: current.progress,
+            current_time: current.current_time,
+        })
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// Shift the lyrics by `offset` seconds, returning the line at the last known position
+    /// if that changes it
+    pub fn set_offset(&mut self, offset: f64) -> Option<LineChange> {
+        self.offset = offset;
+        let time = self.last_time?;
+        self.update(time)
+    }
+
+    /// Active lyrics, sorted by start time
+    pub fn lyrics(&self) -> &[LyricLine] {
+        &self.lyrics
+    }
+
+    /// Current line at the last known playback position
+    pub fn current(&self) -> Option<LineChange> {
+        if self.lyrics.is_empty() {
+            return None;
+        }
+        self.last_time.map(|time| self.snapshot(time))
+    }
+
+    fn index_at(&self, time: f64) -> Option<usize> {
+        // Lyrics are sorted, so the active line is the last one starting at or before `time`
+        self.lyrics
+            .partition_point(|line| line.time <= time)
+            .checked_sub(1)
+    }
+
+    fn snapshot(&self, playback_time: f64) -> LineChange {
+        let time = playback_time + self.offset;
+        let index = self.index_at(time);
+        let line = index.and_then(|i| self.lyrics.get(i)).cloned();
+        let next_line = match index {
+            Some(i) => self.lyrics.get(i + 1).cloned(),
+            None => self.lyrics.first().cloned(),
+        };
+
+        let progress = match &line {
+            Some(line) => {
+                let end = line
+                    .duration
+                    .map(|d| line.time + d)
+                    .or_else(|| next_line.as_ref().map(|next| next.time));
+                match end {
+                    Some(end) if end > line.time => {
+                        ((time - line.time) / (end - line.time)).clamp(0.0, 1.0)
+                    }
+                    _ => 0.0,
+                }
+            }
+            None => 0.0,
+        };
+
+        LineChange {
+            index,
+            line,
+            next_line,
+            progress,
+            current_time: playback_time,
+        }
+    }
+}
+
+pub fn emit_line_change(app_handle: &tauri::AppHandle, change: &LineChange) {
+    debug!("Lyric line changed: {:?}", change.index);
+    if let Err(e) = app_handle.emit("lyric-line-changed", change) {
+        error!("Failed to emit lyric-line-changed event: {}", e);
+    }
+    #[cfg(desktop)]
+    crate::tray::show_line(
+        app_handle,
+        change.line.as_ref().map(|line| line.text.as_str()),
+    );
+}
+
+pub fn emit_line_progress(app_handle: &tauri::AppHandle, progress: &LineProgress) {
+    if let Err(e) = app_handle.emit("lyric-progress", progress) {
+        error!("Failed to emit lyric-progress event: {}", e);
+    }
+}
+
+/// Lets other windows (karaoke) show the same lyrics as the lyrics bar
+pub fn emit_active_lyrics(app_handle: &tauri::AppHandle, lyrics: &[LyricLine]) {
+    if let Err(e) = app_handle.emit("active-lyrics-changed", lyrics) {
+        error!("Failed to emit active-lyrics-changed event: {}", e);
+    }
+}
+
+#[tauri::command]
+pub async fn set_active_lyrics(
+    lyrics: Vec<LyricLine>,
+    lyrics_state: State<'_, LyricsState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    info!("Setting active lyrics ({} lines)", lyrics.len());
+
+    let mut tracker = lyrics_state.lock().await;
+    let change = tracker.set_lyrics(lyrics);
+    emit_active_lyrics(&app_handle, tracker.lyrics());
+    if let Some(change) = change {
+        emit_line_change(&app_handle, &change);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn clear_active_lyrics(
+    lyrics_state: State<'_, LyricsState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    lyrics_state.lock().await.clear();
+    emit_active_lyrics(&app_handle, &[]);
+    Ok(())
+}
+
+/// Nudge the offset by `delta` seconds, for the offset shortcuts and tray items
+pub async fn adjust_offset(app_handle: &tauri::AppHandle, delta: f64) {
+    let change = {
+        let lyrics_state = app_handle.state::<LyricsState>();
+        let mut tracker = lyrics_state.lock().await;
+        let offset = tracker.offset() + delta;
+        tracker.set_offset(offset)
+    };
+    if let Some(change) = change {
+        emit_line_change(app_handle, &change);
+    }
+}
+
+/// Keep line tracking on the same offset as the lyrics bar
+#[tauri::command]
+pub async fn set_lyrics_offset(
+    offset: f64,
+    lyrics_state: State<'_, LyricsState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    if !offset.is_finite() {
+        return Err(format!("Invalid lyrics offset: {}", offset));
+    }
+    debug!("Lyrics offset: {:.2}s", offset);
+    let change = lyrics_state.lock().await.set_offset(offset);
+    if let Some(change) = change {
+        emit_line_change(&app_handle, &change);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_active_lyrics(
+    lyrics_state: State<'_, LyricsState>,
+) -> Result<Vec<LyricLine>, String> {
+    Ok(lyrics_state.lock().await.lyrics().to_vec())
+}
+
+#[tauri::command]
+pub async fn get_current_line(
+    lyrics_state: State<'_, LyricsState>,
+) -> Result<Option<LineChange>, String> {
+    Ok(lyrics_state.lock().await.current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(time: f64, text: &str, duration: Option<f64>) -> LyricLine {
+        LyricLine {
+            time,
+            text: text.to_string(),
+            duration,
+        }
+    }
+
+    fn tracker() -> LineTracker {
+        let mut tracker = LineTracker::new();
+        tracker.set_lyrics(vec![
+            line(10.0, "second", Some(5.0)),
+            line(5.0, "first", Some(5.0)),
+            line(15.0, "third", None),
+        ]);
+        tracker
+    }
+
+    #[test]
+    fn emits_only_when_line_changes() {
+        let mut tracker = tracker();
+
+        assert!(tracker.update(1.0).is_none()); // before the first line
+        let change = tracker.update(5.5).expect("entered first line");
+        assert_eq!(change.index, Some(0));
+        assert_eq!(change.line.unwrap().text, "first");
+        assert_eq!(change.next_line.unwrap().text, "second");
+
+        assert!(tracker.update(7.0).is_none());
+        assert_eq!(tracker.update(10.0).unwrap().index, Some(1));
+    }
+
+    #[test]
+    fn seeking_backwards_before_first_line_reports_no_line() {
+        let mut tracker = tracker();
+        tracker.update(12.0);
+
+        let change = tracker.update(2.0).expect("left the lyrics");
+        assert_eq!(change.index, None);
+        assert!(change.line.is_none());
+        assert_eq!(change.next_line.unwrap().text, "first");
+    }
+
+    #[test]
+    fn progress_uses_duration_or_next_line() {
+        let mut tracker = tracker();
+        tracker.update(7.5);
+        assert!((tracker.current().unwrap().progress - 0.5).abs() < 1e-9);
+
+        tracker.update(20.0);
+        let last = tracker.current().unwrap();
+        assert_eq!(last.index, Some(2));
+        assert_eq!(last.progress, 0.0); // no duration and no next line
+    }
+
+    #[test]
+    fn progress_is_throttled_and_needs_a_line() {
+        let start = Instant::now();
+        let mut tracker = tracker();
+        tracker.update(2.0);
+        assert!(tracker.progress(start).is_none()); // before the first line
+
+        tracker.update(6.0);
+        let progress = tracker.progress(start).expect("inside the first line");
+        assert_eq!(progress.index, 0);
+        assert!((progress.progress - 0.2).abs() < 1e-9);
+
+        tracker.update(6.1);
+        assert!(tracker
+            .progress(start + Duration::from_millis(100))
+            .is_none());
+        tracker.update(7.0);
+        let progress = tracker.progress(start + PROGRESS_INTERVAL).unwrap();
+        assert!((progress.progress - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn offset_shifts_the_active_line() {
+        let mut tracker = tracker();
+        assert_eq!(tracker.update(9.0).unwrap().index, Some(0));
+
+        let change = tracker
+            .set_offset(1.5)
+            .expect("offset moves into the next line");
+        assert_eq!(change.index, Some(1));
+        assert_eq!(change.current_time, 9.0);
+        assert!((change.progress - 0.1).abs() < 1e-9);
+
+        assert!(tracker.update(9.2).is_none());
+        assert_eq!(tracker.set_offset(0.0).unwrap().index, Some(0));
+    }
+
+    #[test]
+    fn setting_lyrics_mid_song_reports_current_line() {
+        let mut tracker = LineTracker::new();
+        assert!(tracker.update(11.0).is_none());
+
+        let change = tracker
+            .set_lyrics(vec![line(5.0, "first", None), line(10.0, "second", None)])
+            .expect("line at last known position");
+        assert_eq!(change.index, Some(1));
+    }
+}
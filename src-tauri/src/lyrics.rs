@@ -1,9 +1,10 @@
 use crate::track_cleaning::{clean_track_name, remove_artist_from_track};
-use crate::types::LyricLine;
+use crate::types::{LyricLine, SettingsState};
 use anyhow::{anyhow, Result};
 use log::{debug, info, warn};
 use reqwest::Client;
 use std::time::Duration;
+use tauri::State;
 use tokio::{task::JoinSet, time::timeout};
 use tokio_util::sync::CancellationToken;
 
@@ -96,12 +97,15 @@ pub async fn fetch_lyrics(
     track_name: String,
     artist_name: String,
     _request_id: Option<String>, // Keep for compatibility but not needed with this approach
+    settings_state: State<'_, SettingsState>,
 ) -> Result<Vec<LyricLine>, String> {
     info!("Fetching lyrics for: {} by {}", track_name, artist_name);
+    let settings = settings_state.lock().await.clone();
 
-    let cleaned_track = clean_track_name(&track_name).await;
+    let cleaned_track = clean_track_name(&track_name, &settings.track_cleaning).await;
     let track_without_artist = remove_artist_from_track(&track_name, &artist_name);
-    let cleaned_track_without_artist = clean_track_name(&track_without_artist).await;
+    let cleaned_track_without_artist =
+        clean_track_name(&track_without_artist, &settings.track_cleaning).await;
 
     // Build candidates with priority (higher = more likely to succeed)
     let mut candidates = Vec::new();
@@ -222,8 +226,8 @@ pub async fn fetch_lyrics(
 
     match search_strict_priority(
         candidates,
-        Duration::from_secs(10), // per-request timeout
-        Duration::from_secs(30), // overall timeout
+        settings.lyrics.request_timeout(),
+        settings.lyrics.search_timeout(),
         |lyrics: &[LyricLine]| !lyrics.is_empty(),
     ).await {
         Ok((winner, lyrics)) => {
@@ -322,8 +326,10 @@ where
 pub async fn fetch_lrclib_raw(
     track_name: String,
     artist_name: String,
+    settings_state: State<'_, SettingsState>,
 ) -> Result<serde_json::Value, String> {
     info!("Fetching raw LRCLib for: {} by {}", track_name, artist_name);
+    let request_timeout = settings_state.lock().await.lyrics.request_timeout();
 
     let client = Client::builder().build().map_err(|e| e.to_string())?;
     let mut candidates: Vec<serde_json::Value> = Vec::new();
@@ -343,7 +349,7 @@ pub async fn fetch_lrclib_raw(
     if let Ok(resp) = client
         .get(&url)
         .header("User-Agent", "Lyryc/0.1.0")
-        .timeout(Duration::from_secs(10))
+        .timeout(request_timeout)
         .send()
         .await
     {
@@ -363,7 +369,7 @@ pub async fn fetch_lrclib_raw(
         if let Ok(resp) = client
             .get(&url)
             .header("User-Agent", "Lyryc/0.1.0")
-            .timeout(Duration::from_secs(10))
+            .timeout(request_timeout)
             .send()
             .await
         {
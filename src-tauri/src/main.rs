@@ -2,5 +2,11 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    // Browsers launch the same binary as a native messaging host
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = lyryc_lib::run_from_args(&args) {
+        std::process::exit(code);
+    }
+
     lyryc_lib::run()
 }
@@ -0,0 +1,620 @@
+//! Native players on Linux, read over MPRIS (D-Bus).
+//!
+//! Every player on the session bus that is not a browser is polled once a second
+//! and reported to the track pipeline as if it were one more extension client, so
+//! source arbitration treats Spotify desktop, mpv or Rhythmbox like a browser tab.
+//! Browsers are skipped because the extension already reports them.
+
+use crate::protocol::{now_millis, InboundMessage, PlaybackCommand, PlaybackState, TrackUpdate};
+use crate::track_pipeline::PipelineEvent;
+use crate::types::{MprisState, TrackPipeline};
+use crate::websocket::CommandOutcome;
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Manager;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use zbus::fdo::DBusProxy;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{proxy, Connection};
+
+/// Client ID native players are reported under in the source registry
+pub const CLIENT_ID: &str = "mpris";
+
+const BUS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Players the browser extension already covers
+const BROWSER_PLAYERS: &[&str] = &[
+    "chromium",
+    "chrome",
+    "firefox",
+    "brave",
+    "vivaldi",
+    "opera",
+    "edge",
+    "plasma-browser-integration",
+];
+
+#[proxy(
+    interface = "org.mpris.MediaPlayer2.Player",
+    default_path = "/org/mpris/MediaPlayer2"
+)]
+trait Player {
+    fn play(&self) -> zbus::Result<()>;
+    fn pause(&self) -> zbus::Result<()>;
+    fn play_pause(&self) -> zbus::Result<()>;
+    fn next(&self) -> zbus::Result<()>;
+    fn previous(&self) -> zbus::Result<()>;
+    fn seek(&self, offset: i64) -> zbus::Result<()>;
+    fn set_position(&self, track_id: &ObjectPath<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn metadata(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+    #[zbus(property)]
+    fn position(&self) -> zbus::Result<i64>;
+    #[zbus(property)]
+    fn volume(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn set_volume(&self, volume: f64) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn shuffle(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn set_shuffle(&self, shuffle: bool) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn loop_status(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn set_loop_status(&self, status: &str) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn can_play(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_pause(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_seek(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_go_next(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_go_previous(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_control(&self) -> zbus::Result<bool>;
+}
+
+/// Polls MPRIS players on one bus connection and controls them
+pub struct MprisWatcher {
+    connection: Connection,
+    /// Last update reported per player, keyed by source name
+    players: Mutex<HashMap<String, TrackUpdate>>,
+}
+
+impl MprisWatcher {
+    /// Takes any bus connection so tests can point it at a private bus
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            players: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn player_names(&self) -> zbus::Result<Vec<String>> {
+        let names = DBusProxy::new(&self.connection).await?.list_names().await?;
+        Ok(names
+            .into_iter()
+            .map(|name| name.to_string())
+            .filter(|name| name.starts_with(BUS_PREFIX) && !is_browser(source_name(name)))
+            .collect())
+    }
+
+    async fn player(&self, source: &str) -> zbus::Result<PlayerProxy<'static>> {
+        // Position is never signalled, so cached properties would go stale
+        PlayerProxy::builder(&self.connection)
+            .destination(format!("{}{}", BUS_PREFIX, source))?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+    }
+
+    async fn read_player(&self, source: &str) -> zbus::Result<Option<TrackUpdate>> {
+        let player = self.player(source).await?;
+        let status = player.playback_status().await?;
+        if status == "Stopped" {
+            return Ok(None);
+        }
+        let metadata = player.metadata().await?;
+        // Not every player implements Position
+        let position = player.position().await.ok();
+        Ok(track_update_from(source, &status, &metadata, position))
+    }
+
+    /// Read every player once and return the messages a browser tab would have sent
+    pub async fn poll(&self) -> Vec<InboundMessage> {
+        let names = match self.player_names().await {
+            Ok(names) => names,
+            Err(e) => {
+                debug!("Failed to list MPRIS players: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut players = self.players.lock().await;
+        let mut messages = Vec::new();
+        let mut seen = HashSet::new();
+
+        for bus_name in names {
+            let source = source_name(&bus_name).to_string();
+            let update = match self.read_player(&source).await {
+                Ok(Some(update)) => update,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("Failed to read MPRIS player {}: {}", source, e);
+                    continue;
+                }
+            };
+            seen.insert(source.clone());
+
+            let message = match players.get(&source) {
+                None => Some(InboundMessage::TrackDetected(update.clone())),
+                Some(_) if update.is_playing => Some(InboundMessage::TrackProgress(update.clone())),
+                // Paused players are only reported when something changed
+                Some(previous)
+                    if previous.is_playing
+                        || previous.title != update.title
+                        || previous.artist != update.artist =>
+                {
+                    Some(InboundMessage::TrackPaused(update.clone()))
+                }
+                Some(_) => None,
+            };
+            messages.extend(message);
+            players.insert(source, update);
+        }
+
+        // Players that quit or stopped
+        let gone: Vec<String> = players
+            .keys()
+            .filter(|source| !seen.contains(*source))
+            .cloned()
+            .collect();
+        for source in gone {
+            if let Some(update) = players.remove(&source) {
+                messages.push(InboundMessage::TrackStopped(update));
+            }
+        }
+
+        messages
+    }
+
+    /// Run a playback command on a player and report its resulting state
+    pub async fn execute(
+        &self,
+        source: &str,
+        command: &PlaybackCommand,
+    ) -> Result<PlaybackState, String> {
+        let player = self
+            .player(source)
+            .await
+            .map_err(|e| format!("Player {} is not available: {}", source, e))?;
+
+        let result = match *command {
+            PlaybackCommand::Play => {
+                require(player.can_play().await, source, command)?;
+                player.play().await
+            }
+            PlaybackCommand::Pause => {
+                require(player.can_pause().await, source, command)?;
+                player.pause().await
+            }
+            PlaybackCommand::Toggle => {
+                require(player.can_pause().await, source, command)?;
+                player.play_pause().await
+            }
+            PlaybackCommand::Next => {
+                require(player.can_go_next().await, source, command)?;
+                player.next().await
+            }
+            PlaybackCommand::Previous => {
+                require(player.can_go_previous().await, source, command)?;
+                player.previous().await
+            }
+            PlaybackCommand::Seek { seek_time } => {
+                require(player.can_seek().await, source, command)?;
+                // SetPosition is ignored unless it names the current track
+                let metadata = player.metadata().await.map_err(|e| e.to_string())?;
+                let track_id = metadata
+                    .get("mpris:trackid")
+                    .and_then(|value| value_string(value))
+                    .ok_or_else(|| format!("{} did not report a track id", source))?;
+                let track_id =
+                    ObjectPath::try_from(track_id.as_str()).map_err(|e| e.to_string())?;
+                player
+                    .set_position(&track_id, seconds_to_micros(seek_time))
+                    .await
+            }
+            PlaybackCommand::SeekRelative { offset } => {
+                require(player.can_seek().await, source, command)?;
+                player.seek(seconds_to_micros(offset)).await
+            }
+            PlaybackCommand::Volume { level } => {
+                require(player.can_control().await, source, command)?;
+                player.set_volume(level).await
+            }
+            PlaybackCommand::Shuffle => {
+                require(player.can_control().await, source, command)?;
+                match player.shuffle().await {
+                    Ok(shuffle) => player.set_shuffle(!shuffle).await,
+                    Err(e) => Err(e),
+                }
+            }
+            PlaybackCommand::Repeat => {
+                require(player.can_control().await, source, command)?;
+                match player.loop_status().await {
+                    Ok(status) => player.set_loop_status(next_loop_status(&status)).await,
+                    Err(e) => Err(e),
+                }
+            }
+            PlaybackCommand::Like => {
+                return Err(format!("{} does not support 'like'", source));
+            }
+        };
+        result.map_err(|e| format!("'{}' failed on {}: {}", command.name(), source, e))?;
+
+        let metadata = player.metadata().await.unwrap_or_default();
+        Ok(PlaybackState {
+            is_playing: player
+                .playback_status()
+                .await
+                .map(|status| status == "Playing")
+                .unwrap_or(false),
+            current_time: player.position().await.ok().map(micros_to_seconds),
+            duration: metadata
+                .get("mpris:length")
+                .and_then(|value| value_micros(value)),
+        })
+    }
+
+    /// Poll forever, feeding changes into the track pipeline
+    pub async fn run(self: Arc<Self>, pipeline: TrackPipeline) {
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            ticker.tick().await;
+            for message in self.poll().await {
+                let event = PipelineEvent::Track(CLIENT_ID.to_string(), message);
+                if pipeline.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn require(
+    allowed: zbus::Result<bool>,
+    source: &str,
+    command: &PlaybackCommand,
+) -> Result<(), String> {
+    match allowed {
+        Ok(true) => Ok(()),
+        _ => Err(format!("{} does not support '{}'", source, command.name())),
+    }
+}
+
+fn source_name(bus_name: &str) -> &str {
+    bus_name.strip_prefix(BUS_PREFIX).unwrap_or(bus_name)
+}
+
+fn is_browser(source: &str) -> bool {
+    let player = source.split('.').next().unwrap_or(source).to_lowercase();
+    BROWSER_PLAYERS
+        .iter()
+        .any(|browser| player.starts_with(browser))
+}
+
+fn next_loop_status(status: &str) -> &'static str {
+    match status {
+        "None" => "Playlist",
+        "Playlist" => "Track",
+        _ => "None",
+    }
+}
+
+fn seconds_to_micros(seconds: f64) -> i64 {
+    (seconds * 1_000_000.0) as i64
+}
+
+fn micros_to_seconds(micros: i64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
+
+fn value_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.to_string()),
+        Value::ObjectPath(path) => Some(path.to_string()),
+        Value::Value(inner) => value_string(inner),
+        _ => None,
+    }
+}
+
+fn value_strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(array) => array.iter().filter_map(value_string).collect(),
+        Value::Value(inner) => value_strings(inner),
+        other => value_string(other).into_iter().collect(),
+    }
+}
+
+/// Lengths are specified as i64 but some players send unsigned integers
+fn value_micros(value: &Value) -> Option<f64> {
+    match value {
+        Value::I64(n) => Some(micros_to_seconds(*n)),
+        Value::U64(n) => Some(*n as f64 / 1_000_000.0),
+        Value::I32(n) => Some(micros_to_seconds(i64::from(*n))),
+        Value::U32(n) => Some(micros_to_seconds(i64::from(*n))),
+        Value::Value(inner) => value_micros(inner),
+        _ => None,
+    }
+}
+
+/// Translate MPRIS metadata into the update an extension would send; None when nothing is loaded
+fn track_update_from(
+    source: &str,
+    status: &str,
+    metadata: &HashMap<String, OwnedValue>,
+    position: Option<i64>,
+) -> Option<TrackUpdate> {
+    let title = metadata
+        .get("xesam:title")
+        .and_then(|value| value_string(value))
+        .filter(|title| !title.trim().is_empty())?;
+    let artist = metadata
+        .get("xesam:artist")
+        .map(|value| value_strings(value).join(", "))
+        .unwrap_or_default();
+
+    Some(TrackUpdate {
+        title,
+        artist,
+        thumbnail: metadata
+            .get("mpris:artUrl")
+            .and_then(|value| value_string(value)),
+        source: source.to_string(),
+        url: metadata
+            .get("xesam:url")
+            .and_then(|value| value_string(value))
+            .unwrap_or_default(),
+        timestamp: now_millis(),
+        is_playing: status == "Playing",
+        current_time: position.map(micros_to_seconds),
+        duration: metadata
+            .get("mpris:length")
+            .and_then(|value| value_micros(value)),
+    })
+}
+
+/// Connect to the session bus and start polling; logs and gives up when there is no bus
+pub fn start(app_handle: tauri::AppHandle, pipeline: TrackPipeline) {
+    tauri::async_runtime::spawn(async move {
+        let connection = match Connection::session().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(
+                    "MPRIS unavailable, native players will not be tracked: {}",
+                    e
+                );
+                return;
+            }
+        };
+
+        let watcher = Arc::new(MprisWatcher::new(connection));
+        *app_handle.state::<MprisState>().lock().await = Some(watcher.clone());
+        info!("Watching MPRIS players on the session bus");
+        watcher.run(pipeline).await;
+    });
+}
+
+pub async fn execute_command(
+    app_handle: &tauri::AppHandle,
+    source: Option<String>,
+    command: PlaybackCommand,
+) -> Result<CommandOutcome, String> {
+    command.validate()?;
+    let watcher = app_handle
+        .state::<MprisState>()
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| "MPRIS is not available".to_string())?;
+    let source = source.ok_or_else(|| "No native player selected".to_string())?;
+
+    info!("Sending '{}' to MPRIS player {}", command.name(), source);
+    let state = watcher.execute(&source, &command).await?;
+    Ok(CommandOutcome {
+        command_id: Uuid::new_v4().to_string(),
+        client_id: CLIENT_ID.to_string(),
+        acknowledged: true,
+        state: Some(state),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+
+    struct FakePlayer {
+        status: String,
+        position: i64,
+    }
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl FakePlayer {
+        fn play(&mut self) {
+            self.status = "Playing".to_string();
+        }
+        fn pause(&mut self) {
+            self.status = "Paused".to_string();
+        }
+        fn play_pause(&mut self) {
+            self.status = if self.status == "Playing" {
+                "Paused"
+            } else {
+                "Playing"
+            }
+            .to_string();
+        }
+        fn seek(&mut self, offset: i64) {
+            self.position += offset;
+        }
+        fn set_position(&mut self, _track_id: ObjectPath<'_>, position: i64) {
+            self.position = position;
+        }
+
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            self.status.clone()
+        }
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            let mut metadata = HashMap::new();
+            let mut insert = |key: &str, value: Value<'_>| {
+                metadata.insert(key.to_string(), value.try_to_owned().unwrap());
+            };
+            insert(
+                "mpris:trackid",
+                Value::from(ObjectPath::try_from("/org/fake/track/1").unwrap()),
+            );
+            insert("xesam:title", Value::from("Song"));
+            insert("xesam:artist", Value::from(vec!["Artist"]));
+            insert("mpris:length", Value::from(200_000_000i64));
+            metadata
+        }
+        #[zbus(property)]
+        fn position(&self) -> i64 {
+            self.position
+        }
+        #[zbus(property)]
+        fn can_play(&self) -> bool {
+            true
+        }
+        #[zbus(property)]
+        fn can_pause(&self) -> bool {
+            true
+        }
+        #[zbus(property)]
+        fn can_seek(&self) -> bool {
+            true
+        }
+    }
+
+    /// Private session bus, killed when dropped
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+        }
+    }
+
+    fn private_bus() -> Option<PrivateBus> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some(PrivateBus {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn reports_and_controls_a_player_on_a_private_bus() {
+        let bus = match private_bus() {
+            Some(bus) => bus,
+            None => {
+                eprintln!("dbus-daemon not available, skipping");
+                return;
+            }
+        };
+
+        let player_connection = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.mpris.MediaPlayer2.fakeplayer")
+            .unwrap()
+            .serve_at(
+                "/org/mpris/MediaPlayer2",
+                FakePlayer {
+                    status: "Playing".to_string(),
+                    position: 12_500_000,
+                },
+            )
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let watcher_connection = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let watcher = MprisWatcher::new(watcher_connection);
+
+        match watcher.poll().await.as_slice() {
+            [InboundMessage::TrackDetected(update)] => {
+                assert_eq!(update.source, "fakeplayer");
+                assert_eq!(update.title, "Song");
+                assert_eq!(update.artist, "Artist");
+                assert_eq!(update.current_time, Some(12.5));
+                assert_eq!(update.duration, Some(200.0));
+            }
+            other => panic!("unexpected messages: {:?}", other),
+        }
+
+        let state = watcher
+            .execute("fakeplayer", &PlaybackCommand::Pause)
+            .await
+            .unwrap();
+        assert!(!state.is_playing);
+        assert!(matches!(
+            watcher.poll().await.as_slice(),
+            [InboundMessage::TrackPaused(_)]
+        ));
+
+        let state = watcher
+            .execute("fakeplayer", &PlaybackCommand::Seek { seek_time: 30.0 })
+            .await
+            .unwrap();
+        assert_eq!(state.current_time, Some(30.0));
+        assert!(watcher
+            .execute("fakeplayer", &PlaybackCommand::Like)
+            .await
+            .is_err());
+
+        player_connection
+            .release_name("org.mpris.MediaPlayer2.fakeplayer")
+            .await
+            .unwrap();
+        assert!(matches!(
+            watcher.poll().await.as_slice(),
+            [InboundMessage::TrackStopped(_)]
+        ));
+    }
+
+    #[test]
+    fn skips_browser_players() {
+        assert!(is_browser("chromium.instance1234"));
+        assert!(is_browser("firefox.instance_1_2"));
+        assert!(!is_browser("spotify"));
+        assert!(!is_browser("mpv"));
+    }
+}
@@ -0,0 +1,680 @@
+//! Native messaging host mode.
+//!
+//! Browsers launch the `lyryc` binary with the extension origin as its first argument
+//! and exchange length-prefixed JSON over stdin/stdout. The host relays those frames to
+//! the running app over a local socket, speaking the same WebSocket protocol the
+//! extension uses on `ws://localhost`, so users whose loopback ports are blocked can
+//! still connect.
+
+use crate::discovery;
+use crate::types::AuthState;
+use crate::websocket::WebSocketServer;
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use serde_json::json;
+use std::io;
+use std::path::{Path, PathBuf};
+use tauri::State;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::{client_async, tungstenite::Message};
+
+/// Name the extension passes to `chrome.runtime.connectNative`
+pub const HOST_NAME: &str = "com.lyryc.host";
+
+/// Browsers refuse host messages larger than 1 MB
+const MAX_MESSAGE_TO_BROWSER: usize = 1024 * 1024;
+/// Upper bound on extension messages, which are never more than a track update
+const MAX_MESSAGE_FROM_BROWSER: usize = 4 * 1024 * 1024;
+
+/// How the binary was asked to run, when it is not starting the app
+#[derive(Debug, PartialEq)]
+enum Invocation {
+    /// Launched by a browser; the origin is forwarded to the app's origin check
+    Host { origin: Option<String> },
+    /// `lyryc --install-native-host <extension-id>...`
+    Install { extension_ids: Vec<String> },
+}
+
+fn parse_invocation(args: &[String]) -> Option<Invocation> {
+    let first = args.first()?;
+    if first == "--install-native-host" {
+        return Some(Invocation::Install {
+            extension_ids: args[1..].to_vec(),
+        });
+    }
+    if first == "--native-messaging" {
+        return Some(Invocation::Host { origin: None });
+    }
+    // Chromium passes the caller's origin, Firefox the manifest path and extension ID
+    if first.starts_with("chrome-extension://") {
+        return Some(Invocation::Host {
+            origin: Some(first.clone()),
+        });
+    }
+    if first.ends_with(".json") {
+        if let Some(extension_id) = args.get(1) {
+            return Some(Invocation::Host {
+                origin: Some(format!("moz-extension://{}", extension_id)),
+            });
+        }
+    }
+    None
+}
+
+/// Run the non-GUI modes of the binary; returns the exit code, or `None` to start the app
+pub fn run_from_args(args: &[String]) -> Option<i32> {
+    let invocation = parse_invocation(args)?;
+
+    // stdout belongs to the browser in host mode, so logs only go to stderr
+    let _ = env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Info)
+        .target(env_logger::Target::Stderr)
+        .try_init();
+
+    Some(match invocation {
+        Invocation::Host { origin } => run_host(origin),
+        Invocation::Install { extension_ids } => match install_host_manifests(&extension_ids) {
+            Ok(paths) => {
+                for path in paths {
+                    println!("Installed {}", path.display());
+                }
+                0
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        },
+    })
+}
+
+fn run_host(origin: Option<String>) -> i32 {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("Failed to start native messaging host: {}", e);
+            return 1;
+        }
+    };
+
+    match runtime.block_on(bridge_stdio(origin)) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("Native messaging host stopped: {}", e);
+            1
+        }
+    }
+}
+
+async fn bridge_stdio(origin: Option<String>) -> Result<(), String> {
+    #[cfg(unix)]
+    let stream = {
+        let path = discovery::bridge_endpoint();
+        tokio::net::UnixStream::connect(&path)
+            .await
+            .map_err(|e| format!("Lyryc is not running ({}: {})", path.display(), e))?
+    };
+    #[cfg(windows)]
+    let stream = {
+        let name = discovery::bridge_endpoint();
+        tokio::net::windows::named_pipe::ClientOptions::new()
+            .open(&name)
+            .map_err(|e| format!("Lyryc is not running ({}: {})", name, e))?
+    };
+
+    bridge(stream, origin, tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+/// Pump frames between the browser and the app until either side hangs up
+async fn bridge<S, R, W>(
+    stream: S,
+    origin: Option<String>,
+    mut browser_in: R,
+    mut browser_out: W,
+) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut request = "ws://localhost/"
+        .into_client_request()
+        .map_err(|e| e.to_string())?;
+    if let Some(origin) = origin {
+        let value = HeaderValue::from_str(&origin).map_err(|e| e.to_string())?;
+        request.headers_mut().insert("origin", value);
+    }
+
+    let (ws_stream, _) = client_async(request, stream)
+        .await
+        .map_err(|e| format!("Bridge handshake failed: {}", e))?;
+    info!("Native messaging host connected to Lyryc");
+    let (mut app_sender, mut app_receiver) = ws_stream.split();
+
+    let to_app = async {
+        while let Some(text) = read_frame(&mut browser_in)
+            .await
+            .map_err(|e| format!("Failed to read from browser: {}", e))?
+        {
+            app_sender
+                .send(Message::Text(text))
+                .await
+                .map_err(|e| format!("Failed to forward to Lyryc: {}", e))?;
+        }
+        // The browser closed the port
+        let _ = app_sender.close().await;
+        Ok(())
+    };
+
+    let to_browser = async {
+        while let Some(message) = app_receiver.next().await {
+            let text = match message.map_err(|e| format!("Bridge connection lost: {}", e))? {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            match write_frame(&mut browser_out, &text).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                    warn!("Dropping message for the browser: {}", e)
+                }
+                Err(e) => return Err(format!("Failed to write to browser: {}", e)),
+            }
+        }
+        Ok(())
+    };
+
+    tokio::select! {
+        result = to_app => result,
+        result = to_browser => result,
+    }
+}
+
+/// Read one native-endian length-prefixed message; `None` once the browser hangs up
+async fn read_frame<R>(reader: &mut R) -> io::Result<Option<String>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_MESSAGE_FROM_BROWSER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds the limit", length),
+        ));
+    }
+
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload).await?;
+    String::from_utf8(payload)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_frame<W>(writer: &mut W, message: &str) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if message.len() > MAX_MESSAGE_TO_BROWSER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("message of {} bytes exceeds the limit", message.len()),
+        ));
+    }
+    writer
+        .write_all(&(message.len() as u32).to_ne_bytes())
+        .await?;
+    writer.write_all(message.as_bytes()).await?;
+    writer.flush().await
+}
+
+/// App side of the bridge: a socket in the per-user runtime directory
+#[cfg(unix)]
+pub struct BridgeListener {
+    listener: tokio::net::UnixListener,
+    path: PathBuf,
+    /// Device and inode of the socket this listener created
+    file_id: Option<(u64, u64)>,
+}
+
+#[cfg(unix)]
+impl BridgeListener {
+    pub async fn bind() -> Result<Self, String> {
+        discovery::ensure_runtime_dir()?;
+        let path = discovery::bridge_endpoint();
+        if path.exists() {
+            if tokio::net::UnixStream::connect(&path).await.is_ok() {
+                return Err(format!("{} is in use by another instance", path.display()));
+            }
+            // Left behind by an instance that did not shut down cleanly
+            let _ = std::fs::remove_file(&path);
+        }
+
+        let listener = tokio::net::UnixListener::bind(&path)
+            .map_err(|e| format!("Failed to bind {}: {}", path.display(), e))?;
+        info!("Native messaging bridge listening on: {}", path.display());
+        let file_id = socket_file_id(&path);
+        Ok(Self {
+            listener,
+            path,
+            file_id,
+        })
+    }
+
+    pub async fn serve(self, server: &WebSocketServer) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    server.accept_stream(stream, "native messaging host".to_string())
+                }
+                Err(e) => {
+                    error!("Native messaging bridge stopped: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for BridgeListener {
+    fn drop(&mut self) {
+        // Another listener may have replaced the socket since; leave that one alone
+        if self.file_id.is_some() && socket_file_id(&self.path) == self.file_id {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn socket_file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::symlink_metadata(path)
+        .ok()
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+/// App side of the bridge: a named pipe scoped to the current user
+#[cfg(windows)]
+pub struct BridgeListener {
+    name: String,
+    next: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+#[cfg(windows)]
+impl BridgeListener {
+    pub async fn bind() -> Result<Self, String> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let name = discovery::bridge_endpoint();
+        let next = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&name)
+            .map_err(|e| format!("Failed to create {}: {}", name, e))?;
+        info!("Native messaging bridge listening on: {}", name);
+        Ok(Self { name, next })
+    }
+
+    pub async fn serve(mut self, server: &WebSocketServer) {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        loop {
+            if let Err(e) = self.next.connect().await {
+                error!("Native messaging bridge stopped: {}", e);
+                break;
+            }
+            // Open the next instance before handing this one off so hosts never miss the pipe
+            let next = match ServerOptions::new().create(&self.name) {
+                Ok(next) => next,
+                Err(e) => {
+                    error!("Native messaging bridge stopped: {}", e);
+                    break;
+                }
+            };
+            let connected = std::mem::replace(&mut self.next, next);
+            server.accept_stream(connected, "native messaging host".to_string());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Browser {
+    Chrome,
+    Chromium,
+    Brave,
+    Edge,
+    Firefox,
+}
+
+impl Browser {
+    const ALL: [Browser; 5] = [
+        Browser::Chrome,
+        Browser::Chromium,
+        Browser::Brave,
+        Browser::Edge,
+        Browser::Firefox,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Browser::Chrome => "chrome",
+            Browser::Chromium => "chromium",
+            Browser::Brave => "brave",
+            Browser::Edge => "edge",
+            Browser::Firefox => "firefox",
+        }
+    }
+
+    /// Directory the browser looks for host manifests in
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn manifest_dir(self, home: &Path) -> PathBuf {
+        match self {
+            Browser::Chrome => home.join(".config/google-chrome/NativeMessagingHosts"),
+            Browser::Chromium => home.join(".config/chromium/NativeMessagingHosts"),
+            Browser::Brave => home.join(".config/BraveSoftware/Brave-Browser/NativeMessagingHosts"),
+            Browser::Edge => home.join(".config/microsoft-edge/NativeMessagingHosts"),
+            Browser::Firefox => home.join(".mozilla/native-messaging-hosts"),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn manifest_dir(self, home: &Path) -> PathBuf {
+        let support = home.join("Library/Application Support");
+        match self {
+            Browser::Chrome => support.join("Google/Chrome/NativeMessagingHosts"),
+            Browser::Chromium => support.join("Chromium/NativeMessagingHosts"),
+            Browser::Brave => support.join("BraveSoftware/Brave-Browser/NativeMessagingHosts"),
+            Browser::Edge => support.join("Microsoft Edge/NativeMessagingHosts"),
+            Browser::Firefox => support.join("Mozilla/NativeMessagingHosts"),
+        }
+    }
+
+    /// Windows browsers find manifests through the registry, so they can live anywhere
+    #[cfg(windows)]
+    fn manifest_dir(self, _home: &Path) -> PathBuf {
+        let base = std::env::var_os("LOCALAPPDATA")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        base.join("Lyryc")
+            .join("NativeMessagingHosts")
+            .join(self.name())
+    }
+
+    #[cfg(windows)]
+    fn registry_key(self) -> String {
+        let vendor = match self {
+            Browser::Chrome => r"Google\Chrome",
+            Browser::Chromium => "Chromium",
+            Browser::Brave => r"BraveSoftware\Brave-Browser",
+            Browser::Edge => r"Microsoft\Edge",
+            Browser::Firefox => "Mozilla",
+        };
+        format!(
+            r"HKCU\Software\{}\NativeMessagingHosts\{}",
+            vendor, HOST_NAME
+        )
+    }
+
+    /// Whether the browser looks installed; manifests are only written for those
+    fn is_present(self, home: &Path) -> bool {
+        if cfg!(windows) {
+            return true;
+        }
+        self.manifest_dir(home)
+            .parent()
+            .is_some_and(|profile| profile.exists())
+    }
+}
+
+/// Chromium extension IDs are 32 characters from `a` to `p`
+fn is_chromium_id(extension_id: &str) -> bool {
+    extension_id.len() == 32 && extension_id.bytes().all(|b| (b'a'..=b'p').contains(&b))
+}
+
+/// Host manifest for one browser, or `None` when no given ID belongs to that browser family
+fn host_manifest(
+    browser: Browser,
+    executable: &Path,
+    extension_ids: &[String],
+) -> Option<serde_json::Value> {
+    let mut manifest = json!({
+        "name": HOST_NAME,
+        "description": "Lyryc desktop app",
+        "path": executable,
+        "type": "stdio",
+    });
+
+    if matches!(browser, Browser::Firefox) {
+        let ids: Vec<&String> = extension_ids
+            .iter()
+            .filter(|id| !is_chromium_id(id))
+            .collect();
+        if ids.is_empty() {
+            return None;
+        }
+        manifest["allowed_extensions"] = json!(ids);
+    } else {
+        let origins: Vec<String> = extension_ids
+            .iter()
+            .filter(|id| is_chromium_id(id))
+            .map(|id| format!("chrome-extension://{}/", id))
+            .collect();
+        if origins.is_empty() {
+            return None;
+        }
+        manifest["allowed_origins"] = json!(origins);
+    }
+    Some(manifest)
+}
+
+/// Write host manifests for every installed browser; browsers only launch the host for
+/// the extension IDs listed, so at least one is required
+pub fn install_host_manifests(extension_ids: &[String]) -> Result<Vec<PathBuf>, String> {
+    if extension_ids.is_empty() {
+        return Err("At least one extension ID is required".to_string());
+    }
+
+    let executable =
+        std::env::current_exe().map_err(|e| format!("Failed to locate executable: {}", e))?;
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .ok_or_else(|| "Could not determine the home directory".to_string())?;
+
+    let mut installed = Vec::new();
+    for browser in Browser::ALL {
+        let Some(manifest) = host_manifest(browser, &executable, extension_ids) else {
+            continue;
+        };
+        if !browser.is_present(&home) {
+            continue;
+        }
+
+        let dir = browser.manifest_dir(&home);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(format!("{}.json", HOST_NAME));
+        let contents = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+        std::fs::write(&path, contents)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+        #[cfg(windows)]
+        register_manifest(browser, &path)?;
+
+        info!(
+            "Installed {} native messaging host: {}",
+            browser.name(),
+            path.display()
+        );
+        installed.push(path);
+    }
+
+    if installed.is_empty() {
+        return Err("No installed browser matches the given extension IDs".to_string());
+    }
+    Ok(installed)
+}
+
+#[cfg(windows)]
+fn register_manifest(browser: Browser, manifest: &Path) -> Result<(), String> {
+    let status = std::process::Command::new("reg")
+        .args(["add", &browser.registry_key(), "/ve", "/t", "REG_SZ", "/d"])
+        .arg(manifest)
+        .arg("/f")
+        .status()
+        .map_err(|e| format!("Failed to run reg: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to register {} host manifest",
+            browser.name()
+        ))
+    }
+}
+
+/// Install host manifests for the extension IDs allowed to pair with the app
+#[tauri::command]
+pub async fn install_native_messaging_host(
+    auth_state: State<'_, AuthState>,
+) -> Result<Vec<String>, String> {
+    let extension_ids = auth_state
+        .read()
+        .map_err(|e| e.to_string())?
+        .allowed_extension_ids
+        .clone();
+    if extension_ids.is_empty() {
+        return Err(
+            "Set the allowed extension IDs first; browsers only launch hosts for listed extensions"
+                .to_string(),
+        );
+    }
+
+    let paths = install_host_manifests(&extension_ids)?;
+    Ok(paths
+        .into_iter()
+        .map(|path| path.display().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn recognizes_browser_launches() {
+        assert_eq!(parse_invocation(&args(&[])), None);
+        assert_eq!(
+            parse_invocation(&args(&["chrome-extension://abc/", "--parent-window=0"])),
+            Some(Invocation::Host {
+                origin: Some("chrome-extension://abc/".to_string())
+            })
+        );
+        assert_eq!(
+            parse_invocation(&args(&[
+                "/home/u/.mozilla/com.lyryc.host.json",
+                "lyryc@example.com"
+            ])),
+            Some(Invocation::Host {
+                origin: Some("moz-extension://lyryc@example.com".to_string())
+            })
+        );
+        assert_eq!(
+            parse_invocation(&args(&["--install-native-host", "abc"])),
+            Some(Invocation::Install {
+                extension_ids: args(&["abc"])
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            write_frame(&mut writer, r#"{"message_type":"ping"}"#)
+                .await
+                .unwrap();
+            write_frame(&mut writer, "").await.unwrap();
+        });
+
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap().as_deref(),
+            Some(r#"{"message_type":"ping"}"#)
+        );
+        assert_eq!(read_frame(&mut reader).await.unwrap().as_deref(), Some(""));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frames() {
+        let oversized = ((MAX_MESSAGE_FROM_BROWSER + 1) as u32).to_ne_bytes();
+        let mut reader: &[u8] = &oversized;
+        assert!(read_frame(&mut reader).await.is_err());
+
+        let mut sink = Vec::new();
+        let message = "x".repeat(MAX_MESSAGE_TO_BROWSER + 1);
+        let err = write_frame(&mut sink, &message).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(sink.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dropping_a_stale_bridge_keeps_the_new_socket() {
+        let path = std::env::temp_dir().join(format!("lyryc-bridge-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let stale = BridgeListener {
+            listener,
+            file_id: socket_file_id(&path),
+            path: path.clone(),
+        };
+
+        // A restart replaced the socket before the old listener went away
+        std::fs::remove_file(&path).unwrap();
+        let current = tokio::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        assert!(path.exists());
+
+        let current = BridgeListener {
+            listener: current,
+            file_id: socket_file_id(&path),
+            path: path.clone(),
+        };
+        drop(current);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn manifests_split_ids_by_browser_family() {
+        let ids = args(&["abcdefghijklmnopabcdefghijklmnop", "lyryc@example.com"]);
+        let exe = Path::new("/usr/bin/lyryc");
+
+        let chrome = host_manifest(Browser::Chrome, exe, &ids).unwrap();
+        assert_eq!(chrome["name"], HOST_NAME);
+        assert_eq!(
+            chrome["allowed_origins"],
+            json!(["chrome-extension://abcdefghijklmnopabcdefghijklmnop/"])
+        );
+        let firefox = host_manifest(Browser::Firefox, exe, &ids).unwrap();
+        assert_eq!(firefox["allowed_extensions"], json!(["lyryc@example.com"]));
+
+        assert!(host_manifest(Browser::Firefox, exe, &ids[..1]).is_none());
+    }
+}
@@ -0,0 +1,41 @@
+//! Browser-source overlay for OBS and other streaming tools.
+//!
+//! A self-contained page served by the HTTP API at `/overlay`. It follows the same
+//! `lyric-line-changed` and `lyric-progress` events the desktop app emits over
+//! `/events`, so no window capture is needed; styling comes from query parameters.
+
+use crate::types::{AuthState, HttpApiState};
+use axum::http::header;
+use axum::response::{Html, IntoResponse};
+use tauri::State;
+
+const OVERLAY_HTML: &str = include_str!("../assets/overlay.html");
+
+pub async fn overlay_page() -> impl IntoResponse {
+    ([(header::CACHE_CONTROL, "no-store")], Html(OVERLAY_HTML))
+}
+
+/// URL to paste into a browser source; the HTTP API must be running. It carries the
+/// read-only overlay token, never the API or pairing token
+#[tauri::command]
+pub async fn get_overlay_url(
+    http_state: State<'_, HttpApiState>,
+    auth_state: State<'_, AuthState>,
+) -> Result<String, String> {
+    let port = http_state
+        .lock()
+        .await
+        .as_ref()
+        .map(|handle| handle.port())
+        .ok_or_else(|| "Start the HTTP API to use the overlay".to_string())?;
+    let token = auth_state
+        .read()
+        .map_err(|e| e.to_string())?
+        .overlay_token
+        .clone();
+    Ok(format!(
+        "http://127.0.0.1:{}/overlay?token={}",
+        port,
+        urlencoding::encode(&token)
+    ))
+}
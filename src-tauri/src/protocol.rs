@@ -0,0 +1,500 @@
+//! Wire protocol spoken between the browser extension and the app.
+//!
+//! Every frame is a JSON object `{ "message_type": ..., "data": ..., "timestamp": ... }`.
+//! After the server greets a new connection with `connected`, the extension must
+//! send `HELLO` with its protocol version, capabilities and the pairing token shown
+//! in the app; the server answers with `WELCOME` or an `ERROR` and only then accepts
+//! track messages. New optional fields can be added without a version bump;
+//! anything that changes the meaning of an existing message must bump
+//! `PROTOCOL_VERSION`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Protocol version implemented by this build
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest extension protocol version still accepted
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Capability advertised in HELLO by extensions that answer commands with COMMAND_RESULT
+pub const CAPABILITY_COMMAND_ACK: &str = "command_ack";
+/// Capability advertised in HELLO by extensions that accept PLAYBACK_COMMAND at all
+pub const CAPABILITY_PLAYBACK_CONTROL: &str = "playback_control";
+
+/// Commands every extension handled before per-source capabilities were reported
+const LEGACY_COMMANDS: &[&str] = &["play", "pause", "next", "previous", "seek"];
+
+/// Message types the server knows how to parse, used to tell unknown types from bad payloads
+const INBOUND_MESSAGE_TYPES: &[&str] = &[
+    "HELLO",
+    "TRACK_DETECTED",
+    "TRACK_PAUSED",
+    "TRACK_STOPPED",
+    "TRACK_PROGRESS",
+    "COMMAND_RESULT",
+    "ping",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    #[serde(flatten)]
+    pub payload: T,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(payload: T) -> Self {
+        Self {
+            payload,
+            timestamp: now_millis(),
+        }
+    }
+}
+
+/// Frame received from the extension
+pub type ExtensionMessage = Envelope<InboundMessage>;
+/// Frame sent to the extension
+pub type AppMessage = Envelope<OutboundMessage>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "message_type", content = "data")]
+pub enum InboundMessage {
+    #[serde(rename = "HELLO")]
+    Hello(Hello),
+    #[serde(rename = "TRACK_DETECTED")]
+    TrackDetected(TrackUpdate),
+    #[serde(rename = "TRACK_PAUSED")]
+    TrackPaused(TrackUpdate),
+    #[serde(rename = "TRACK_STOPPED")]
+    TrackStopped(TrackUpdate),
+    #[serde(rename = "TRACK_PROGRESS")]
+    TrackProgress(TrackUpdate),
+    #[serde(rename = "COMMAND_RESULT")]
+    CommandResult(CommandResult),
+    #[serde(rename = "ping")]
+    Ping {},
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "message_type", content = "data")]
+pub enum OutboundMessage {
+    /// Greeting sent as soon as the socket opens, before the handshake
+    #[serde(rename = "connected")]
+    Connected {
+        client_id: String,
+        status: String,
+        protocol_version: u32,
+    },
+    #[serde(rename = "WELCOME")]
+    Welcome {
+        client_id: String,
+        protocol_version: u32,
+        app_version: String,
+    },
+    #[serde(rename = "PLAYBACK_COMMAND")]
+    PlaybackCommand {
+        /// Echoed back in COMMAND_RESULT
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command_id: Option<String>,
+        /// Player the command is meant for; every music tab when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+        #[serde(flatten)]
+        command: PlaybackCommand,
+    },
+    #[serde(rename = "pong")]
+    Pong {},
+    #[serde(rename = "ERROR")]
+    Error(ProtocolError),
+}
+
+/// Handshake sent by the extension right after connecting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub extension_version: Option<String>,
+    pub browser: Option<String>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Pairing token shown in the app and entered once in the extension popup
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Playback commands each source (e.g. "spotify") can execute
+    #[serde(default)]
+    pub source_capabilities: HashMap<String, Vec<String>>,
+}
+
+/// Player control understood by the extension; `command` is the wire name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum PlaybackCommand {
+    Play,
+    Pause,
+    Toggle,
+    Next,
+    Previous,
+    /// Jump to an absolute position in seconds
+    Seek {
+        #[serde(rename = "seekTime")]
+        seek_time: f64,
+    },
+    /// Move by `offset` seconds from the current position
+    SeekRelative {
+        offset: f64,
+    },
+    /// Player volume between 0.0 and 1.0
+    Volume {
+        level: f64,
+    },
+    Like,
+    Shuffle,
+    Repeat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackUpdate {
+    pub title: String,
+    pub artist: String,
+    pub thumbnail: Option<String>,
+    pub source: String,
+    pub url: String,
+    pub timestamp: u64,
+    pub is_playing: bool,
+    #[serde(rename = "currentTime")]
+    pub current_time: Option<f64>,
+    pub duration: Option<f64>,
+}
+
+/// Acknowledgement of a PLAYBACK_COMMAND carrying a `command_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandResult {
+    pub command_id: String,
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Playback state of the targeted player after the command ran
+    #[serde(default)]
+    pub state: Option<PlaybackState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackState {
+    pub is_playing: bool,
+    #[serde(rename = "currentTime")]
+    pub current_time: Option<f64>,
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    MalformedMessage,
+    UnsupportedMessage,
+    InvalidPayload,
+    UnsupportedVersion,
+    HandshakeRequired,
+    Unauthorized,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub message_type: Option<String>,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            message_type: None,
+        }
+    }
+
+    pub fn for_message(mut self, message_type: impl Into<String>) -> Self {
+        self.message_type = Some(message_type.into());
+        self
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl InboundMessage {
+    pub fn message_type(&self) -> &'static str {
+        match self {
+            InboundMessage::Hello(_) => "HELLO",
+            InboundMessage::TrackDetected(_) => "TRACK_DETECTED",
+            InboundMessage::TrackPaused(_) => "TRACK_PAUSED",
+            InboundMessage::TrackStopped(_) => "TRACK_STOPPED",
+            InboundMessage::TrackProgress(_) => "TRACK_PROGRESS",
+            InboundMessage::CommandResult(_) => "COMMAND_RESULT",
+            InboundMessage::Ping {} => "ping",
+        }
+    }
+}
+
+impl PlaybackCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlaybackCommand::Play => "play",
+            PlaybackCommand::Pause => "pause",
+            PlaybackCommand::Toggle => "toggle",
+            PlaybackCommand::Next => "next",
+            PlaybackCommand::Previous => "previous",
+            PlaybackCommand::Seek { .. } => "seek",
+            PlaybackCommand::SeekRelative { .. } => "seek_relative",
+            PlaybackCommand::Volume { .. } => "volume",
+            PlaybackCommand::Like => "like",
+            PlaybackCommand::Shuffle => "shuffle",
+            PlaybackCommand::Repeat => "repeat",
+        }
+    }
+
+    /// Map the old free-form `command` + `seekTime` pair onto a typed command
+    pub fn from_legacy(command: &str, seek_time: Option<f64>) -> Result<Self, String> {
+        match command {
+            "play" => Ok(PlaybackCommand::Play),
+            "pause" => Ok(PlaybackCommand::Pause),
+            "toggle" => Ok(PlaybackCommand::Toggle),
+            "next" => Ok(PlaybackCommand::Next),
+            "previous" => Ok(PlaybackCommand::Previous),
+            "like" => Ok(PlaybackCommand::Like),
+            "shuffle" => Ok(PlaybackCommand::Shuffle),
+            "repeat" => Ok(PlaybackCommand::Repeat),
+            "seek" => seek_time
+                .map(|seek_time| PlaybackCommand::Seek { seek_time })
+                .ok_or_else(|| "seek requires a seek time".to_string()),
+            other => Err(format!("Unknown playback command '{}'", other)),
+        }
+    }
+
+    /// Reject arguments no player could act on
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            PlaybackCommand::Seek { seek_time } if !seek_time.is_finite() || seek_time < 0.0 => {
+                Err(format!("Invalid seek position {}", seek_time))
+            }
+            PlaybackCommand::SeekRelative { offset } if !offset.is_finite() => {
+                Err(format!("Invalid seek offset {}", offset))
+            }
+            PlaybackCommand::Volume { level } if !(0.0..=1.0).contains(&level) => {
+                Err(format!("Volume {} is outside 0.0-1.0", level))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Hello {
+    /// Check that `command` is something the client, and the addressed source, can execute
+    pub fn supports(&self, source: Option<&str>, command: &PlaybackCommand) -> Result<(), String> {
+        if !self
+            .capabilities
+            .iter()
+            .any(|capability| capability == CAPABILITY_PLAYBACK_CONTROL)
+        {
+            return Err("The extension does not support playback control".to_string());
+        }
+
+        let name = command.name();
+        // Extensions that predate per-source capabilities only know the original commands
+        if self.source_capabilities.is_empty() {
+            return if LEGACY_COMMANDS.contains(&name) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "The extension does not support '{}'; update it",
+                    name
+                ))
+            };
+        }
+
+        let supported = match source {
+            Some(source) => self
+                .source_capabilities
+                .get(source)
+                .ok_or_else(|| format!("The extension does not control '{}'", source))?
+                .iter()
+                .any(|command| command == name),
+            None => self
+                .source_capabilities
+                .values()
+                .flatten()
+                .any(|command| command == name),
+        };
+        if supported {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} does not support '{}'",
+                source.unwrap_or("No connected player"),
+                name
+            ))
+        }
+    }
+
+    /// Check that the extension speaks a protocol version this build understands
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.protocol_version) {
+            Ok(())
+        } else {
+            Err(ProtocolError::new(
+                ErrorCode::UnsupportedVersion,
+                format!(
+                    "Protocol version {} is not supported (expected {}-{})",
+                    self.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                ),
+            )
+            .for_message("HELLO"))
+        }
+    }
+}
+
+/// Parse a text frame, classifying failures so they can be reported back to the sender
+pub fn parse_inbound(text: &str) -> Result<ExtensionMessage, ProtocolError> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| {
+        ProtocolError::new(ErrorCode::MalformedMessage, format!("Invalid JSON: {}", e))
+    })?;
+
+    let message_type = value
+        .get("message_type")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ProtocolError::new(ErrorCode::MalformedMessage, "Missing message_type"))?
+        .to_string();
+
+    if !INBOUND_MESSAGE_TYPES.contains(&message_type.as_str()) {
+        return Err(ProtocolError::new(
+            ErrorCode::UnsupportedMessage,
+            format!("Unknown message type: {}", message_type),
+        )
+        .for_message(message_type));
+    }
+
+    serde_json::from_value(value).map_err(|e| {
+        ProtocolError::new(ErrorCode::InvalidPayload, e.to_string()).for_message(message_type)
+    })
+}
+
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_track_messages_and_legacy_ping() {
+        let text = r#"{"message_type":"TRACK_PROGRESS","data":{"title":"Song","artist":"Artist",
+            "thumbnail":null,"source":"spotify","url":"https://open.spotify.com","timestamp":1,
+            "is_playing":true,"currentTime":12.5,"duration":200},"timestamp":2}"#;
+        let message = parse_inbound(text).unwrap();
+        match message.payload {
+            InboundMessage::TrackProgress(update) => assert_eq!(update.current_time, Some(12.5)),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let ping = parse_inbound(r#"{"message_type":"ping","data":{},"timestamp":3}"#).unwrap();
+        assert_eq!(ping.payload.message_type(), "ping");
+    }
+
+    #[test]
+    fn classifies_parse_failures() {
+        let malformed = parse_inbound("not json").unwrap_err();
+        assert_eq!(malformed.code, ErrorCode::MalformedMessage);
+
+        let unknown = parse_inbound(r#"{"message_type":"SING","data":{}}"#).unwrap_err();
+        assert_eq!(unknown.code, ErrorCode::UnsupportedMessage);
+        assert_eq!(unknown.message_type.as_deref(), Some("SING"));
+
+        let invalid =
+            parse_inbound(r#"{"message_type":"TRACK_DETECTED","data":{"title":1}}"#).unwrap_err();
+        assert_eq!(invalid.code, ErrorCode::InvalidPayload);
+    }
+
+    #[test]
+    fn rejects_unsupported_protocol_versions() {
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION + 1,
+            extension_version: None,
+            browser: None,
+            capabilities: Vec::new(),
+            auth_token: None,
+            source_capabilities: HashMap::new(),
+        };
+        assert_eq!(
+            hello.validate().unwrap_err().code,
+            ErrorCode::UnsupportedVersion
+        );
+    }
+
+    #[test]
+    fn serializes_outbound_messages_in_wire_format() {
+        let message = AppMessage::new(OutboundMessage::PlaybackCommand {
+            command_id: Some("c1".to_string()),
+            source: None,
+            command: PlaybackCommand::Seek { seek_time: 42.0 },
+        });
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["message_type"], "PLAYBACK_COMMAND");
+        assert_eq!(value["data"]["command"], "seek");
+        assert_eq!(value["data"]["seekTime"], 42.0);
+        assert_eq!(value["data"]["command_id"], "c1");
+        assert!(value["data"].get("source").is_none());
+    }
+
+    #[test]
+    fn validates_commands_against_source_capabilities() {
+        let mut hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            extension_version: None,
+            browser: None,
+            capabilities: vec![CAPABILITY_PLAYBACK_CONTROL.to_string()],
+            auth_token: None,
+            source_capabilities: HashMap::new(),
+        };
+
+        // Older extensions only get the original command set
+        assert!(hello.supports(None, &PlaybackCommand::Pause).is_ok());
+        assert!(hello.supports(None, &PlaybackCommand::Like).is_err());
+
+        hello.source_capabilities.insert(
+            "spotify".to_string(),
+            vec!["play".to_string(), "like".to_string()],
+        );
+        assert!(hello
+            .supports(Some("spotify"), &PlaybackCommand::Like)
+            .is_ok());
+        assert!(hello
+            .supports(Some("spotify"), &PlaybackCommand::Shuffle)
+            .is_err());
+        assert!(hello
+            .supports(Some("soundcloud"), &PlaybackCommand::Play)
+            .is_err());
+
+        assert!(PlaybackCommand::Volume { level: 1.5 }.validate().is_err());
+        assert!(PlaybackCommand::from_legacy("seek", None).is_err());
+    }
+
+    #[test]
+    fn parses_command_results() {
+        let text = r#"{"message_type":"COMMAND_RESULT","data":{"command_id":"c1","success":true,
+            "state":{"is_playing":false,"currentTime":42.0,"duration":200}}}"#;
+        match parse_inbound(text).unwrap().payload {
+            InboundMessage::CommandResult(result) => {
+                assert_eq!(result.command_id, "c1");
+                assert!(!result.state.unwrap().is_playing);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+}
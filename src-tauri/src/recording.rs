@@ -0,0 +1,346 @@
+//! Recording of inbound extension traffic and deterministic replay.
+//!
+//! While a recording is active, every parsed `ExtensionMessage` is appended to a JSONL
+//! file together with the receiving client and its offset from the start of the
+//! recording. Replay opens one in-memory connection per recorded client on a
+//! `WebSocketServer` and sends the messages again at their recorded pace (optionally
+//! sped up), so they go through the same handshake, parsing and callbacks as live
+//! traffic.
+
+use crate::protocol::{ExtensionMessage, InboundMessage};
+use crate::types::{AuthState, RecorderState, WebSocketState};
+use crate::websocket::WebSocketServer;
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tauri::{Manager, State};
+use tokio_tungstenite::{client_async, tungstenite::Message};
+
+/// One line of a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Milliseconds since the recording started
+    pub at_ms: u64,
+    pub client_id: String,
+    #[serde(flatten)]
+    pub event: RecordedEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    Connected,
+    Message { message: ExtensionMessage },
+    Disconnected,
+}
+
+pub struct SessionRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    started: Instant,
+}
+
+impl SessionRecorder {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+            started: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one event; pairing tokens are never written to disk
+    pub fn record(&mut self, client_id: &str, mut event: RecordedEvent) -> Result<(), String> {
+        if let RecordedEvent::Message { message } = &mut event {
+            if let InboundMessage::Hello(hello) = &mut message.payload {
+                hello.auth_token = None;
+            }
+        }
+
+        let frame = RecordedFrame {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            client_id: client_id.to_string(),
+            event,
+        };
+        let line = serde_json::to_string(&frame).map_err(|e| e.to_string())?;
+        writeln!(self.writer, "{}", line)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+/// Record into the active recording, if any
+pub fn record_event(recorder: &RecorderState, client_id: &str, event: RecordedEvent) {
+    let Ok(mut guard) = recorder.lock() else {
+        return;
+    };
+    if let Some(recorder) = guard.as_mut() {
+        if let Err(e) = recorder.record(client_id, event) {
+            warn!("Stopping session recording: {}", e);
+            *guard = None;
+        }
+    }
+}
+
+pub fn parse_recording(contents: &str) -> Result<Vec<RecordedFrame>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| format!("Line {}: {}", index + 1, e))
+        })
+        .collect()
+}
+
+pub fn load_recording(path: &Path) -> Result<Vec<RecordedFrame>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse_recording(&contents)
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplaySummary {
+    pub clients: usize,
+    pub messages: usize,
+    pub duration_ms: u64,
+}
+
+/// Replay `frames` against `server`, `speed` times faster than recorded. HELLO messages
+/// carry `auth_token` since recordings never contain the original one.
+pub async fn replay(
+    server: &WebSocketServer,
+    frames: Vec<RecordedFrame>,
+    speed: f64,
+    auth_token: &str,
+) -> Result<ReplaySummary, String> {
+    if speed.is_nan() || speed <= 0.0 {
+        return Err(format!("Replay speed must be positive, got {}", speed));
+    }
+
+    // One connection per recorded client, in order of first appearance
+    let mut order = Vec::new();
+    let mut by_client: HashMap<String, Vec<RecordedFrame>> = HashMap::new();
+    for frame in frames {
+        if !by_client.contains_key(&frame.client_id) {
+            order.push(frame.client_id.clone());
+        }
+        by_client
+            .entry(frame.client_id.clone())
+            .or_default()
+            .push(frame);
+    }
+
+    let started = Instant::now();
+    let replays = order.into_iter().map(|client_id| {
+        let frames = by_client.remove(&client_id).unwrap_or_default();
+        replay_client(server, client_id, frames, speed, auth_token, started)
+    });
+    let results = futures_util::future::join_all(replays).await;
+
+    let mut summary = ReplaySummary {
+        duration_ms: started.elapsed().as_millis() as u64,
+        ..Default::default()
+    };
+    for result in results {
+        summary.messages += result?;
+        summary.clients += 1;
+    }
+    info!(
+        "Replayed {} messages from {} clients in {}ms",
+        summary.messages, summary.clients, summary.duration_ms
+    );
+    Ok(summary)
+}
+
+async fn replay_client(
+    server: &WebSocketServer,
+    client_id: String,
+    frames: Vec<RecordedFrame>,
+    speed: f64,
+    auth_token: &str,
+    started: Instant,
+) -> Result<usize, String> {
+    let at = |frame: &RecordedFrame| {
+        started + Duration::from_secs_f64(frame.at_ms as f64 / 1000.0 / speed)
+    };
+    if let Some(first) = frames.first() {
+        tokio::time::sleep_until(at(first).into()).await;
+    }
+
+    let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+    server.accept_stream(server_side, format!("replay of {}", client_id));
+    let (ws_stream, _) = client_async("ws://localhost/", client_side)
+        .await
+        .map_err(|e| format!("Replay handshake failed: {}", e))?;
+    let (mut sender, mut receiver) = ws_stream.split();
+    // Keep reading so the server's frames and pings are answered
+    let drain = tokio::spawn(async move { while let Some(Ok(_)) = receiver.next().await {} });
+
+    let mut sent = 0;
+    for frame in frames {
+        tokio::time::sleep_until(at(&frame).into()).await;
+        match frame.event {
+            RecordedEvent::Connected => {}
+            RecordedEvent::Message { mut message } => {
+                if let InboundMessage::Hello(hello) = &mut message.payload {
+                    hello.auth_token = Some(auth_token.to_string());
+                }
+                let text = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+                sender
+                    .send(Message::Text(text))
+                    .await
+                    .map_err(|e| format!("Replay of {} failed: {}", client_id, e))?;
+                sent += 1;
+            }
+            RecordedEvent::Disconnected => break,
+        }
+    }
+
+    let _ = sender.close().await;
+    let _ = drain.await;
+    Ok(sent)
+}
+
+fn default_recording_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let dir = app_handle
+        .path()
+        .app_log_dir()
+        .map_err(|e| format!("Failed to resolve log directory: {}", e))?;
+    Ok(dir
+        .join("recordings")
+        .join(format!("session-{}.jsonl", crate::protocol::now_millis())))
+}
+
+/// Start recording inbound extension traffic, returning the file being written
+#[tauri::command]
+pub async fn start_session_recording(
+    path: Option<String>,
+    recorder_state: State<'_, RecorderState>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => default_recording_path(&app_handle)?,
+    };
+    let recorder = SessionRecorder::create(&path)?;
+    *recorder_state.lock().map_err(|e| e.to_string())? = Some(recorder);
+    info!("Recording extension traffic to {}", path.display());
+    Ok(path.display().to_string())
+}
+
+/// Stop the active recording, returning its file
+#[tauri::command]
+pub async fn stop_session_recording(
+    recorder_state: State<'_, RecorderState>,
+) -> Result<Option<String>, String> {
+    let recorder = recorder_state.lock().map_err(|e| e.to_string())?.take();
+    Ok(recorder.map(|recorder| {
+        info!("Stopped recording to {}", recorder.path().display());
+        recorder.path().display().to_string()
+    }))
+}
+
+#[tauri::command]
+pub async fn replay_session(
+    path: String,
+    speed: Option<f64>,
+    ws_state: State<'_, WebSocketState>,
+    auth_state: State<'_, AuthState>,
+) -> Result<ReplaySummary, String> {
+    let frames = load_recording(Path::new(&path))?;
+    let server = ws_state
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| "WebSocket server not available".to_string())?;
+    let token = auth_state
+        .read()
+        .map_err(|e| e.to_string())?
+        .pairing_token
+        .clone();
+    info!("Replaying {} ({} frames)", path, frames.len());
+    replay(&server, frames, speed.unwrap_or(1.0), &token).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthConfig;
+    use crate::websocket::WebSocketConfig;
+    use std::sync::{Arc, Mutex};
+
+    const RECORDING: &str = r#"
+{"at_ms":0,"client_id":"a","kind":"connected"}
+{"at_ms":5,"client_id":"a","kind":"message","message":{"message_type":"HELLO","data":{"protocol_version":1,"extension_version":"1.0.0","browser":"chrome","capabilities":[]},"timestamp":1}}
+{"at_ms":40,"client_id":"a","kind":"message","message":{"message_type":"TRACK_DETECTED","data":{"title":"One","artist":"Band","thumbnail":null,"source":"spotify","url":"u","timestamp":2,"is_playing":true,"currentTime":0.3,"duration":200.0},"timestamp":2}}
+{"at_ms":80,"client_id":"a","kind":"message","message":{"message_type":"TRACK_PROGRESS","data":{"title":"One","artist":"Band","thumbnail":null,"source":"spotify","url":"u","timestamp":3,"is_playing":true,"currentTime":1.3,"duration":200.0},"timestamp":3}}
+{"at_ms":90,"client_id":"a","kind":"disconnected"}
+"#;
+
+    #[test]
+    fn never_writes_pairing_tokens() {
+        let path =
+            std::env::temp_dir().join(format!("lyryc-recording-{}.jsonl", std::process::id()));
+        let mut frames = parse_recording(RECORDING).unwrap();
+        if let RecordedEvent::Message { message } = &mut frames[1].event {
+            if let InboundMessage::Hello(hello) = &mut message.payload {
+                hello.auth_token = Some("ABCD-1234-EF56".to_string());
+            }
+        }
+
+        let mut recorder = SessionRecorder::create(&path).unwrap();
+        for frame in frames {
+            recorder.record(&frame.client_id, frame.event).unwrap();
+        }
+        drop(recorder);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(!contents.contains("ABCD-1234-EF56"));
+        assert_eq!(parse_recording(&contents).unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn replays_through_the_server() {
+        let auth = AuthConfig::generate();
+        let token = auth.pairing_token.clone();
+        let mut server = WebSocketServer::new(
+            WebSocketConfig::default(),
+            Arc::new(std::sync::RwLock::new(auth)),
+        );
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&received);
+        server.set_track_callback(move |_, message| {
+            sink.lock()
+                .unwrap()
+                .push(message.message_type().to_string());
+        });
+
+        let frames = parse_recording(RECORDING).unwrap();
+        let summary = replay(&server, frames, 10.0, &token).await.unwrap();
+
+        assert_eq!(summary.clients, 1);
+        assert_eq!(summary.messages, 3);
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec!["TRACK_DETECTED", "TRACK_PROGRESS"]
+        );
+    }
+}
@@ -0,0 +1,533 @@
+//! Persistent application settings.
+//!
+//! Settings live in `settings.json` in the app config directory, one section per
+//! module. The file carries a `version`; older files are migrated step by step on
+//! load, files from a newer build are left untouched: this build runs on defaults and
+//! refuses to save over them. Missing fields take their
+//! defaults, so adding a setting does not need a migration, renaming one does.
+
+use crate::shortcuts::{self, ShortcutBindings};
+use crate::types::{HttpApiState, SettingsState, WebSocketState};
+use crate::websocket::WebSocketConfig;
+use crate::websocket_commands::restart_extension_server;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{Emitter, Manager, State};
+use tokio::sync::MutexGuard;
+
+pub const SETTINGS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    pub version: u32,
+    pub websocket: WebSocketSettings,
+    pub http_api: HttpApiSettings,
+    pub lyrics: LyricsSettings,
+    pub track_cleaning: TrackCleaningSettings,
+    pub window: WindowSettings,
+    pub shortcuts: ShortcutBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            websocket: WebSocketSettings::default(),
+            http_api: HttpApiSettings::default(),
+            lyrics: LyricsSettings::default(),
+            track_cleaning: TrackCleaningSettings::default(),
+            window: WindowSettings::default(),
+            shortcuts: shortcuts::default_bindings(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WebSocketSettings {
+    /// Preferred port; the server falls back through the range the extension probes
+    pub port: u16,
+    pub ping_interval_secs: u64,
+    /// Clients silent for longer are dropped; must exceed the ping interval
+    pub idle_timeout_secs: u64,
+    pub command_timeout_secs: u64,
+    pub send_queue_capacity: usize,
+}
+
+impl Default for WebSocketSettings {
+    fn default() -> Self {
+        let config = WebSocketConfig::default();
+        Self {
+            port: config.port,
+            ping_interval_secs: config.heartbeat.interval.as_secs(),
+            idle_timeout_secs: config.heartbeat.timeout.as_secs(),
+            command_timeout_secs: config.command_timeout.as_secs(),
+            send_queue_capacity: config.send_queue_capacity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HttpApiSettings {
+    /// Start the local HTTP API with the app
+    pub enabled: bool,
+    pub port: u16,
+}
+
+impl Default for HttpApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: crate::http_api::DEFAULT_HTTP_PORT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LyricsSettings {
+    /// Timeout of a single LRCLib request
+    pub request_timeout_secs: u64,
+    /// Timeout of a whole search across every strategy
+    pub search_timeout_secs: u64,
+}
+
+impl Default for LyricsSettings {
+    fn default() -> Self {
+        Self {
+            request_timeout_secs: 10,
+            search_timeout_secs: 30,
+        }
+    }
+}
+
+impl LyricsSettings {
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+
+    pub fn search_timeout(&self) -> Duration {
+        Duration::from_secs(self.search_timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TrackCleaningSettings {
+    /// Ask an LLM to clean titles when `GITHUB_TOKEN` is set; regex cleaning otherwise
+    pub llm_enabled: bool,
+    /// GitHub Models ids, tried in order
+    pub llm_models: Vec<String>,
+    pub llm_timeout_secs: u64,
+}
+
+impl Default for TrackCleaningSettings {
+    fn default() -> Self {
+        Self {
+            llm_enabled: true,
+            // Best performing models from testing
+            llm_models: vec![
+                "meta/Meta-Llama-3.1-8B-Instruct".to_string(),
+                "meta/Llama-4-Maverick-17B-128E-Instruct-FP8".to_string(),
+                "mistral-ai/Ministral-3B".to_string(),
+            ],
+            llm_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WindowSettings {
+    /// Height of the lyrics bar in physical pixels until it is resized
+    pub height: u32,
+    /// How long `enable_drag_mode` keeps the window interactive
+    pub drag_mode_secs: u64,
+    /// Monitor the window moves to whenever it is connected
+    pub follow_monitor: Option<String>,
+    /// Idle time before the window is hidden while nothing plays; `None` keeps it up
+    pub auto_hide_secs: Option<u64>,
+    pub auto_hide_mode: AutoHideMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoHideMode {
+    #[default]
+    Hide,
+    /// Keep the window but let the frontend fade it out
+    Fade,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            height: 40,
+            drag_mode_secs: 5,
+            follow_monitor: None,
+            auto_hide_secs: None,
+            auto_hide_mode: AutoHideMode::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), String> {
+        let websocket = &self.websocket;
+        if websocket.port == 0 || self.http_api.port == 0 {
+            return Err("Ports must be between 1 and 65535".to_string());
+        }
+        if websocket.ping_interval_secs == 0 || websocket.command_timeout_secs == 0 {
+            return Err("WebSocket intervals and timeouts must be at least 1 second".to_string());
+        }
+        if websocket.idle_timeout_secs <= websocket.ping_interval_secs {
+            return Err(format!(
+                "WebSocket idle timeout ({}s) must be longer than the ping interval ({}s)",
+                websocket.idle_timeout_secs, websocket.ping_interval_secs
+            ));
+        }
+        if websocket.send_queue_capacity == 0 {
+            return Err("WebSocket send queue capacity must be at least 1".to_string());
+        }
+        if self.lyrics.request_timeout_secs == 0
+            || self.lyrics.search_timeout_secs < self.lyrics.request_timeout_secs
+        {
+            return Err(
+                "Lyrics search timeout must be at least the request timeout, which must be at least 1 second"
+                    .to_string(),
+            );
+        }
+        if self.track_cleaning.llm_enabled && self.track_cleaning.llm_models.is_empty() {
+            return Err("LLM track cleaning needs at least one model".to_string());
+        }
+        if self.track_cleaning.llm_timeout_secs == 0 {
+            return Err("LLM timeout must be at least 1 second".to_string());
+        }
+        if self.window.height < 20 {
+            return Err("Window height must be at least 20 pixels".to_string());
+        }
+        if self.window.auto_hide_secs.is_some_and(|secs| secs < 5) {
+            return Err("Auto-hide delay must be at least 5 seconds".to_string());
+        }
+        shortcuts::validate_bindings(&self.shortcuts)
+    }
+
+    /// Parse a settings file, migrating it to the current version
+    pub fn from_json(contents: &str) -> Result<Self, String> {
+        let mut value: Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+        migrate(&mut value)?;
+        let settings: Settings = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Load the stored settings, falling back to defaults. A file that cannot be used is
+    /// set aside rather than overwritten; one from a newer version is left alone, and
+    /// `save` refuses to replace it.
+    pub fn load_or_default(path: &Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+
+        if let Some(version) = newer_version(&contents) {
+            warn!(
+                "Settings version {} is newer than this build supports; using defaults without saving",
+                version
+            );
+            return Self::default();
+        }
+
+        match Self::from_json(&contents) {
+            Ok(settings) => settings,
+            Err(e) => {
+                let backup = path.with_extension("json.invalid");
+                warn!(
+                    "Invalid settings in {} ({}), moving it to {}",
+                    path.display(),
+                    e,
+                    backup.display()
+                );
+                let _ = std::fs::rename(path, &backup);
+                Self::default()
+            }
+        }
+    }
+
+    /// Fails without writing when the file on disk comes from a newer build
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(version) = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| newer_version(&contents))
+        {
+            return Err(format!(
+                "{} was written by a newer version of Lyryc (settings version {}); \
+                 update Lyryc to change settings",
+                path.display(),
+                version
+            ));
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// The `version` of a settings document, if it is newer than this build supports
+fn newer_version(contents: &str) -> Option<u64> {
+    serde_json::from_str::<Value>(contents)
+        .ok()
+        .and_then(|value| value.get("version")?.as_u64())
+        .filter(|version| *version > SETTINGS_VERSION as u64)
+}
+
+/// Bring a settings document up to `SETTINGS_VERSION`, one version at a time
+fn migrate(value: &mut Value) -> Result<(), String> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| "Settings must be a JSON object".to_string())?;
+    let mut version = match object.get("version") {
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("Settings version {} is not a valid version", version))?,
+        None => 0,
+    };
+    if version > SETTINGS_VERSION {
+        return Err(format!(
+            "Settings version {} is newer than this build supports ({})",
+            version, SETTINGS_VERSION
+        ));
+    }
+
+    while version < SETTINGS_VERSION {
+        match version {
+            // Hand-written files without a version use the version 1 layout
+            0 => {}
+            _ => return Err(format!("No migration from settings version {}", version)),
+        }
+        version += 1;
+    }
+    object.insert("version".to_string(), Value::from(SETTINGS_VERSION));
+    Ok(())
+}
+
+/// Overlay `patch` onto `base`: objects merge key by key, everything else is replaced
+fn merge(base: &mut Value, patch: Value) {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, patch) => *base = patch,
+    }
+}
+
+pub fn settings_file_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app_handle
+        .path()
+        .app_config_dir()
+        .map(|dir| dir.join("settings.json"))
+        .map_err(|e| format!("Failed to resolve config directory: {}", e))
+}
+
+/// Current settings; a clone so callers do not hold the lock across awaits
+pub async fn current(app_handle: &tauri::AppHandle) -> Settings {
+    app_handle.state::<SettingsState>().lock().await.clone()
+}
+
+/// Store `settings`, tell the frontend and apply what cannot be read on demand.
+/// `stored` is the locked state `settings` was derived from, so concurrent updates
+/// cannot overwrite each other; it is released before the changes are applied.
+pub(crate) async fn commit(
+    app_handle: &tauri::AppHandle,
+    mut stored: MutexGuard<'_, Settings>,
+    settings: Settings,
+) -> Result<Settings, String> {
+    settings.validate()?;
+    let previous = stored.clone();
+    let path = settings_file_path(app_handle)?;
+    // Shortcuts the OS refuses are not saved
+    let shortcuts_changed = settings.shortcuts != previous.shortcuts;
+    if shortcuts_changed {
+        shortcuts::apply(app_handle, &previous.shortcuts, &settings.shortcuts)?;
+    }
+    if let Err(e) = settings.save(&path) {
+        // Keep the OS bindings in line with the settings that stay in effect
+        if shortcuts_changed {
+            if let Err(rollback) =
+                shortcuts::apply(app_handle, &settings.shortcuts, &previous.shortcuts)
+            {
+                warn!("Failed to restore the previous shortcuts: {}", rollback);
+            }
+        }
+        return Err(e);
+    }
+    *stored = settings.clone();
+    // Restarting the servers reads the settings again
+    drop(stored);
+    if let Err(e) = app_handle.emit("settings-changed", &settings) {
+        warn!("Failed to emit settings-changed event: {}", e);
+    }
+
+    if settings.websocket != previous.websocket {
+        let running = app_handle.state::<WebSocketState>().lock().await.is_some();
+        if running {
+            info!("WebSocket settings changed, restarting the server");
+            restart_extension_server(app_handle, None).await?;
+        }
+    }
+    if settings.http_api != previous.http_api {
+        apply_http_api(app_handle, &settings.http_api).await?;
+    }
+    if settings.window.follow_monitor != previous.window.follow_monitor {
+        crate::window_geometry::reapply(app_handle, "main").await?;
+    }
+    Ok(settings)
+}
+
+async fn apply_http_api(
+    app_handle: &tauri::AppHandle,
+    settings: &HttpApiSettings,
+) -> Result<(), String> {
+    let http_state = app_handle.state::<HttpApiState>();
+    let mut guard = http_state.lock().await;
+    if let Some(handle) = guard.take() {
+        handle.stop(app_handle);
+    }
+    if settings.enabled {
+        *guard = Some(crate::http_api::start(app_handle, settings.port).await?);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_settings(settings_state: State<'_, SettingsState>) -> Result<Settings, String> {
+    Ok(settings_state.lock().await.clone())
+}
+
+/// Merge a partial settings object, e.g. `{"lyrics": {"requestTimeoutSecs": 15}}`
+#[tauri::command]
+pub async fn update_settings(
+    patch: Value,
+    app_handle: tauri::AppHandle,
+) -> Result<Settings, String> {
+    let settings_state = app_handle.state::<SettingsState>();
+    let stored = settings_state.lock().await;
+    let mut value = serde_json::to_value(&*stored).map_err(|e| e.to_string())?;
+    merge(&mut value, patch);
+    let mut settings: Settings =
+        serde_json::from_value(value).map_err(|e| format!("Invalid settings: {}", e))?;
+    settings.version = SETTINGS_VERSION;
+    info!("Updating settings");
+    commit(&app_handle, stored, settings).await
+}
+
+/// Reset one section (e.g. "lyrics") or, without a section, everything
+#[tauri::command]
+pub async fn reset_settings(
+    section: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<Settings, String> {
+    let settings_state = app_handle.state::<SettingsState>();
+    let stored = settings_state.lock().await;
+    let defaults = Settings::default();
+    let mut settings = stored.clone();
+    match section.as_deref() {
+        None => settings = defaults,
+        Some("websocket") => settings.websocket = defaults.websocket,
+        Some("httpApi") => settings.http_api = defaults.http_api,
+        Some("lyrics") => settings.lyrics = defaults.lyrics,
+        Some("trackCleaning") => settings.track_cleaning = defaults.track_cleaning,
+        Some("window") => settings.window = defaults.window,
+        Some("shortcuts") => settings.shortcuts = defaults.shortcuts,
+        Some(other) => return Err(format!("Unknown settings section '{}'", other)),
+    }
+    info!(
+        "Resetting settings: {}",
+        section.as_deref().unwrap_or("all")
+    );
+    commit(&app_handle, stored, settings).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_missing_fields_and_stamps_the_version() {
+        let settings = Settings::from_json(r#"{"lyrics": {"requestTimeoutSecs": 5}}"#).unwrap();
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.lyrics.request_timeout_secs, 5);
+        assert_eq!(settings.lyrics.search_timeout_secs, 30);
+        assert_eq!(settings.websocket, WebSocketSettings::default());
+    }
+
+    #[test]
+    fn rejects_invalid_and_newer_files() {
+        let invalid =
+            r#"{"version": 1, "websocket": {"pingIntervalSecs": 60, "idleTimeoutSecs": 30}}"#;
+        assert!(Settings::from_json(invalid)
+            .unwrap_err()
+            .contains("idle timeout"));
+        assert!(Settings::from_json(r#"{"version": 99}"#)
+            .unwrap_err()
+            .starts_with("Settings version"));
+        // Would wrap around to version 1 if truncated
+        assert!(Settings::from_json(r#"{"version": 4294967297}"#).is_err());
+        assert!(Settings::from_json(r#"{"version": -1}"#).is_err());
+        assert!(Settings::from_json("[]").is_err());
+    }
+
+    #[test]
+    fn patches_merge_into_sections() {
+        let mut value = serde_json::to_value(Settings::default()).unwrap();
+        merge(
+            &mut value,
+            serde_json::json!({"window": {"height": 56}, "trackCleaning": {"llmModels": ["a"]}}),
+        );
+        let settings: Settings = serde_json::from_value(value).unwrap();
+        assert_eq!(settings.window.height, 56);
+        assert_eq!(settings.window.drag_mode_secs, 5);
+        assert_eq!(settings.track_cleaning.llm_models, vec!["a".to_string()]);
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn newer_files_are_not_overwritten() {
+        let path = std::env::temp_dir().join(format!("lyryc-settings-{}.json", std::process::id()));
+        let newer = format!(
+            r#"{{"version": {}, "window": {{"height": 64}}}}"#,
+            SETTINGS_VERSION + 1
+        );
+        std::fs::write(&path, &newer).unwrap();
+
+        let loaded = Settings::load_or_default(&path);
+        assert_eq!(loaded, Settings::default());
+
+        // What `update_settings` would store after a patch
+        let mut value = serde_json::to_value(&loaded).unwrap();
+        merge(&mut value, serde_json::json!({"window": {"height": 56}}));
+        let updated: Settings = serde_json::from_value(value).unwrap();
+        assert!(updated.save(&path).unwrap_err().contains("newer version"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), newer);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(updated.save(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
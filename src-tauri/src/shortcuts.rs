@@ -0,0 +1,399 @@
+//! User-configurable global shortcuts.
+//!
+//! Bindings live in the `shortcuts` settings section as action → accelerator, e.g.
+//! `"playPause": "Ctrl+Alt+P"`. They are registered with the OS at startup and whenever
+//! the section changes. A binding the OS refuses is logged and reported by
+//! `get_shortcuts` at startup, and rejected with the OS error when set at runtime.
+
+use crate::line_tracking;
+use crate::protocol::PlaybackCommand;
+use crate::settings;
+use crate::types::{SettingsState, ShortcutRegistryState, SourceState, WebSocketState};
+use crate::websocket_commands::dispatch_playback_command;
+use crate::window_management::{toggle_click_through, toggle_window_visibility};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tauri::{Emitter, Manager};
+#[cfg(desktop)]
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut};
+
+/// Seconds one offset shortcut press moves the lyrics, like the small offset buttons
+pub const OFFSET_STEP_SECS: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShortcutAction {
+    ToggleClickThrough,
+    ToggleVisibility,
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    OffsetIncrease,
+    OffsetDecrease,
+    ResearchLyrics,
+}
+
+impl ShortcutAction {
+    pub const ALL: [ShortcutAction; 8] = [
+        ShortcutAction::ToggleClickThrough,
+        ShortcutAction::ToggleVisibility,
+        ShortcutAction::PlayPause,
+        ShortcutAction::NextTrack,
+        ShortcutAction::PreviousTrack,
+        ShortcutAction::OffsetIncrease,
+        ShortcutAction::OffsetDecrease,
+        ShortcutAction::ResearchLyrics,
+    ];
+
+    /// Name used in settings and error messages
+    pub fn name(self) -> &'static str {
+        match self {
+            ShortcutAction::ToggleClickThrough => "toggleClickThrough",
+            ShortcutAction::ToggleVisibility => "toggleVisibility",
+            ShortcutAction::PlayPause => "playPause",
+            ShortcutAction::NextTrack => "nextTrack",
+            ShortcutAction::PreviousTrack => "previousTrack",
+            ShortcutAction::OffsetIncrease => "offsetIncrease",
+            ShortcutAction::OffsetDecrease => "offsetDecrease",
+            ShortcutAction::ResearchLyrics => "researchLyrics",
+        }
+    }
+}
+
+/// Accelerator per action; actions without an entry are unbound
+pub type ShortcutBindings = BTreeMap<ShortcutAction, String>;
+
+/// Only the two shortcuts the app always had; media keys are left for the user to pick
+pub fn default_bindings() -> ShortcutBindings {
+    BTreeMap::from([
+        (
+            ShortcutAction::ToggleClickThrough,
+            "Ctrl+Shift+D".to_string(),
+        ),
+        (ShortcutAction::ToggleVisibility, "Ctrl+Shift+M".to_string()),
+    ])
+}
+
+/// Every binding must parse and no two actions may share a shortcut
+pub fn validate_bindings(bindings: &ShortcutBindings) -> Result<(), String> {
+    let mut seen: HashMap<String, ShortcutAction> = HashMap::new();
+    for (action, binding) in bindings {
+        let key = canonical(binding).map_err(|e| {
+            format!(
+                "Invalid shortcut '{}' for {}: {}",
+                binding,
+                action.name(),
+                e
+            )
+        })?;
+        if let Some(other) = seen.insert(key, *action) {
+            return Err(format!(
+                "{} is bound to both {} and {}",
+                binding,
+                other.name(),
+                action.name()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Spelling-independent form, so "ctrl+shift+d" and "Shift+Control+D" conflict
+#[cfg(desktop)]
+fn canonical(binding: &str) -> Result<String, String> {
+    binding
+        .parse::<Shortcut>()
+        .map(Shortcut::into_string)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(desktop))]
+fn canonical(binding: &str) -> Result<String, String> {
+    Ok(binding.trim().to_lowercase())
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShortcutStatus {
+    pub action: ShortcutAction,
+    pub shortcut: Option<String>,
+    /// Whether the OS accepted the binding
+    pub registered: bool,
+    pub error: Option<String>,
+}
+
+/// What is registered with the OS right now
+#[derive(Debug, Default)]
+pub struct ShortcutRegistry {
+    /// Shortcut ids the OS accepted and the action each triggers
+    actions: HashMap<u32, ShortcutAction>,
+    /// Bindings the OS refused, with its reason
+    failed: HashMap<ShortcutAction, String>,
+}
+
+impl ShortcutRegistry {
+    pub fn action_for(&self, shortcut_id: u32) -> Option<ShortcutAction> {
+        self.actions.get(&shortcut_id).copied()
+    }
+
+    pub fn statuses(&self, bindings: &ShortcutBindings) -> Vec<ShortcutStatus> {
+        ShortcutAction::ALL
+            .iter()
+            .map(|action| ShortcutStatus {
+                action: *action,
+                shortcut: bindings.get(action).cloned(),
+                registered: self.actions.values().any(|a| a == action),
+                error: self.failed.get(action).cloned(),
+            })
+            .collect()
+    }
+}
+
+/// Replace everything registered with `bindings`, recording what the OS refuses
+#[cfg(desktop)]
+fn register_all(app_handle: &tauri::AppHandle, bindings: &ShortcutBindings) -> ShortcutRegistry {
+    let global_shortcut = app_handle.global_shortcut();
+    if let Err(e) = global_shortcut.unregister_all() {
+        warn!("Failed to unregister global shortcuts: {}", e);
+    }
+
+    let mut registry = ShortcutRegistry::default();
+    for (action, binding) in bindings {
+        let result = binding
+            .parse::<Shortcut>()
+            .map_err(|e| e.to_string())
+            .and_then(|shortcut| {
+                global_shortcut
+                    .register(shortcut)
+                    .map(|_| shortcut.id())
+                    .map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(id) => {
+                registry.actions.insert(id, *action);
+            }
+            Err(e) => {
+                warn!(
+                    "Could not register global shortcut {} for {}: {}",
+                    binding,
+                    action.name(),
+                    e
+                );
+                registry.failed.insert(*action, e);
+            }
+        }
+    }
+    registry
+}
+
+#[cfg(not(desktop))]
+fn register_all(app_handle: &tauri::AppHandle, bindings: &ShortcutBindings) -> ShortcutRegistry {
+    let _ = app_handle;
+    ShortcutRegistry {
+        actions: HashMap::new(),
+        failed: bindings
+            .keys()
+            .map(|action| (*action, "Global shortcuts are not supported".to_string()))
+            .collect(),
+    }
+}
+
+fn store(app_handle: &tauri::AppHandle, registry: ShortcutRegistry) {
+    *app_handle
+        .state::<ShortcutRegistryState>()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = registry;
+}
+
+/// Register the stored bindings at startup; refusals do not stop the app
+pub fn register_startup(app_handle: &tauri::AppHandle, bindings: &ShortcutBindings) {
+    let registry = register_all(app_handle, bindings);
+    let registered: Vec<String> = bindings
+        .iter()
+        .filter(|(action, _)| !registry.failed.contains_key(action))
+        .map(|(action, binding)| format!("{} ({})", binding, action.name()))
+        .collect();
+    info!("Global shortcuts registered: {}", registered.join(", "));
+    store(app_handle, registry);
+}
+
+/// Switch from `previous` to `bindings`. If the OS refuses a binding that changed, the
+/// previous bindings are restored and the refusal is returned.
+pub fn apply(
+    app_handle: &tauri::AppHandle,
+    previous: &ShortcutBindings,
+    bindings: &ShortcutBindings,
+) -> Result<(), String> {
+    let registry = register_all(app_handle, bindings);
+    let refused: Vec<String> = registry
+        .failed
+        .iter()
+        .filter(|(action, _)| previous.get(action) != bindings.get(action))
+        .map(|(action, e)| format!("{} for {} ({})", bindings[action], action.name(), e))
+        .collect();
+
+    if !refused.is_empty() {
+        store(app_handle, register_all(app_handle, previous));
+        return Err(format!(
+            "The system refused {}; another application may already use it",
+            refused.join(", ")
+        ));
+    }
+    store(app_handle, registry);
+    Ok(())
+}
+
+/// Run the action bound to a pressed shortcut
+pub fn handle_pressed(app_handle: &tauri::AppHandle, shortcut_id: u32) {
+    let action = app_handle
+        .state::<ShortcutRegistryState>()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .action_for(shortcut_id);
+    let Some(action) = action else {
+        return;
+    };
+
+    info!("Global shortcut triggered: {}", action.name());
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = run_action(&app_handle, action).await {
+            error!("Shortcut action {} failed: {}", action.name(), e);
+        }
+    });
+}
+
+pub(crate) async fn run_action(
+    app_handle: &tauri::AppHandle,
+    action: ShortcutAction,
+) -> Result<(), String> {
+    match action {
+        ShortcutAction::ToggleClickThrough => toggle_click_through(app_handle).await.map(|_| ()),
+        ShortcutAction::ToggleVisibility => toggle_window_visibility(app_handle.clone())
+            .await
+            .map(|_| ()),
+        ShortcutAction::PlayPause => playback(app_handle, PlaybackCommand::Toggle).await,
+        ShortcutAction::NextTrack => playback(app_handle, PlaybackCommand::Next).await,
+        ShortcutAction::PreviousTrack => playback(app_handle, PlaybackCommand::Previous).await,
+        ShortcutAction::OffsetIncrease => adjust_offset(app_handle, OFFSET_STEP_SECS).await,
+        ShortcutAction::OffsetDecrease => adjust_offset(app_handle, -OFFSET_STEP_SECS).await,
+        ShortcutAction::ResearchLyrics => app_handle
+            .emit("lyrics-research-requested", ())
+            .map_err(|e| e.to_string()),
+    }
+}
+
+async fn playback(app_handle: &tauri::AppHandle, command: PlaybackCommand) -> Result<(), String> {
+    let ws_state = app_handle.state::<WebSocketState>();
+    let source_state = app_handle.state::<SourceState>();
+    dispatch_playback_command(command, None, None, &ws_state, &source_state, app_handle)
+        .await
+        .map(|_| ())
+}
+
+/// Applied to line tracking right away; the frontend stores it for the current track
+async fn adjust_offset(app_handle: &tauri::AppHandle, delta: f64) -> Result<(), String> {
+    line_tracking::adjust_offset(app_handle, delta).await;
+    app_handle
+        .emit(
+            "lyrics-offset-adjust",
+            serde_json::json!({ "delta": delta }),
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_shortcuts(app_handle: tauri::AppHandle) -> Result<Vec<ShortcutStatus>, String> {
+    let bindings = settings::current(&app_handle).await.shortcuts;
+    Ok(app_handle
+        .state::<ShortcutRegistryState>()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .statuses(&bindings))
+}
+
+/// Bind `action` to `shortcut` (e.g. "Ctrl+Alt+P"), or unbind it without one
+#[tauri::command]
+pub async fn set_shortcut(
+    action: ShortcutAction,
+    shortcut: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<ShortcutStatus>, String> {
+    let settings_state = app_handle.state::<SettingsState>();
+    let stored = settings_state.lock().await;
+    let mut updated = stored.clone();
+    match shortcut
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+    {
+        Some(shortcut) => {
+            // Unparsable shortcuts are reported by validation
+            let key = canonical(&shortcut).ok();
+            if let Some((other, _)) = updated.shortcuts.iter().find(|(other, binding)| {
+                **other != action && key.is_some() && canonical(binding).ok() == key
+            }) {
+                return Err(format!("{} is already bound to {}", shortcut, other.name()));
+            }
+            info!("Binding {} to {}", action.name(), shortcut);
+            updated.shortcuts.insert(action, shortcut);
+        }
+        None => {
+            info!("Unbinding {}", action.name());
+            updated.shortcuts.remove(&action);
+        }
+    }
+
+    settings::commit(&app_handle, stored, updated).await?;
+    get_shortcuts(app_handle).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn defaults_keep_the_original_shortcuts() {
+        let bindings = default_bindings();
+        assert_eq!(
+            bindings
+                .get(&ShortcutAction::ToggleClickThrough)
+                .map(String::as_str),
+            Some("Ctrl+Shift+D")
+        );
+        assert!(validate_bindings(&bindings).is_ok());
+        assert_eq!(Settings::default().shortcuts, bindings);
+    }
+
+    #[test]
+    fn rejects_conflicts_and_unparsable_bindings() {
+        let mut bindings = default_bindings();
+        bindings.insert(ShortcutAction::PlayPause, "shift+control+d".to_string());
+        let error = validate_bindings(&bindings).unwrap_err();
+        assert!(error.contains("toggleClickThrough") && error.contains("playPause"));
+
+        let mut bindings = default_bindings();
+        bindings.insert(ShortcutAction::NextTrack, "Ctrl+Shift+Nope".to_string());
+        assert!(validate_bindings(&bindings)
+            .unwrap_err()
+            .starts_with("Invalid shortcut 'Ctrl+Shift+Nope' for nextTrack"));
+    }
+
+    #[test]
+    fn reads_bindings_from_settings() {
+        let settings = Settings::from_json(
+            r#"{"shortcuts": {"toggleVisibility": "Alt+M", "offsetIncrease": "Ctrl+Alt+Equal"}}"#,
+        )
+        .unwrap();
+        assert_eq!(settings.shortcuts.len(), 2);
+        assert!(!settings
+            .shortcuts
+            .contains_key(&ShortcutAction::ToggleClickThrough));
+
+        let registry = ShortcutRegistry::default();
+        let statuses = registry.statuses(&settings.shortcuts);
+        assert_eq!(statuses.len(), ShortcutAction::ALL.len());
+        assert!(statuses.iter().all(|status| !status.registered));
+    }
+}
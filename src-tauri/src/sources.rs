@@ -0,0 +1,406 @@
+use crate::protocol::TrackUpdate;
+use crate::track_pipeline::{PipelineEvent, SourcesReply};
+use crate::types::{SourceState, TrackPipeline};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::{Emitter, State};
+
+/// One player: a source (spotify, youtube, ...) reported by one connected client
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SourceKey {
+    pub client_id: String,
+    pub source: String,
+}
+
+impl SourceKey {
+    pub fn new(client_id: &str, source: &str) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            source: source.to_string(),
+        }
+    }
+}
+
+/// How the active source is chosen when several players report tracks
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum ArbitrationPolicy {
+    /// Follow whichever player most recently started playing
+    #[default]
+    MostRecentlyPlaying,
+    /// Prefer a source by name (e.g. "spotify") whenever it is present
+    Pinned { source: String },
+    /// Stay on the source the user selected until it disappears
+    Manual,
+}
+
+#[derive(Debug, Clone)]
+struct SourceEntry {
+    last_update: TrackUpdate,
+    started_playing_at: Option<u64>,
+    last_seen: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceSummary {
+    #[serde(flatten)]
+    pub key: SourceKey,
+    pub title: String,
+    pub artist: String,
+    pub url: String,
+    pub is_playing: bool,
+    pub current_time: Option<f64>,
+    pub last_seen: u64,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourcesSnapshot {
+    pub policy: ArbitrationPolicy,
+    pub active: Option<SourceKey>,
+    pub sources: Vec<SourceSummary>,
+}
+
+/// Result of feeding an update through arbitration
+#[derive(Debug, Default)]
+pub struct Arbitration {
+    /// Update to push through the track pipeline, if the active source produced one
+    pub forward: Option<TrackUpdate>,
+    /// The active source changed; consumers must treat `forward` as a new track
+    pub switched: bool,
+    /// The active source went away and no other player took over
+    pub ended: bool,
+}
+
+/// Tracks every player reported by connected clients and picks the one to follow
+#[derive(Debug, Default)]
+pub struct SourceRegistry {
+    sources: HashMap<SourceKey, SourceEntry>,
+    policy: ArbitrationPolicy,
+    selected: Option<SourceKey>,
+    active: Option<SourceKey>,
+}
+
+impl SourceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn active(&self) -> Option<&SourceKey> {
+        self.active.as_ref()
+    }
+
+    /// Record an update from `client_id`; `stopped` removes the player
+    pub fn record(
+        &mut self,
+        client_id: &str,
+        update: TrackUpdate,
+        stopped: bool,
+        now: u64,
+    ) -> Arbitration {
+        let key = SourceKey::new(client_id, &update.source);
+
+        if stopped {
+            self.sources.remove(&key);
+            let was_active = self.active.as_ref() == Some(&key);
+            let mut arbitration = self.rearbitrate(None);
+            if was_active && self.active.is_none() {
+                // Last player went away; let consumers see it stop
+                arbitration.forward = Some(TrackUpdate {
+                    is_playing: false,
+                    ..update
+                });
+            }
+            return arbitration;
+        }
+
+        let started_playing_at = match self.sources.get(&key) {
+            Some(previous) if previous.last_update.is_playing && update.is_playing => {
+                previous.started_playing_at
+            }
+            Some(previous) if !update.is_playing => previous.started_playing_at,
+            _ if update.is_playing => Some(now),
+            _ => None,
+        };
+
+        self.sources.insert(
+            key.clone(),
+            SourceEntry {
+                last_update: update,
+                started_playing_at,
+                last_seen: now,
+            },
+        );
+
+        self.rearbitrate(Some(&key))
+    }
+
+    /// Forget every player reported by a disconnected client
+    pub fn remove_client(&mut self, client_id: &str) -> Arbitration {
+        self.sources.retain(|key, _| key.client_id != client_id);
+        self.rearbitrate(None)
+    }
+
+    pub fn set_policy(&mut self, policy: ArbitrationPolicy) -> Arbitration {
+        self.policy = policy;
+        self.rearbitrate(None)
+    }
+
+    /// Switch to a specific player and stay there
+    pub fn select(&mut self, key: SourceKey) -> Result<Arbitration, String> {
+        if !self.sources.contains_key(&key) {
+            return Err(format!(
+                "Unknown source '{}' on client {}",
+                key.source, key.client_id
+            ));
+        }
+        self.policy = ArbitrationPolicy::Manual;
+        self.selected = Some(key);
+        Ok(self.rearbitrate(None))
+    }
+
+    /// Player reporting `source`, preferring the active one, then the most recently seen
+    pub fn find(&self, source: &str) -> Option<SourceKey> {
+        if let Some(active) = self.active.as_ref().filter(|key| key.source == source) {
+            return Some(active.clone());
+        }
+        self.sources
+            .iter()
+            .filter(|(key, _)| key.source == source)
+            .max_by_key(|(_, entry)| entry.last_seen)
+            .map(|(key, _)| key.clone())
+    }
+
+    pub fn snapshot(&self) -> SourcesSnapshot {
+        let mut sources: Vec<SourceSummary> = self
+            .sources
+            .iter()
+            .map(|(key, entry)| SourceSummary {
+                key: key.clone(),
+                title: entry.last_update.title.clone(),
+                artist: entry.last_update.artist.clone(),
+                url: entry.last_update.url.clone(),
+                is_playing: entry.last_update.is_playing,
+                current_time: entry.last_update.current_time,
+                last_seen: entry.last_seen,
+                active: self.active.as_ref() == Some(key),
+            })
+            .collect();
+        sources.sort_by_key(|summary| std::cmp::Reverse(summary.last_seen));
+
+        SourcesSnapshot {
+            policy: self.policy.clone(),
+            active: self.active.clone(),
+            sources,
+        }
+    }
+
+    fn rearbitrate(&mut self, updated: Option<&SourceKey>) -> Arbitration {
+        let previous = self.active.take();
+        self.active = self.pick(previous.as_ref());
+        let switched = previous != self.active;
+        let ended = previous.is_some() && self.active.is_none();
+
+        let forward = match &self.active {
+            Some(active) if switched || updated == Some(active) => self
+                .sources
+                .get(active)
+                .map(|entry| entry.last_update.clone()),
+            _ => None,
+        };
+
+        Arbitration {
+            forward,
+            switched,
+            ended,
+        }
+    }
+
+    fn pick(&self, current: Option<&SourceKey>) -> Option<SourceKey> {
+        match &self.policy {
+            ArbitrationPolicy::Manual => {
+                if let Some(selected) = &self.selected {
+                    if self.sources.contains_key(selected) {
+                        return Some(selected.clone());
+                    }
+                }
+            }
+            ArbitrationPolicy::Pinned { source } => {
+                if let Some(key) = self.most_recent(current, |key| &key.source == source) {
+                    return Some(key);
+                }
+            }
+            ArbitrationPolicy::MostRecentlyPlaying => {}
+        }
+        self.most_recent(current, |_| true)
+    }
+
+    /// Most recently started player; when nothing plays, stay put rather than flip
+    fn most_recent<F>(&self, current: Option<&SourceKey>, filter: F) -> Option<SourceKey>
+    where
+        F: Fn(&SourceKey) -> bool,
+    {
+        let candidates = self.sources.iter().filter(|(key, _)| filter(key));
+
+        let playing = candidates
+            .clone()
+            .filter(|(_, entry)| entry.last_update.is_playing)
+            .max_by_key(|(_, entry)| entry.started_playing_at);
+        if let Some((key, _)) = playing {
+            return Some(key.clone());
+        }
+
+        if let Some(current) = current {
+            if filter(current) && self.sources.contains_key(current) {
+                return Some(current.clone());
+            }
+        }
+
+        candidates
+            .max_by_key(|(_, entry)| entry.last_seen)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+pub fn emit_sources_changed(app_handle: &tauri::AppHandle, registry: &SourceRegistry) {
+    if let Err(e) = app_handle.emit("active-source-changed", registry.snapshot()) {
+        log::error!("Failed to emit active-source-changed event: {}", e);
+    }
+}
+
+#[tauri::command]
+pub async fn list_track_sources(
+    source_state: State<'_, SourceState>,
+) -> Result<SourcesSnapshot, String> {
+    Ok(source_state.lock().await.snapshot())
+}
+
+/// Switches go through the track pipeline so they are ordered with track updates;
+/// an update arbitrated before the switch cannot be forwarded after it
+async fn switch_sources(
+    pipeline: &TrackPipeline,
+    event: impl FnOnce(SourcesReply) -> PipelineEvent,
+) -> Result<SourcesSnapshot, String> {
+    let (reply, response) = tokio::sync::oneshot::channel();
+    pipeline
+        .send(event(reply))
+        .map_err(|_| "Track pipeline is not running".to_string())?;
+    response
+        .await
+        .map_err(|_| "Track pipeline is not running".to_string())?
+}
+
+#[tauri::command]
+pub async fn select_track_source(
+    client_id: String,
+    source: String,
+    pipeline: State<'_, TrackPipeline>,
+) -> Result<SourcesSnapshot, String> {
+    info!("Selecting track source {} on client {}", source, client_id);
+    let key = SourceKey::new(&client_id, &source);
+    switch_sources(&pipeline, |reply| PipelineEvent::Select(key, reply)).await
+}
+
+#[tauri::command]
+pub async fn set_source_policy(
+    policy: ArbitrationPolicy,
+    pipeline: State<'_, TrackPipeline>,
+) -> Result<SourcesSnapshot, String> {
+    info!("Source arbitration policy: {:?}", policy);
+    switch_sources(&pipeline, |reply| PipelineEvent::SetPolicy(policy, reply)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(source: &str, title: &str, is_playing: bool) -> TrackUpdate {
+        TrackUpdate {
+            title: title.to_string(),
+            artist: "Artist".to_string(),
+            thumbnail: None,
+            source: source.to_string(),
+            url: format!("https://{}.example/{}", source, title),
+            timestamp: 0,
+            is_playing,
+            current_time: Some(10.0),
+            duration: Some(200.0),
+        }
+    }
+
+    #[test]
+    fn follows_the_most_recently_started_player() {
+        let mut registry = SourceRegistry::new();
+        registry.record("a", update("spotify", "One", true), false, 1);
+
+        // A second tab starts playing later and takes over
+        let arbitration = registry.record("a", update("youtube", "Two", true), false, 2);
+        assert!(arbitration.switched);
+        assert_eq!(arbitration.forward.unwrap().title, "Two");
+
+        // Progress from the background player is not forwarded
+        let arbitration = registry.record("a", update("spotify", "One", true), false, 3);
+        assert!(arbitration.forward.is_none());
+        assert_eq!(registry.active().unwrap().source, "youtube");
+    }
+
+    #[test]
+    fn pausing_everything_keeps_the_active_source() {
+        let mut registry = SourceRegistry::new();
+        registry.record("a", update("spotify", "One", true), false, 1);
+        registry.record("b", update("youtube", "Two", false), false, 2);
+
+        let arbitration = registry.record("a", update("spotify", "One", false), false, 3);
+        assert!(!arbitration.switched);
+        assert_eq!(registry.active().unwrap().source, "spotify");
+    }
+
+    #[test]
+    fn pinned_source_wins_while_present() {
+        let mut registry = SourceRegistry::new();
+        registry.set_policy(ArbitrationPolicy::Pinned {
+            source: "spotify".to_string(),
+        });
+        registry.record("a", update("spotify", "One", false), false, 1);
+        registry.record("a", update("youtube", "Two", true), false, 2);
+        assert_eq!(registry.active().unwrap().source, "spotify");
+
+        let arbitration = registry.record("a", update("spotify", "One", false), true, 3);
+        assert!(arbitration.switched);
+        assert_eq!(registry.active().unwrap().source, "youtube");
+    }
+
+    #[test]
+    fn manual_selection_falls_back_when_client_leaves() {
+        let mut registry = SourceRegistry::new();
+        registry.record("a", update("spotify", "One", true), false, 1);
+        registry.record("b", update("youtube", "Two", true), false, 2);
+
+        let arbitration = registry
+            .select(SourceKey::new("a", "spotify"))
+            .expect("known source");
+        assert!(arbitration.switched);
+        assert_eq!(arbitration.forward.unwrap().title, "One");
+
+        let arbitration = registry.remove_client("a");
+        assert!(arbitration.switched);
+        assert!(!arbitration.ended);
+        assert_eq!(registry.active().unwrap().client_id, "b");
+        assert!(registry.select(SourceKey::new("a", "spotify")).is_err());
+    }
+
+    #[test]
+    fn stopping_the_last_player_forwards_a_stopped_update() {
+        let mut registry = SourceRegistry::new();
+        registry.record("a", update("spotify", "One", true), false, 1);
+
+        let arbitration = registry.record("a", update("spotify", "One", true), true, 2);
+        assert!(arbitration.switched);
+        assert!(arbitration.ended);
+        assert!(!arbitration.forward.unwrap().is_playing);
+        assert!(registry.active().is_none());
+    }
+}
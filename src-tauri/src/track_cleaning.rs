@@ -1,3 +1,4 @@
+use crate::settings::TrackCleaningSettings;
 use log::{debug, info};
 use regex::Regex;
 use reqwest::Client;
@@ -582,8 +583,11 @@ pub fn remove_artist_from_track(track_name: &str, artist_name: &str) -> String {
 }
 
 /// Main track cleaning function - tries LLM first, falls back to regex
-pub async fn clean_track_name(track_name: &str) -> String {
-    match clean_track_name_llm(track_name).await {
+pub async fn clean_track_name(track_name: &str, settings: &TrackCleaningSettings) -> String {
+    if !settings.llm_enabled {
+        return clean_track_name_sync(track_name);
+    }
+    match clean_track_name_llm(track_name, settings).await {
         Ok(cleaned) => cleaned,
         Err(e) => {
             debug!("LLM cleaning failed, using regex fallback: {}", e);
@@ -593,7 +597,10 @@ pub async fn clean_track_name(track_name: &str) -> String {
 }
 
 /// Cleans track name using LLM when GitHub token is available
-pub async fn clean_track_name_llm(track_name: &str) -> Result<String, String> {
+pub async fn clean_track_name_llm(
+    track_name: &str,
+    settings: &TrackCleaningSettings,
+) -> Result<String, String> {
     // Load environment variables
     dotenv::dotenv().ok();
 
@@ -605,15 +612,11 @@ pub async fn clean_track_name_llm(track_name: &str) -> Result<String, String> {
         }
     };
 
-    // Best performing models from testing
-    let models = [
-        "meta/Meta-Llama-3.1-8B-Instruct",
-        "meta/Llama-4-Maverick-17B-128E-Instruct-FP8",
-        "mistral-ai/Ministral-3B",
-    ];
+    let models = &settings.llm_models;
+    let timeout = Duration::from_secs(settings.llm_timeout_secs);
 
     for (i, model_id) in models.iter().enumerate() {
-        match try_llm_cleaning(track_name, model_id, &github_token).await {
+        match try_llm_cleaning(track_name, model_id, &github_token, timeout).await {
             Ok(cleaned) => {
                 info!(
                     "LLM cleaning successful with {}: '{}' -> '{}'",
@@ -646,9 +649,10 @@ async fn try_llm_cleaning(
     track_name: &str,
     model_id: &str,
     github_token: &str,
+    timeout: Duration,
 ) -> Result<String, String> {
     let client = Client::builder()
-        .timeout(Duration::from_secs(30))
+        .timeout(timeout)
         .build()
         .map_err(|e| e.to_string())?;
 
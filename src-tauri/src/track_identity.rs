@@ -0,0 +1,337 @@
+//! Decides when the followed player moved on to another track.
+//!
+//! Updates are compared by identity (source, video id, title, artist, duration) rather
+//! than by playback position, so joining mid-song or a player that starts reporting at
+//! 0.3s still counts as a new track. A different identity must be reported twice in a
+//! row before it replaces the current track, which absorbs pages that update title and
+//! artist one after the other.
+
+use crate::protocol::TrackUpdate;
+use serde::Serialize;
+
+/// A jump larger than this against the extrapolated position is reported as a seek
+const SEEK_THRESHOLD_SECS: f64 = 3.0;
+/// Durations reported for the same track by the same player differ by less than this
+const DURATION_TOLERANCE_SECS: f64 = 2.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackIdentity {
+    pub source: String,
+    /// Player-specific media id, only taken from URLs that always name the playing media
+    pub media_id: Option<String>,
+    pub title: String,
+    pub artist: String,
+    pub duration: Option<f64>,
+}
+
+impl TrackIdentity {
+    pub fn from_update(update: &TrackUpdate) -> Self {
+        Self {
+            source: update.source.clone(),
+            media_id: media_id(&update.url),
+            title: update.title.trim().to_string(),
+            artist: update.artist.trim().to_string(),
+            duration: update.duration.filter(|duration| *duration > 0.0),
+        }
+    }
+
+    pub fn matches(&self, other: &TrackIdentity) -> bool {
+        if self.source != other.source {
+            return false;
+        }
+        if let (Some(a), Some(b)) = (&self.media_id, &other.media_id) {
+            return a == b;
+        }
+
+        let durations_agree = match (self.duration, other.duration) {
+            (Some(a), Some(b)) => (a - b).abs() <= DURATION_TOLERANCE_SECS,
+            _ => true, // players often report the duration only once it has loaded
+        };
+        self.title.eq_ignore_ascii_case(&other.title)
+            && self.artist.eq_ignore_ascii_case(&other.artist)
+            && durations_agree
+    }
+}
+
+/// YouTube's `v` parameter always names the playing video; other players' page URLs
+/// can stay put while the queue moves on, so they are not trusted
+fn media_id(url: &str) -> Option<String> {
+    if !url.contains("youtube.com/") {
+        return None;
+    }
+    let query = url.split_once('?')?.1;
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("v="))
+        .map(|id| id.split('#').next().unwrap_or(id).to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// Emitted as `track-started`, `track-ended` and `track-seeked`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TrackChange {
+    Started {
+        track: TrackIdentity,
+        position: Option<f64>,
+    },
+    Ended {
+        track: TrackIdentity,
+    },
+    Seeked {
+        track: TrackIdentity,
+        from: f64,
+        to: f64,
+    },
+}
+
+impl TrackChange {
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            TrackChange::Started { .. } => "track-started",
+            TrackChange::Ended { .. } => "track-ended",
+            TrackChange::Seeked { .. } => "track-seeked",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    time: f64,
+    at: u64,
+    playing: bool,
+}
+
+impl Position {
+    /// Where playback should be at `now` if nobody touched it
+    fn extrapolate(&self, now: u64) -> f64 {
+        if self.playing {
+            self.time + now.saturating_sub(self.at) as f64 / 1000.0
+        } else {
+            self.time
+        }
+    }
+}
+
+/// Follows the active player's updates and reports track changes and seeks
+#[derive(Debug, Default)]
+pub struct TrackChangeDetector {
+    current: Option<TrackIdentity>,
+    /// A different track seen once, waiting for confirmation
+    candidate: Option<TrackIdentity>,
+    position: Option<Position>,
+}
+
+impl TrackChangeDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed an update from the active player; `switched` means the player itself changed,
+    /// which confirms a new track right away
+    pub fn observe(&mut self, update: &TrackUpdate, switched: bool, now: u64) -> Vec<TrackChange> {
+        let mut changes = Vec::new();
+        if update.title.trim().is_empty() {
+            return changes; // pages report an empty title while loading
+        }
+
+        let identity = TrackIdentity::from_update(update);
+        let same_track = self
+            .current
+            .as_ref()
+            .is_some_and(|current| current.matches(&identity));
+
+        if same_track {
+            self.candidate = None;
+            if let (Some(position), Some(time)) = (self.position, update.current_time) {
+                let expected = position.extrapolate(now);
+                if (time - expected).abs() > SEEK_THRESHOLD_SECS {
+                    changes.push(TrackChange::Seeked {
+                        track: identity.clone(),
+                        from: expected,
+                        to: time,
+                    });
+                }
+            }
+            // Later updates fill in details such as the duration
+            self.current = Some(identity);
+        } else {
+            let confirmed = self.current.is_none()
+                || switched
+                || self
+                    .candidate
+                    .as_ref()
+                    .is_some_and(|candidate| candidate.matches(&identity));
+            if !confirmed {
+                self.candidate = Some(identity);
+                return changes;
+            }
+
+            self.candidate = None;
+            if let Some(previous) = self.current.take() {
+                changes.push(TrackChange::Ended { track: previous });
+            }
+            changes.push(TrackChange::Started {
+                track: identity.clone(),
+                position: update.current_time,
+            });
+            self.current = Some(identity);
+        }
+
+        self.position = update.current_time.map(|time| Position {
+            time,
+            at: now,
+            playing: update.is_playing,
+        });
+        changes
+    }
+
+    /// The followed player went away without another taking over
+    pub fn end(&mut self) -> Option<TrackChange> {
+        self.candidate = None;
+        self.position = None;
+        self.current
+            .take()
+            .map(|track| TrackChange::Ended { track })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::InboundMessage;
+    use crate::recording::{parse_recording, RecordedEvent};
+
+    fn update(title: &str, artist: &str, time: f64) -> TrackUpdate {
+        TrackUpdate {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            thumbnail: None,
+            source: "spotify".to_string(),
+            url: "https://open.spotify.com/".to_string(),
+            timestamp: 0,
+            is_playing: true,
+            current_time: Some(time),
+            duration: Some(200.0),
+        }
+    }
+
+    fn names(changes: &[TrackChange]) -> Vec<&'static str> {
+        changes.iter().map(TrackChange::event_name).collect()
+    }
+
+    #[test]
+    fn joining_mid_song_starts_the_track() {
+        let mut detector = TrackChangeDetector::new();
+        let changes = detector.observe(&update("One", "Band", 95.4), false, 0);
+        assert_eq!(names(&changes), vec!["track-started"]);
+        assert!(matches!(&changes[0], TrackChange::Started { track, .. } if track.title == "One"));
+
+        // Progress on the same track is not a change
+        assert!(detector
+            .observe(&update("One", "Band", 96.4), false, 1000)
+            .is_empty());
+    }
+
+    #[test]
+    fn a_new_track_needs_confirmation() {
+        let mut detector = TrackChangeDetector::new();
+        detector.observe(&update("One", "Band", 198.0), false, 0);
+
+        // Title updated before the artist: one odd frame is ignored
+        assert!(detector
+            .observe(&update("Two", "Band", 0.3), false, 1000)
+            .is_empty());
+        assert!(detector
+            .observe(&update("Two", "Other Band", 0.6), false, 1300)
+            .is_empty());
+        let changes = detector.observe(&update("Two", "Other Band", 1.6), false, 2300);
+        assert_eq!(names(&changes), vec!["track-ended", "track-started"]);
+        assert_eq!(
+            changes[1],
+            TrackChange::Started {
+                track: TrackIdentity::from_update(&update("Two", "Other Band", 1.6)),
+                position: Some(1.6),
+            }
+        );
+    }
+
+    #[test]
+    fn source_switch_confirms_immediately() {
+        let mut detector = TrackChangeDetector::new();
+        detector.observe(&update("One", "Band", 10.0), false, 0);
+        let mut other = update("One", "Band", 40.0);
+        other.source = "youtube-music".to_string();
+        let changes = detector.observe(&other, true, 500);
+        assert_eq!(names(&changes), vec!["track-ended", "track-started"]);
+    }
+
+    #[test]
+    fn reports_seeks_against_the_extrapolated_position() {
+        let mut detector = TrackChangeDetector::new();
+        detector.observe(&update("One", "Band", 10.0), false, 0);
+        assert!(detector
+            .observe(&update("One", "Band", 12.1), false, 2000)
+            .is_empty());
+
+        let changes = detector.observe(&update("One", "Band", 60.0), false, 3000);
+        match &changes[..] {
+            [TrackChange::Seeked { from, to, .. }] => {
+                assert!((from - 13.1).abs() < 0.01);
+                assert_eq!(*to, 60.0);
+            }
+            other => panic!("expected a seek, got {:?}", other),
+        }
+
+        assert_eq!(
+            names(&detector.end().into_iter().collect::<Vec<_>>()),
+            vec!["track-ended"]
+        );
+        assert!(detector.end().is_none());
+    }
+
+    #[test]
+    fn youtube_video_ids_decide_identity() {
+        let mut first = update("Live Version", "Band", 0.0);
+        first.url = "https://music.youtube.com/watch?v=abc123&list=RD".to_string();
+        let mut renamed = first.clone();
+        renamed.title = "Live Version (Remastered)".to_string();
+        let mut other = first.clone();
+        other.url = "https://music.youtube.com/watch?v=xyz789".to_string();
+
+        let first = TrackIdentity::from_update(&first);
+        assert_eq!(first.media_id.as_deref(), Some("abc123"));
+        assert!(first.matches(&TrackIdentity::from_update(&renamed)));
+        assert!(!first.matches(&TrackIdentity::from_update(&other)));
+    }
+
+    #[test]
+    fn detects_changes_in_a_recorded_session() {
+        // A session that joined mid-song and moved on to a track starting at 0.3s
+        let recording = r#"
+{"at_ms":0,"client_id":"a","kind":"connected"}
+{"at_ms":10,"client_id":"a","kind":"message","message":{"message_type":"TRACK_DETECTED","data":{"title":"One","artist":"Band","thumbnail":null,"source":"spotify","url":"u","timestamp":1,"is_playing":true,"currentTime":95.0,"duration":200.0},"timestamp":1}}
+{"at_ms":1010,"client_id":"a","kind":"message","message":{"message_type":"TRACK_PROGRESS","data":{"title":"One","artist":"Band","thumbnail":null,"source":"spotify","url":"u","timestamp":2,"is_playing":true,"currentTime":96.0,"duration":200.0},"timestamp":2}}
+{"at_ms":2010,"client_id":"a","kind":"message","message":{"message_type":"TRACK_DETECTED","data":{"title":"Two","artist":"Band","thumbnail":null,"source":"spotify","url":"u","timestamp":3,"is_playing":true,"currentTime":0.3,"duration":180.0},"timestamp":3}}
+{"at_ms":3010,"client_id":"a","kind":"message","message":{"message_type":"TRACK_PROGRESS","data":{"title":"Two","artist":"Band","thumbnail":null,"source":"spotify","url":"u","timestamp":4,"is_playing":true,"currentTime":1.3,"duration":180.0},"timestamp":4}}
+"#;
+
+        let mut detector = TrackChangeDetector::new();
+        let mut events = Vec::new();
+        for frame in parse_recording(recording).unwrap() {
+            let RecordedEvent::Message { message } = frame.event else {
+                continue;
+            };
+            if let InboundMessage::TrackDetected(update) | InboundMessage::TrackProgress(update) =
+                message.payload
+            {
+                events.extend(names(&detector.observe(&update, false, frame.at_ms)));
+            }
+        }
+        assert_eq!(
+            events,
+            vec!["track-started", "track-ended", "track-started"]
+        );
+    }
+}
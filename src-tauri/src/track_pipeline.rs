@@ -0,0 +1,177 @@
+//! Single ordered path from every track source (extension clients, native
+//! players) through source arbitration into the shared track and lyrics state.
+
+use crate::auto_hide;
+use crate::line_tracking::{emit_active_lyrics, emit_line_change, emit_line_progress};
+use crate::protocol::{now_millis, InboundMessage, TrackUpdate};
+use crate::sources::{
+    emit_sources_changed, Arbitration, ArbitrationPolicy, SourceKey, SourcesSnapshot,
+};
+use crate::track_identity::TrackChange;
+use crate::types::{LyricsState, SourceState, TrackDetectorState, TrackInfo, TrackState};
+use log::{debug, error, info};
+use std::time::Instant;
+use tauri::{Emitter, Manager};
+use tokio::sync::{mpsc, oneshot};
+
+/// Answers a source switch with the sources as they are after it
+pub type SourcesReply = oneshot::Sender<Result<SourcesSnapshot, String>>;
+
+/// Track messages and disconnects from any source client, and source switches by the
+/// user, processed in arrival order
+pub enum PipelineEvent {
+    Track(String, InboundMessage),
+    ClientGone(String),
+    /// The user picked a player, through a command or the tray
+    Select(SourceKey, SourcesReply),
+    SetPolicy(ArbitrationPolicy, SourcesReply),
+}
+
+/// Feed every client's track messages through source arbitration; only the
+/// active source reaches the shared track state
+pub async fn run_track_pipeline(
+    app_handle: tauri::AppHandle,
+    mut events: mpsc::UnboundedReceiver<PipelineEvent>,
+) {
+    let source_state = app_handle.state::<SourceState>().inner().clone();
+
+    while let Some(event) = events.recv().await {
+        let arbitration = {
+            let mut registry = source_state.lock().await;
+            let mut reply = None;
+            let arbitration = match event {
+                PipelineEvent::Track(client_id, message) => match message {
+                    InboundMessage::TrackStopped(update) => {
+                        registry.record(&client_id, update, true, now_millis())
+                    }
+                    InboundMessage::TrackDetected(update)
+                    | InboundMessage::TrackPaused(update)
+                    | InboundMessage::TrackProgress(update) => {
+                        registry.record(&client_id, update, false, now_millis())
+                    }
+                    _ => continue,
+                },
+                PipelineEvent::ClientGone(client_id) => registry.remove_client(&client_id),
+                PipelineEvent::Select(key, respond) => match registry.select(key) {
+                    Ok(arbitration) => {
+                        reply = Some(respond);
+                        arbitration
+                    }
+                    Err(e) => {
+                        let _ = respond.send(Err(e));
+                        continue;
+                    }
+                },
+                PipelineEvent::SetPolicy(policy, respond) => {
+                    reply = Some(respond);
+                    registry.set_policy(policy)
+                }
+            };
+            if arbitration.switched {
+                if let Some(active) = registry.active() {
+                    info!(
+                        "Active source: {} on client {}",
+                        active.source, active.client_id
+                    );
+                }
+            }
+            // Selections change the policy even when the active source stays
+            if arbitration.switched || reply.is_some() {
+                emit_sources_changed(&app_handle, &registry);
+            }
+            if let Some(reply) = reply {
+                let _ = reply.send(Ok(registry.snapshot()));
+            }
+            arbitration
+        };
+
+        forward_track_update(&app_handle, arbitration).await;
+    }
+}
+
+/// Apply the active source's update to the shared track state and notify the frontend
+pub async fn forward_track_update(app_handle: &tauri::AppHandle, arbitration: Arbitration) {
+    let changes = {
+        let detector_state = app_handle.state::<TrackDetectorState>();
+        let mut detector = detector_state.lock().await;
+        let mut changes = match &arbitration.forward {
+            Some(update) => detector.observe(update, arbitration.switched, now_millis()),
+            None => Vec::new(),
+        };
+        if arbitration.ended {
+            changes.extend(detector.end());
+        }
+        changes
+    };
+
+    for change in &changes {
+        if let (TrackChange::Started { .. }, Some(update)) = (change, &arbitration.forward) {
+            start_track(app_handle, update).await;
+        }
+        debug!("Emitting {}", change.event_name());
+        if let Err(e) = app_handle.emit(change.event_name(), change) {
+            error!("Failed to emit {} event: {}", change.event_name(), e);
+        }
+    }
+    #[cfg(desktop)]
+    crate::tray::refresh(app_handle, false).await;
+
+    let track_update = match arbitration.forward {
+        Some(track_update) => track_update,
+        None => return,
+    };
+
+    // Always emit playback state and time updates (they change frequently)
+    if let Err(e) = app_handle.emit("playback-state", &track_update.is_playing) {
+        error!("Failed to emit playback-state event: {}", e);
+    }
+
+    if let Some(current_time) = track_update.current_time {
+        if let Err(e) = app_handle.emit(
+            "track-time-update",
+            &serde_json::json!({
+                "currentTime": current_time,
+                "duration": track_update.duration.unwrap_or(0.0),
+                "isPlaying": track_update.is_playing
+            }),
+        ) {
+            error!("Failed to emit track-time-update event: {}", e);
+        }
+
+        let (change, progress) = {
+            let lyrics_state = app_handle.state::<LyricsState>();
+            let mut tracker = lyrics_state.lock().await;
+            let change = tracker.update(current_time);
+            (change, tracker.progress(Instant::now()))
+        };
+        if let Some(change) = change {
+            emit_line_change(app_handle, &change);
+        }
+        if let Some(progress) = progress {
+            emit_line_progress(app_handle, &progress);
+        }
+    }
+}
+
+/// Make `update` the current track; the lyrics of the previous one no longer apply
+async fn start_track(app_handle: &tauri::AppHandle, update: &TrackUpdate) {
+    let track_info = TrackInfo {
+        title: update.title.clone(),
+        artist: update.artist.clone(),
+        album: None,
+        duration: update.duration,
+        thumbnail: update.thumbnail.clone(),
+    };
+    info!(
+        "Track changed: '{}' by '{}'",
+        track_info.title, track_info.artist
+    );
+
+    *app_handle.state::<TrackState>().lock().await = Some(track_info.clone());
+    auto_hide::track_started(app_handle).await;
+    app_handle.state::<LyricsState>().lock().await.clear();
+    emit_active_lyrics(app_handle, &[]);
+    if let Err(e) = app_handle.emit("track-updated", &track_info) {
+        error!("Failed to emit track-updated event: {}", e);
+    }
+}
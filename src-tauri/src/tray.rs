@@ -0,0 +1,328 @@
+//! The tray icon and its menu.
+//!
+//! The menu shows the current track with playback, lyrics and window actions, a source
+//! picker and quit. It is rebuilt whenever what it shows changes; the tooltip follows
+//! the current lyric line.
+
+use crate::shortcuts::{self, ShortcutAction, OFFSET_STEP_SECS};
+use crate::sources::{select_track_source, SourceKey};
+use crate::types::{ClickThroughState, SourceState, TrackState, TrayState};
+use crate::window_management::{quit_app, toggle_window_visibility};
+use log::{error, info};
+use tauri::menu::{CheckMenuItemBuilder, Menu, MenuBuilder, MenuItemBuilder, SubmenuBuilder};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+use tauri::{Manager, Runtime};
+
+pub const TRAY_ID: &str = "lyryc-tray";
+const SOURCE_PREFIX: &str = "source:";
+const QUIT_ID: &str = "quit";
+/// Windows cuts tooltips off at 128 characters
+const TOOLTIP_MAX_CHARS: usize = 127;
+
+/// What the menu shows; the menu is only rebuilt when this changes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrayView {
+    pub track: Option<(String, String)>, // title, artist
+    pub playing: bool,
+    pub click_through: bool,
+    pub sources: Vec<(SourceKey, bool)>, // with whether it is the active one
+}
+
+impl TrayView {
+    fn now_playing(&self) -> String {
+        match &self.track {
+            Some((title, artist)) => format!("{} — {}", title, artist),
+            None => "Nothing playing".to_string(),
+        }
+    }
+
+    /// The source name, with its client when several clients report the same one
+    fn source_label(&self, key: &SourceKey) -> String {
+        let same_name = self
+            .sources
+            .iter()
+            .filter(|(other, _)| other.source == key.source)
+            .count();
+        if same_name > 1 {
+            format!("{} ({})", key.source, key.client_id)
+        } else {
+            key.source.clone()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TrayCommand {
+    Action(ShortcutAction),
+    Source(SourceKey),
+    Quit,
+}
+
+fn source_menu_id(key: &SourceKey) -> String {
+    format!("{}{}/{}", SOURCE_PREFIX, key.client_id, key.source)
+}
+
+/// Menu items use the shortcut action names as ids, so both run the same actions
+pub fn parse_menu_id(id: &str) -> Option<TrayCommand> {
+    if id == QUIT_ID {
+        return Some(TrayCommand::Quit);
+    }
+    if let Some(key) = id.strip_prefix(SOURCE_PREFIX) {
+        let (client_id, source) = key.split_once('/')?;
+        return Some(TrayCommand::Source(SourceKey::new(client_id, source)));
+    }
+    ShortcutAction::ALL
+        .into_iter()
+        .find(|action| action.name() == id)
+        .map(TrayCommand::Action)
+}
+
+pub fn tooltip(view: &TrayView, line: Option<&str>) -> String {
+    let text = match (line.filter(|line| !line.trim().is_empty()), &view.track) {
+        (Some(line), _) => format!("♪ {}", line),
+        (None, Some(_)) => view.now_playing(),
+        (None, None) => "Lyryc - Clean Lyric Viewer".to_string(),
+    };
+    if text.chars().count() <= TOOLTIP_MAX_CHARS {
+        return text;
+    }
+    let mut truncated: String = text.chars().take(TOOLTIP_MAX_CHARS - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn build_menu<R: Runtime, M: Manager<R>>(manager: &M, view: &TrayView) -> tauri::Result<Menu<R>> {
+    let has_track = view.track.is_some();
+    let action = |action: ShortcutAction, text: &str, enabled: bool| {
+        MenuItemBuilder::with_id(action.name(), text)
+            .enabled(enabled)
+            .build(manager)
+    };
+
+    let now_playing = MenuItemBuilder::with_id("now-playing", view.now_playing())
+        .enabled(false)
+        .build(manager)?;
+    let play_pause = action(
+        ShortcutAction::PlayPause,
+        if view.playing { "Pause" } else { "Play" },
+        has_track,
+    )?;
+    let next = action(ShortcutAction::NextTrack, "Next", has_track)?;
+    let previous = action(ShortcutAction::PreviousTrack, "Previous", has_track)?;
+    let research = action(
+        ShortcutAction::ResearchLyrics,
+        "Search Lyrics Again",
+        has_track,
+    )?;
+    let offset_increase = action(
+        ShortcutAction::OffsetIncrease,
+        &format!("Lyrics Offset +{:.1}s", OFFSET_STEP_SECS),
+        has_track,
+    )?;
+    let offset_decrease = action(
+        ShortcutAction::OffsetDecrease,
+        &format!("Lyrics Offset -{:.1}s", OFFSET_STEP_SECS),
+        has_track,
+    )?;
+    let click_through =
+        CheckMenuItemBuilder::with_id(ShortcutAction::ToggleClickThrough.name(), "Click-Through")
+            .checked(view.click_through)
+            .build(manager)?;
+    let visibility = action(ShortcutAction::ToggleVisibility, "Show/Hide Lyrics", true)?;
+
+    let mut source_menu = SubmenuBuilder::new(manager, "Source").enabled(!view.sources.is_empty());
+    for (key, active) in &view.sources {
+        let item = CheckMenuItemBuilder::with_id(source_menu_id(key), view.source_label(key))
+            .checked(*active)
+            .build(manager)?;
+        source_menu = source_menu.item(&item);
+    }
+    let source_menu = source_menu.build()?;
+
+    MenuBuilder::new(manager)
+        .items(&[&now_playing])
+        .separator()
+        .items(&[&play_pause, &next, &previous])
+        .separator()
+        .items(&[&research, &offset_increase, &offset_decrease])
+        .separator()
+        .items(&[&click_through, &visibility, &source_menu])
+        .separator()
+        .text(QUIT_ID, "Quit Lyryc")
+        .build()
+}
+
+/// Create the tray icon: a click toggles the lyrics bar, the menu has everything else
+pub fn build(app: &tauri::App) -> tauri::Result<()> {
+    let view = TrayView::default();
+    TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip(tooltip(&view, None))
+        .icon(app.default_window_icon().unwrap().clone())
+        .menu(&build_menu(app, &view)?)
+        .show_menu_on_left_click(false)
+        .on_menu_event(|app_handle, event| handle_menu_event(app_handle, event.id().as_ref()))
+        .on_tray_icon_event(|tray, event| {
+            // The right button opens the menu
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                info!("Tray icon clicked - toggling window visibility");
+                let app_handle = tray.app_handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = toggle_window_visibility(app_handle).await {
+                        error!("Failed to toggle window from tray click: {}", e);
+                    }
+                });
+            }
+        })
+        .build(app)?;
+    Ok(())
+}
+
+fn handle_menu_event(app_handle: &tauri::AppHandle, id: &str) {
+    let Some(command) = parse_menu_id(id) else {
+        return;
+    };
+    info!("Tray menu: {}", id);
+    let app_handle = app_handle.clone();
+    let id = id.to_string();
+    tauri::async_runtime::spawn(async move {
+        let result = match command {
+            TrayCommand::Action(action) => shortcuts::run_action(&app_handle, action).await,
+            TrayCommand::Source(key) => {
+                select_track_source(key.client_id, key.source, app_handle.state())
+                    .await
+                    .map(|_| ())
+            }
+            TrayCommand::Quit => quit_app(app_handle.clone()).await,
+        };
+        if let Err(e) = result {
+            error!("Tray menu action {} failed: {}", id, e);
+        }
+        // Check items flip themselves when clicked; put them back in line with the state
+        refresh(&app_handle, true).await;
+    });
+}
+
+/// Rebuild the menu if the track, playback state, sources or click-through changed
+pub async fn refresh(app_handle: &tauri::AppHandle, force: bool) {
+    let Some(tray) = app_handle.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    let track = app_handle
+        .state::<TrackState>()
+        .lock()
+        .await
+        .as_ref()
+        .map(|track| (track.title.clone(), track.artist.clone()));
+    let snapshot = app_handle.state::<SourceState>().lock().await.snapshot();
+    let mut sources: Vec<(SourceKey, bool)> = snapshot
+        .sources
+        .iter()
+        .map(|summary| (summary.key.clone(), summary.active))
+        .collect();
+    // Sources are reported most recent first; keep the menu order stable
+    sources.sort_by(|(a, _), (b, _)| (&a.source, &a.client_id).cmp(&(&b.source, &b.client_id)));
+    let view = TrayView {
+        track,
+        playing: snapshot
+            .sources
+            .iter()
+            .any(|summary| summary.active && summary.is_playing),
+        click_through: *app_handle.state::<ClickThroughState>().lock().await,
+        sources,
+    };
+
+    {
+        let state = app_handle.state::<TrayState>();
+        let mut current = state.lock().unwrap_or_else(|e| e.into_inner());
+        if *current == view && !force {
+            return;
+        }
+        if current.track != view.track {
+            let _ = tray.set_tooltip(Some(tooltip(&view, None)));
+        }
+        *current = view.clone();
+    }
+    match build_menu(app_handle, &view) {
+        Ok(menu) => {
+            if let Err(e) = tray.set_menu(Some(menu)) {
+                error!("Failed to update tray menu: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to build tray menu: {}", e),
+    }
+}
+
+/// Show the current lyric line in the tray tooltip
+pub fn show_line(app_handle: &tauri::AppHandle, line: Option<&str>) {
+    let Some(tray) = app_handle.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    let text = {
+        let state = app_handle.state::<TrayState>();
+        let view = state.lock().unwrap_or_else(|e| e.into_inner());
+        tooltip(&view, line)
+    };
+    if let Err(e) = tray.set_tooltip(Some(text)) {
+        error!("Failed to update tray tooltip: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_ids_map_back_to_commands() {
+        for action in ShortcutAction::ALL {
+            assert_eq!(
+                parse_menu_id(action.name()),
+                Some(TrayCommand::Action(action))
+            );
+        }
+        let key = SourceKey::new("7c9e6679-7425-40de-944b-e07fc1f90ae7", "spotify");
+        assert_eq!(
+            parse_menu_id(&source_menu_id(&key)),
+            Some(TrayCommand::Source(key))
+        );
+        assert_eq!(parse_menu_id("quit"), Some(TrayCommand::Quit));
+        assert_eq!(parse_menu_id("now-playing"), None);
+    }
+
+    #[test]
+    fn sources_reported_by_several_clients_name_the_client() {
+        let view = TrayView {
+            sources: vec![
+                (SourceKey::new("a", "spotify"), true),
+                (SourceKey::new("b", "spotify"), false),
+                (SourceKey::new("mpris", "vlc"), false),
+            ],
+            ..TrayView::default()
+        };
+        assert_eq!(view.source_label(&view.sources[0].0), "spotify (a)");
+        assert_eq!(view.source_label(&view.sources[2].0), "vlc");
+    }
+
+    #[test]
+    fn tooltip_prefers_the_current_line_and_fits_windows() {
+        let view = TrayView {
+            track: Some(("Blinding Lights".to_string(), "The Weeknd".to_string())),
+            ..TrayView::default()
+        };
+        assert_eq!(tooltip(&view, None), "Blinding Lights — The Weeknd");
+        assert_eq!(
+            tooltip(&view, Some("I've been tryna call")),
+            "♪ I've been tryna call"
+        );
+        assert_eq!(tooltip(&view, Some("  ")), "Blinding Lights — The Weeknd");
+
+        let long = "la ".repeat(100);
+        let text = tooltip(&view, Some(&long));
+        assert_eq!(text.chars().count(), TOOLTIP_MAX_CHARS);
+        assert!(text.ends_with('…'));
+    }
+}
@@ -2,7 +2,22 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::auth::AuthConfig;
+use crate::auto_hide::AutoHidePolicy;
+use crate::http_api::HttpApiHandle;
+use crate::line_tracking::LineTracker;
+#[cfg(target_os = "linux")]
+use crate::mpris::MprisWatcher;
+use crate::recording::SessionRecorder;
+use crate::settings::Settings;
+use crate::shortcuts::ShortcutRegistry;
+use crate::sources::SourceRegistry;
+use crate::track_identity::TrackChangeDetector;
+use crate::track_pipeline::PipelineEvent;
+#[cfg(desktop)]
+use crate::tray::TrayView;
 use crate::websocket::WebSocketServer;
+use crate::window_geometry::WindowGeometries;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TrackInfo {
@@ -23,4 +38,19 @@ pub struct LyricLine {
 // Global state types
 pub type TrackState = Arc<Mutex<Option<TrackInfo>>>;
 pub type WebSocketState = Arc<Mutex<Option<Arc<WebSocketServer>>>>;
+pub type LyricsState = Arc<Mutex<LineTracker>>;
+pub type SourceState = Arc<Mutex<SourceRegistry>>;
+pub type TrackDetectorState = Arc<Mutex<TrackChangeDetector>>;
+pub type TrackPipeline = tokio::sync::mpsc::UnboundedSender<PipelineEvent>;
+#[cfg(target_os = "linux")]
+pub type MprisState = Arc<Mutex<Option<Arc<MprisWatcher>>>>;
+pub type SettingsState = Arc<Mutex<Settings>>;
+pub type HttpApiState = Arc<Mutex<Option<HttpApiHandle>>>; // None while the opt-in API is off
+pub type RecorderState = Arc<std::sync::Mutex<Option<SessionRecorder>>>; // written from connection tasks
+pub type AuthState = Arc<std::sync::RwLock<AuthConfig>>; // read from the sync WebSocket upgrade callback
+pub type ShortcutRegistryState = Arc<std::sync::Mutex<ShortcutRegistry>>; // read from the sync shortcut handler
+#[cfg(desktop)]
+pub type TrayState = Arc<std::sync::Mutex<TrayView>>; // read from the sync line-change path
+pub type WindowGeometryState = Arc<Mutex<WindowGeometries>>;
+pub type AutoHideState = Arc<Mutex<AutoHidePolicy>>;
 pub type ClickThroughState = Arc<Mutex<bool>>; // true = click-through enabled, false = disabled
@@ -1,209 +1,668 @@
+use crate::client_queue::{
+    client_queue, Delivery, QueueClosed, QueueSender, SendStats, DEFAULT_CAPACITY,
+};
+use crate::protocol::{
+    now_millis, parse_inbound, AppMessage, CommandResult, ErrorCode, Hello, InboundMessage,
+    OutboundMessage, PlaybackCommand, PlaybackState, ProtocolError, CAPABILITY_COMMAND_ACK,
+    PROTOCOL_VERSION,
+};
+use crate::recording::{record_event, RecordedEvent};
+use crate::settings::WebSocketSettings;
+use crate::types::{AuthState, RecorderState};
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::sync::Mutex;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tokio_tungstenite::{accept_hdr_async, tungstenite::Message};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ExtensionMessage {
-    pub message_type: String,
-    pub data: serde_json::Value,
-    pub timestamp: u64,
+use tokio::sync::oneshot;
+
+/// A connected extension that completed the handshake
+pub struct ClientHandle {
+    pub sender: QueueSender,
+    pub hello: Hello,
+    /// Remote address, or a description of the local bridge it came through
+    pub peer: String,
+    /// Unix millis
+    pub connected_at: u64,
+    pub activity: Arc<ClientActivity>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TrackUpdate {
-    pub title: String,
-    pub artist: String,
-    pub thumbnail: Option<String>,
-    pub source: String,
-    pub url: String,
-    pub timestamp: u64,
-    pub is_playing: bool,
-    #[serde(rename = "currentTime")]
-    pub current_time: Option<f64>,
-    pub duration: Option<f64>,
+/// Activity of one connection, updated by its connection task
+#[derive(Debug, Default)]
+pub struct ClientActivity {
+    /// Unix millis of the last frame received from the client, pongs included
+    pub last_seen: AtomicU64,
+    /// Unix millis of the last protocol message, 0 before the first one
+    pub last_message: AtomicU64,
+    pub messages_received: AtomicU64,
+    /// Messages answered with an ERROR
+    pub messages_rejected: AtomicU64,
 }
 
-use tokio::sync::mpsc;
+/// Snapshot of a paired client for diagnostics
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientInfo {
+    pub client_id: String,
+    pub peer: String,
+    pub connected_at: u64,
+    pub protocol_version: u32,
+    pub extension_version: Option<String>,
+    pub browser: Option<String>,
+    pub capabilities: Vec<String>,
+    pub last_seen: u64,
+    pub last_message_at: Option<u64>,
+    pub messages_received: u64,
+    pub messages_rejected: u64,
+    pub send_stats: SendStats,
+    /// Players this client reports, filled in from source arbitration
+    pub sources: Vec<String>,
+    /// The client's player that is currently followed, if any
+    pub active_source: Option<String>,
+}
+
+impl ClientHandle {
+    pub fn info(&self, client_id: &str) -> ClientInfo {
+        let last_message = self.activity.last_message.load(Ordering::Relaxed);
+        ClientInfo {
+            client_id: client_id.to_string(),
+            peer: self.peer.clone(),
+            connected_at: self.connected_at,
+            protocol_version: self.hello.protocol_version,
+            extension_version: self.hello.extension_version.clone(),
+            browser: self.hello.browser.clone(),
+            capabilities: self.hello.capabilities.clone(),
+            last_seen: self.activity.last_seen.load(Ordering::Relaxed),
+            last_message_at: (last_message != 0).then_some(last_message),
+            messages_received: self.activity.messages_received.load(Ordering::Relaxed),
+            messages_rejected: self.activity.messages_rejected.load(Ordering::Relaxed),
+            send_stats: self.sender.stats(),
+            sources: Vec::new(),
+            active_source: None,
+        }
+    }
+}
 
-type ClientConnections = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Message>>>>;
+/// Connection lifecycle of paired clients
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    Connected { client_id: String, hello: Hello },
+    Disconnected { client_id: String, reason: String },
+}
+
+type ClientConnections = Arc<Mutex<HashMap<String, ClientHandle>>>;
+
+/// A command waiting for the extension's COMMAND_RESULT
+struct PendingCommand {
+    client_id: String,
+    reply: oneshot::Sender<CommandResult>,
+}
+
+type PendingCommands = Arc<Mutex<HashMap<String, PendingCommand>>>;
+
+/// Client, and optionally the player within it, a command is addressed to
+#[derive(Debug, Clone)]
+pub struct CommandTarget {
+    pub client_id: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandOutcome {
+    pub command_id: String,
+    pub client_id: String,
+    /// False when the client predates acknowledgements and the command was sent blind
+    pub acknowledged: bool,
+    pub state: Option<PlaybackState>,
+}
+/// Receives track messages from paired clients, tagged with the sending client's ID
+type TrackCallback = Arc<dyn Fn(&str, InboundMessage) + Send + Sync>;
+type ClientEventCallback = Arc<dyn Fn(ClientEvent) + Send + Sync>;
+
+/// Port the extension tries first
+pub const DEFAULT_PORT: u16 = 8765;
+/// Ports tried when the preferred one is taken; the extension probes the same range
+pub const FALLBACK_PORTS: RangeInclusive<u16> = 8765..=8774;
+
+/// How long a shutdown waits for close frames to reach clients
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Server-initiated keepalive; clients silent for longer than `timeout` are dropped
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(45),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    pub port: u16,
+    pub fallback_ports: RangeInclusive<u16>,
+    pub heartbeat: HeartbeatConfig,
+    /// How long to wait for COMMAND_RESULT before reporting a command as failed
+    pub command_timeout: Duration,
+    /// Outbound frames a client may have waiting before new ones are dropped
+    pub send_queue_capacity: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            fallback_ports: FALLBACK_PORTS,
+            heartbeat: HeartbeatConfig::default(),
+            command_timeout: Duration::from_secs(5),
+            send_queue_capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl WebSocketConfig {
+    /// Configuration from the stored settings; `LYRYC_WS_PORT`, `LYRYC_WS_PING_INTERVAL`
+    /// and `LYRYC_WS_IDLE_TIMEOUT` (seconds) still override them for one run
+    pub fn from_settings(settings: &WebSocketSettings) -> Self {
+        let mut config = Self {
+            port: settings.port,
+            heartbeat: HeartbeatConfig {
+                interval: Duration::from_secs(settings.ping_interval_secs),
+                timeout: Duration::from_secs(settings.idle_timeout_secs),
+            },
+            command_timeout: Duration::from_secs(settings.command_timeout_secs),
+            send_queue_capacity: settings.send_queue_capacity,
+            ..Self::default()
+        };
+        if let Ok(value) = std::env::var("LYRYC_WS_PORT") {
+            match value.parse::<u16>() {
+                Ok(port) if port != 0 => config.port = port,
+                _ => warn!("Ignoring invalid LYRYC_WS_PORT value: {}", value),
+            }
+        }
+        if let Some(interval) = env_seconds("LYRYC_WS_PING_INTERVAL") {
+            config.heartbeat.interval = interval;
+        }
+        if let Some(timeout) = env_seconds("LYRYC_WS_IDLE_TIMEOUT") {
+            config.heartbeat.timeout = timeout;
+        }
+        if config.heartbeat.timeout <= config.heartbeat.interval {
+            warn!(
+                "Idle timeout {:?} is not longer than the ping interval {:?}; clients may be dropped between pings",
+                config.heartbeat.timeout, config.heartbeat.interval
+            );
+        }
+        config
+    }
+
+    /// Preferred port first, then the fallback range
+    fn candidate_ports(&self) -> Vec<u16> {
+        std::iter::once(self.port)
+            .chain(
+                self.fallback_ports
+                    .clone()
+                    .filter(|port| *port != self.port),
+            )
+            .collect()
+    }
+}
+
+fn env_seconds(name: &str) -> Option<Duration> {
+    let value = std::env::var(name).ok()?;
+    match value.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+        _ => {
+            warn!("Ignoring invalid {} value: {}", name, value);
+            None
+        }
+    }
+}
 
 pub struct WebSocketServer {
-    port: u16,
+    config: WebSocketConfig,
+    bound_port: AtomicU16, // 0 until bind() succeeds
     pub clients: ClientConnections,
-    track_callback: Option<Arc<dyn Fn(TrackUpdate) + Send + Sync>>,
+    pending_commands: PendingCommands,
+    auth: AuthState,
+    recorder: RecorderState,
+    track_callback: Option<TrackCallback>,
+    client_event_callback: Option<ClientEventCallback>,
+    shutdown: CancellationToken,
+    /// The accept loop and every connection task
+    tasks: TaskTracker,
+    /// The task driving `serve` and the native messaging bridge, awaited on stop so the
+    /// bridge socket is released before a restart binds it again
+    serve_task: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
 }
 
 impl WebSocketServer {
-    pub fn new(port: u16) -> Self {
+    pub fn new(config: WebSocketConfig, auth: AuthState) -> Self {
         Self {
-            port,
+            config,
+            bound_port: AtomicU16::new(0),
             clients: Arc::new(Mutex::new(HashMap::new())),
+            pending_commands: Arc::new(Mutex::new(HashMap::new())),
+            auth,
+            recorder: RecorderState::default(),
             track_callback: None,
+            client_event_callback: None,
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            serve_task: std::sync::Mutex::new(None),
         }
     }
 
     pub fn set_track_callback<F>(&mut self, callback: F)
     where
-        F: Fn(TrackUpdate) + Send + Sync + 'static,
+        F: Fn(&str, InboundMessage) + Send + Sync + 'static,
     {
         self.track_callback = Some(Arc::new(callback));
     }
 
-    pub async fn start(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let addr = format!("127.0.0.1:{}", self.port);
+    /// Share the session recorder so inbound messages land in the active recording
+    pub fn set_recorder(&mut self, recorder: RecorderState) {
+        self.recorder = recorder;
+    }
 
-        match TcpListener::bind(&addr).await {
-            Ok(listener) => {
-                info!("WebSocket server listening on: {}", addr);
+    pub fn set_client_event_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(ClientEvent) + Send + Sync + 'static,
+    {
+        self.client_event_callback = Some(Arc::new(callback));
+    }
 
-                let clients = Arc::clone(&self.clients);
-                let track_callback = self.track_callback.clone();
+    pub fn set_serve_task(&self, task: tokio::task::JoinHandle<()>) {
+        *self.serve_task.lock().unwrap_or_else(|e| e.into_inner()) = Some(task);
+    }
 
-                while let Ok((stream, addr)) = listener.accept().await {
-                    let clients = Arc::clone(&clients);
-                    let callback = track_callback.clone();
+    /// Wait for the task set with `set_serve_task` to finish
+    pub async fn join_serve_task(&self) {
+        let task = self
+            .serve_task
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(task) = task {
+            if let Err(e) = task.await {
+                warn!("WebSocket serve task failed: {}", e);
+            }
+        }
+    }
 
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, addr, clients, callback).await {
-                            error!("Error handling connection from {}: {}", addr, e);
-                        }
-                    });
-                }
+    /// Port the server is actually listening on, if it is bound
+    pub fn port(&self) -> Option<u16> {
+        match self.bound_port.load(Ordering::SeqCst) {
+            0 => None,
+            port => Some(port),
+        }
+    }
 
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to bind to {}: {}", addr, e);
-                error!("This usually means:");
-                error!("  1. Another instance of the application is running");
-                error!("  2. The port is occupied by another process");
-                error!("  3. Permission denied (try running as administrator)");
-                Err(e.into())
+    /// Bind the preferred port, falling back through the configured range when it is taken
+    pub async fn bind(&self) -> Result<TcpListener, String> {
+        let mut failures = Vec::new();
+
+        for port in self.config.candidate_ports() {
+            let addr = format!("127.0.0.1:{}", port);
+            match TcpListener::bind(&addr).await {
+                Ok(listener) => {
+                    // Port 0 lets the OS pick one
+                    let port = listener.local_addr().map_or(port, |addr| addr.port());
+                    if port != self.config.port && self.config.port != 0 {
+                        warn!(
+                            "Preferred port {} unavailable, using fallback port {}",
+                            self.config.port, port
+                        );
+                    }
+                    info!("WebSocket server listening on: 127.0.0.1:{}", port);
+                    self.bound_port.store(port, Ordering::SeqCst);
+                    return Ok(listener);
+                }
+                Err(e) => {
+                    debug!("Failed to bind to {}: {}", addr, e);
+                    failures.push(format!("{}: {}", port, e));
+                }
             }
         }
+
+        error!(
+            "Failed to bind any WebSocket port ({})",
+            failures.join("; ")
+        );
+        error!("This usually means:");
+        error!("  1. Another instance of the application is running");
+        error!("  2. The ports are occupied by other processes");
+        error!("  3. Permission denied (try running as administrator)");
+        Err(format!(
+            "No free port for the WebSocket server (tried {})",
+            failures.join("; ")
+        ))
     }
 
-    pub async fn broadcast_to_extension(&self, message: ExtensionMessage) -> Result<(), String> {
-        let mut clients = self.clients.lock().await;
-        let message_text = serde_json::to_string(&message).map_err(|e| e.to_string())?;
-        let ws_message = Message::Text(message_text.clone());
+    /// Accept connections on a listener returned by `bind` until it fails or the
+    /// server shuts down
+    pub async fn serve(&self, listener: TcpListener) {
+        let _running = self.tasks.token();
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = self.shutdown.cancelled() => break,
+            };
+            let (stream, addr) = match accepted {
+                Ok(connection) => connection,
+                Err(e) => {
+                    error!("WebSocket server stopped accepting connections: {}", e);
+                    break;
+                }
+            };
+            self.accept_stream(stream, addr.to_string());
+        }
 
-        info!(
-            "Broadcasting to {} clients: {}",
-            clients.len(),
-            message_text
-        );
+        // Free the port before `shutdown` stops waiting
+        drop(listener);
+        self.bound_port.store(0, Ordering::SeqCst);
+    }
 
-        if clients.is_empty() {
-            warn!("No WebSocket clients connected - message not sent");
-            return Ok(());
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// Stop accepting connections and close every client with a close frame, waiting
+    /// briefly for the frames to go out. The server cannot be started again.
+    pub async fn shutdown(&self) {
+        info!("Shutting down WebSocket server");
+        self.shutdown.cancel();
+        self.tasks.close();
+        if tokio::time::timeout(SHUTDOWN_GRACE, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "WebSocket connections still open after {:?}, dropping them",
+                SHUTDOWN_GRACE
+            );
         }
+        self.bound_port.store(0, Ordering::SeqCst);
+    }
 
-        // Keep track of disconnected clients to remove them
-        let mut disconnected_clients = Vec::new();
-        let mut successful_sends = 0;
+    /// Run the WebSocket protocol over an already accepted stream, e.g. a TCP socket
+    /// or the local socket native messaging hosts bridge through
+    pub fn accept_stream<S>(&self, stream: S, peer: String)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let context = ConnectionContext {
+            clients: Arc::clone(&self.clients),
+            pending_commands: Arc::clone(&self.pending_commands),
+            auth: Arc::clone(&self.auth),
+            recorder: Arc::clone(&self.recorder),
+            heartbeat: self.config.heartbeat,
+            shutdown: self.shutdown.clone(),
+            queue_capacity: self.config.send_queue_capacity,
+            track_callback: self.track_callback.clone(),
+            client_event_callback: self.client_event_callback.clone(),
+        };
 
-        for (client_id, sender) in clients.iter() {
-            match sender.send(ws_message.clone()) {
-                Ok(_) => {
-                    info!("✅ Sent message to client {}", client_id);
-                    successful_sends += 1;
-                }
-                Err(_) => {
-                    error!("❌ Failed to send to client {}: channel closed", client_id);
-                    disconnected_clients.push(client_id.clone());
-                }
+        self.tasks.spawn(async move {
+            if let Err(e) = handle_connection(stream, &peer, context).await {
+                error!("Error handling connection from {}: {}", peer, e);
             }
-        }
+        });
+    }
 
-        // Remove disconnected clients
-        for client_id in disconnected_clients {
-            clients.remove(&client_id);
-            warn!("Removed disconnected client: {}", client_id);
+    /// Close every paired client, e.g. after the pairing token changed
+    pub async fn disconnect_all(&self, reason: &str) {
+        let mut clients = self.clients.lock().await;
+        for (client_id, client) in clients.drain() {
+            info!("Disconnecting client {}: {}", client_id, reason);
+            let _ = client.sender.send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Policy,
+                reason: reason.to_string().into(),
+            })));
         }
+    }
 
-        info!(
-            "Message broadcast complete: {} successful, {} failed",
-            successful_sends,
-            clients.len() - successful_sends
-        );
-        Ok(())
+    /// Client that most recently sent anything, used when no player has reported a track
+    pub async fn most_recent_client(&self) -> Option<String> {
+        let clients = self.clients.lock().await;
+        clients
+            .iter()
+            .max_by_key(|(_, client)| client.activity.last_seen.load(Ordering::Relaxed))
+            .map(|(client_id, _)| client_id.clone())
+    }
+
+    /// Every paired client, oldest connection first
+    pub async fn client_infos(&self) -> Vec<ClientInfo> {
+        let clients = self.clients.lock().await;
+        let mut infos: Vec<ClientInfo> = clients
+            .iter()
+            .map(|(client_id, client)| client.info(client_id))
+            .collect();
+        infos.sort_by_key(|info| info.connected_at);
+        infos
     }
 
-    pub async fn send_playback_command(
+    /// Send a playback command to one client and wait for its acknowledgement
+    pub async fn send_command(
         &self,
-        command: String,
-        seek_time: Option<f64>,
-    ) -> Result<(), String> {
-        let message = ExtensionMessage {
-            message_type: "PLAYBACK_COMMAND".to_string(),
-            data: serde_json::json!({
-                "command": command,
-                "seekTime": seek_time
-            }),
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
+        target: CommandTarget,
+        command: PlaybackCommand,
+    ) -> Result<CommandOutcome, String> {
+        command.validate()?;
+        let name = command.name();
+
+        let (sender, supports_ack) = {
+            let clients = self.clients.lock().await;
+            if clients.is_empty() {
+                return Err("No extension connected".to_string());
+            }
+            let client = clients
+                .get(&target.client_id)
+                .ok_or_else(|| format!("Extension client {} is not connected", target.client_id))?;
+            client.hello.supports(target.source.as_deref(), &command)?;
+            let supports_ack = client
+                .hello
+                .capabilities
+                .iter()
+                .any(|capability| capability == CAPABILITY_COMMAND_ACK);
+            (client.sender.clone(), supports_ack)
         };
 
-        self.broadcast_to_extension(message).await
+        let command_id = Uuid::new_v4().to_string();
+        let message = AppMessage::new(OutboundMessage::PlaybackCommand {
+            command_id: Some(command_id.clone()),
+            source: target.source.clone(),
+            command,
+        });
+        let text = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+        debug!(
+            "Sending '{}' ({}) to client {} source {:?}",
+            name, command_id, target.client_id, target.source
+        );
+
+        if !supports_ack {
+            deliver(&sender, &target.client_id, Message::Text(text))?;
+            return Ok(CommandOutcome {
+                command_id,
+                client_id: target.client_id,
+                acknowledged: false,
+                state: None,
+            });
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pending_commands.lock().await.insert(
+            command_id.clone(),
+            PendingCommand {
+                client_id: target.client_id.clone(),
+                reply: reply_tx,
+            },
+        );
+        if let Err(e) = deliver(&sender, &target.client_id, Message::Text(text)) {
+            self.pending_commands.lock().await.remove(&command_id);
+            return Err(e);
+        }
+
+        let result = match tokio::time::timeout(self.config.command_timeout, reply_rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => {
+                return Err(format!(
+                    "Extension disconnected before acknowledging '{}'",
+                    name
+                ))
+            }
+            Err(_) => {
+                self.pending_commands.lock().await.remove(&command_id);
+                warn!("⏱️ No acknowledgement for '{}' ({})", name, command_id);
+                return Err(format!(
+                    "Extension did not acknowledge '{}' within {}s",
+                    name,
+                    self.config.command_timeout.as_secs()
+                ));
+            }
+        };
+
+        if result.success {
+            Ok(CommandOutcome {
+                command_id,
+                client_id: target.client_id,
+                acknowledged: true,
+                state: result.state,
+            })
+        } else {
+            Err(format!(
+                "'{}' failed: {}",
+                name,
+                result
+                    .error
+                    .unwrap_or_else(|| "the player rejected the command".to_string())
+            ))
+        }
     }
 }
 
-async fn handle_connection(
-    raw_stream: TcpStream,
-    addr: SocketAddr,
-    clients: ClientConnections,
-    track_callback: Option<Arc<dyn Fn(TrackUpdate) + Send + Sync>>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let ws_stream = accept_async(raw_stream).await?;
-    let client_id = Uuid::new_v4().to_string();
+/// Queue a frame that must not be lost silently, e.g. a playback command
+fn deliver(sender: &QueueSender, client_id: &str, message: Message) -> Result<(), String> {
+    match sender.send(message) {
+        Ok(Delivery::Dropped) => Err(format!(
+            "Extension client {} is not keeping up, dropped the message",
+            client_id
+        )),
+        Ok(_) => Ok(()),
+        Err(QueueClosed) => Err(format!("Extension client {} disconnected", client_id)),
+    }
+}
 
-    info!("New WebSocket connection: {} with ID: {}", addr, client_id);
+fn send_to_client(sender: &QueueSender, message: OutboundMessage) {
+    match serde_json::to_string(&AppMessage::new(message)) {
+        Ok(text) => {
+            if let Ok(Delivery::Dropped) = sender.send(Message::Text(text)) {
+                debug!("Outbound queue full, dropped a message");
+            }
+        }
+        Err(e) => error!("Failed to serialize outbound message: {}", e),
+    }
+}
 
-    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+fn send_error(sender: &QueueSender, activity: &ClientActivity, error: ProtocolError) {
+    warn!("Rejecting extension message: {}", error);
+    activity.messages_rejected.fetch_add(1, Ordering::Relaxed);
+    send_to_client(sender, OutboundMessage::Error(error));
+}
+
+/// Server state shared with every connection task
+#[derive(Clone)]
+struct ConnectionContext {
+    clients: ClientConnections,
+    pending_commands: PendingCommands,
+    auth: AuthState,
+    recorder: RecorderState,
+    heartbeat: HeartbeatConfig,
+    shutdown: CancellationToken,
+    queue_capacity: usize,
+    track_callback: Option<TrackCallback>,
+    client_event_callback: Option<ClientEventCallback>,
+}
 
-    // Send welcome message
-    let welcome_msg = ExtensionMessage {
-        message_type: "connected".to_string(),
-        data: serde_json::json!({
-            "client_id": client_id,
-            "status": "ready"
-        }),
-        timestamp: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64,
+async fn handle_connection<S>(
+    raw_stream: S,
+    peer: &str,
+    context: ConnectionContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let ConnectionContext {
+        clients,
+        pending_commands,
+        auth,
+        recorder,
+        heartbeat,
+        shutdown,
+        queue_capacity,
+        track_callback,
+        client_event_callback,
+    } = context;
+
+    // Validate the Origin header during the HTTP upgrade so web pages never get a socket
+    let auth_for_handshake = Arc::clone(&auth);
+    #[allow(clippy::result_large_err)] // signature is dictated by tungstenite's Callback
+    let check_origin = move |request: &Request, response: Response| {
+        let origin = request
+            .headers()
+            .get("origin")
+            .and_then(|value| value.to_str().ok());
+        let allowed = match auth_for_handshake.read() {
+            Ok(auth) => auth.check_origin(origin),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match allowed {
+            Ok(()) => Ok(response),
+            Err(reason) => {
+                warn!("Rejected WebSocket upgrade from {}: {}", peer, reason);
+                let mut error_response = ErrorResponse::new(Some(reason));
+                *error_response.status_mut() = StatusCode::FORBIDDEN;
+                Err(error_response)
+            }
+        }
     };
 
-    let welcome_text = serde_json::to_string(&welcome_msg)?;
-    ws_sender.send(Message::Text(welcome_text)).await?;
+    let ws_stream = accept_hdr_async(raw_stream, check_origin).await?;
+    let client_id = Uuid::new_v4().to_string();
 
-    // Create mpsc channel for this client
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    info!("New WebSocket connection: {} with ID: {}", peer, client_id);
+    record_event(&recorder, &client_id, RecordedEvent::Connected);
 
-    // Add client to connections after welcome message
-    {
-        let mut clients_guard = clients.lock().await;
-        clients_guard.insert(client_id.clone(), tx);
-        info!(
-            "✅ Client {} added to connections with mpsc channel. Total clients: {}",
-            client_id,
-            clients_guard.len()
-        );
-    }
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+    // Bounded queue for this client; all outbound frames go through it
+    let (tx, mut rx) = client_queue(queue_capacity);
 
     // Spawn a task to forward messages from the channel to the WebSocket
     let client_id_for_sender = client_id.clone();
-    tokio::spawn(async move {
+    let writer = tokio::spawn(async move {
         while let Some(message) = rx.recv().await {
             if let Err(e) = ws_sender.send(message).await {
                 error!(
@@ -219,54 +678,225 @@ async fn handle_connection(
         );
     });
 
-    // Handle incoming messages
-    while let Some(msg) = ws_receiver.next().await {
-        match msg? {
+    // Greet the client; it is only added to connections after a valid HELLO
+    send_to_client(
+        &tx,
+        OutboundMessage::Connected {
+            client_id: client_id.clone(),
+            status: "ready".to_string(),
+            protocol_version: PROTOCOL_VERSION,
+        },
+    );
+
+    let mut handshake_complete = false;
+    let mut disconnect_reason = "closed by client".to_string();
+    let connected_at = now_millis();
+    let activity = Arc::new(ClientActivity {
+        last_seen: AtomicU64::new(connected_at),
+        ..Default::default()
+    });
+
+    let mut ping_timer = tokio::time::interval(heartbeat.interval);
+    ping_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ping_timer.tick().await; // the first tick completes immediately
+
+    // Handle incoming messages, pinging the client while it is quiet
+    loop {
+        let msg = tokio::select! {
+            msg = ws_receiver.next() => msg,
+            _ = shutdown.cancelled() => {
+                let _ = tx.send(Message::Close(Some(CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "server shutting down".into(),
+                })));
+                disconnect_reason = "server shutting down".to_string();
+                break;
+            }
+            _ = ping_timer.tick() => {
+                let idle = now_millis().saturating_sub(activity.last_seen.load(Ordering::Relaxed));
+                if idle > heartbeat.timeout.as_millis() as u64 {
+                    warn!("⏱️ Client {} silent for {}ms, dropping it", client_id, idle);
+                    let _ = tx.send(Message::Close(Some(CloseFrame {
+                        code: CloseCode::Away,
+                        reason: "idle timeout".into(),
+                    })));
+                    disconnect_reason = "idle timeout".to_string();
+                    break;
+                }
+                // Unanswered pings from a stalled client collapse into one
+                let _ = tx.send_latest("ping", Message::Ping(Vec::new()));
+                continue;
+            }
+        };
+
+        let msg = match msg {
+            Some(Ok(msg)) => msg,
+            Some(Err(e)) => {
+                warn!("WebSocket error from client {}: {}", client_id, e);
+                disconnect_reason = format!("connection error: {}", e);
+                break;
+            }
+            None => break,
+        };
+        activity.last_seen.store(now_millis(), Ordering::Relaxed);
+
+        match msg {
             Message::Text(text) => {
-                if let Ok(extension_msg) = serde_json::from_str::<ExtensionMessage>(&text) {
-                    // println!("📨 WebSocket received: {} with data: {}", extension_msg.message_type, extension_msg.data);
-
-                    match extension_msg.message_type.as_str() {
-                        "TRACK_DETECTED" | "TRACK_PAUSED" | "TRACK_STOPPED" | "TRACK_PROGRESS" => {
-                            if let Ok(track_update) =
-                                serde_json::from_value::<TrackUpdate>(extension_msg.data.clone())
-                            {
-                                // println!("✅ Successfully parsed TrackUpdate: {:?}", track_update);
-                                // Call the track callback if available
-                                if let Some(ref callback) = track_callback {
-                                    callback(track_update);
-                                }
-                            } else {
-                                error!("Failed to parse TrackUpdate from: {}", extension_msg.data);
-                            }
+                activity.messages_received.fetch_add(1, Ordering::Relaxed);
+                activity.last_message.store(now_millis(), Ordering::Relaxed);
+                let extension_msg = match parse_inbound(&text) {
+                    Ok(extension_msg) => extension_msg,
+                    Err(e) => {
+                        send_error(&tx, &activity, e);
+                        continue;
+                    }
+                };
+
+                record_event(
+                    &recorder,
+                    &client_id,
+                    RecordedEvent::Message {
+                        message: extension_msg.clone(),
+                    },
+                );
+
+                let message_type = extension_msg.payload.message_type();
+                let needs_handshake = !matches!(
+                    extension_msg.payload,
+                    InboundMessage::Hello(_) | InboundMessage::Ping {}
+                );
+                if needs_handshake && !handshake_complete {
+                    send_error(
+                        &tx,
+                        &activity,
+                        ProtocolError::new(
+                            ErrorCode::HandshakeRequired,
+                            "Send HELLO before other messages",
+                        )
+                        .for_message(message_type),
+                    );
+                    continue;
+                }
+
+                match extension_msg.payload {
+                    InboundMessage::Hello(hello) => {
+                        if let Err(e) = hello.validate() {
+                            send_error(&tx, &activity, e);
+                            let _ = tx.send(Message::Close(Some(CloseFrame {
+                                code: CloseCode::Protocol,
+                                reason: "unsupported protocol version".into(),
+                            })));
+                            disconnect_reason = "unsupported protocol version".to_string();
+                            break;
                         }
-                        "ping" => {
-                            let pong_msg = ExtensionMessage {
-                                message_type: "pong".to_string(),
-                                data: serde_json::json!({}),
-                                timestamp: std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_millis() as u64,
-                            };
-                            let pong_text = serde_json::to_string(&pong_msg)?;
-
-                            // Get sender from connections to send pong
-                            let clients_guard = clients.lock().await;
-                            if let Some(sender) = clients_guard.get(&client_id) {
-                                let _ = sender.send(Message::Text(pong_text));
+
+                        let authorized = auth
+                            .read()
+                            .map(|auth| auth.verify_token(hello.auth_token.as_deref()))
+                            .unwrap_or(false);
+                        if !authorized {
+                            // Slow down guessing before telling the client it failed
+                            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                            send_error(
+                                &tx,
+                                &activity,
+                                ProtocolError::new(
+                                    ErrorCode::Unauthorized,
+                                    "Invalid or missing pairing token",
+                                )
+                                .for_message(message_type),
+                            );
+                            let _ = tx.send(Message::Close(Some(CloseFrame {
+                                code: CloseCode::Policy,
+                                reason: "pairing required".into(),
+                            })));
+                            disconnect_reason = "pairing required".to_string();
+                            break;
+                        }
+
+                        info!(
+                            "Client {} handshake: protocol v{}, extension {:?}, browser {:?}, capabilities {:?}",
+                            client_id,
+                            hello.protocol_version,
+                            hello.extension_version,
+                            hello.browser,
+                            hello.capabilities
+                        );
+
+                        let mut clients_guard = clients.lock().await;
+                        clients_guard.insert(
+                            client_id.clone(),
+                            ClientHandle {
+                                sender: tx.clone(),
+                                hello: hello.clone(),
+                                peer: peer.to_string(),
+                                connected_at,
+                                activity: Arc::clone(&activity),
+                            },
+                        );
+                        info!(
+                            "✅ Client {} added to connections. Total clients: {}",
+                            client_id,
+                            clients_guard.len()
+                        );
+                        drop(clients_guard);
+                        if !handshake_complete {
+                            if let Some(ref callback) = client_event_callback {
+                                callback(ClientEvent::Connected {
+                                    client_id: client_id.clone(),
+                                    hello,
+                                });
                             }
                         }
-                        _ => {
-                            debug!("Unknown message type: {}", extension_msg.message_type);
+                        handshake_complete = true;
+
+                        send_to_client(
+                            &tx,
+                            OutboundMessage::Welcome {
+                                client_id: client_id.clone(),
+                                protocol_version: PROTOCOL_VERSION,
+                                app_version: env!("CARGO_PKG_VERSION").to_string(),
+                            },
+                        );
+                    }
+                    InboundMessage::Ping {} => {
+                        send_to_client(&tx, OutboundMessage::Pong {});
+                    }
+                    track_message @ (InboundMessage::TrackDetected(_)
+                    | InboundMessage::TrackPaused(_)
+                    | InboundMessage::TrackStopped(_)
+                    | InboundMessage::TrackProgress(_)) => {
+                        if let Some(ref callback) = track_callback {
+                            callback(&client_id, track_message);
+                        }
+                    }
+                    InboundMessage::CommandResult(result) => {
+                        let mut pending = pending_commands.lock().await;
+                        let owned_by_client = pending
+                            .get(&result.command_id)
+                            .is_some_and(|command| command.client_id == client_id);
+                        if owned_by_client {
+                            if let Some(command) = pending.remove(&result.command_id) {
+                                let _ = command.reply.send(result);
+                            }
+                        } else {
+                            debug!(
+                                "Ignoring late or unknown COMMAND_RESULT {} from client {}",
+                                result.command_id, client_id
+                            );
                         }
                     }
-                } else {
-                    warn!("Failed to parse message: {}", text);
                 }
             }
             Message::Binary(_) => {
-                debug!("Received binary message (not supported)");
+                send_error(
+                    &tx,
+                    &activity,
+                    ProtocolError::new(
+                        ErrorCode::UnsupportedMessage,
+                        "Binary frames are not supported",
+                    ),
+                );
             }
             Message::Close(_) => {
                 info!("Client {} disconnected", client_id);
@@ -280,17 +910,102 @@ async fn handle_connection(
     {
         let mut clients_guard = clients.lock().await;
         clients_guard.remove(&client_id);
+        let stats = tx.stats();
         info!(
-            "❌ Client {} disconnected and removed. Total clients: {}",
+            "❌ Client {} disconnected and removed (sent {}, coalesced {}, dropped {}, peak queue {}). Total clients: {}",
             client_id,
+            stats.sent,
+            stats.coalesced,
+            stats.dropped,
+            stats.high_water,
             clients_guard.len()
         );
     }
+    record_event(&recorder, &client_id, RecordedEvent::Disconnected);
+    // Commands still waiting on this client fail right away instead of timing out
+    pending_commands
+        .lock()
+        .await
+        .retain(|_, command| command.client_id != client_id);
+
+    if handshake_complete {
+        if let Some(ref callback) = client_event_callback {
+            callback(ClientEvent::Disconnected {
+                client_id: client_id.clone(),
+                reason: disconnect_reason,
+            });
+        }
+    }
+
+    // Let queued frames such as a close frame reach the client before the socket drops
+    drop(tx);
+    let writer_abort = writer.abort_handle();
+    if tokio::time::timeout(SHUTDOWN_GRACE, writer).await.is_err() {
+        writer_abort.abort();
+    }
 
     Ok(())
 }
 
-// Helper function to create a default WebSocket server instance
-pub fn create_websocket_server() -> WebSocketServer {
-    WebSocketServer::new(8765) // Default port
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthConfig;
+
+    #[tokio::test]
+    async fn shutdown_closes_clients_and_frees_the_port() {
+        let auth = AuthConfig::generate();
+        let token = auth.pairing_token.clone();
+        let mut config = WebSocketConfig {
+            port: 0,
+            fallback_ports: 0..=0,
+            ..Default::default()
+        };
+        let server = Arc::new(WebSocketServer::new(
+            config.clone(),
+            Arc::new(std::sync::RwLock::new(auth)),
+        ));
+        let listener = server.bind().await.unwrap();
+        let port = server.port().unwrap();
+        let serving = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.serve(listener).await }
+        });
+
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", port))
+            .await
+            .unwrap();
+        let hello = format!(
+            r#"{{"message_type":"HELLO","data":{{"protocol_version":1,"auth_token":"{}"}},"timestamp":1}}"#,
+            token
+        );
+        client.send(Message::Text(hello)).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.clients.lock().await.is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("client never completed the handshake");
+
+        server.shutdown().await;
+        serving.await.unwrap();
+        assert!(server.port().is_none());
+
+        // The client is told why instead of seeing a reset
+        let close = loop {
+            match client.next().await {
+                Some(Ok(Message::Close(frame))) => break frame,
+                Some(Ok(_)) => continue,
+                other => panic!("expected a close frame, got {:?}", other),
+            }
+        };
+        assert_eq!(close.unwrap().code, CloseCode::Away);
+
+        // The port is free for a restart
+        config.port = port;
+        config.fallback_ports = port..=port;
+        let restarted = WebSocketServer::new(config, server.auth.clone());
+        assert!(restarted.bind().await.is_ok());
+    }
 }
@@ -1,112 +1,239 @@
-use crate::types::{TrackInfo, TrackState, WebSocketState};
-use crate::websocket::{create_websocket_server, TrackUpdate};
+use crate::discovery;
+use crate::native_messaging::BridgeListener;
+use crate::protocol::{now_millis, PlaybackCommand};
+use crate::settings;
+use crate::track_pipeline::PipelineEvent;
+use crate::types::{AuthState, RecorderState, SourceState, TrackPipeline, WebSocketState};
+use crate::websocket::{
+    ClientEvent, ClientInfo, CommandOutcome, CommandTarget, WebSocketConfig, WebSocketServer,
+};
 use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tauri::{Emitter, State};
+use tauri::{Emitter, Manager, State};
 
 #[tauri::command]
 pub async fn init_extension_connection(
     ws_state: State<'_, WebSocketState>,
-    track_state: State<'_, TrackState>,
     app_handle: tauri::AppHandle,
 ) -> Result<String, String> {
     // Ensure idempotent initialization under a single lock
-    let server_arc = {
-        let mut server_guard = ws_state.lock().await;
-        if server_guard.is_some() {
-            info!("WebSocket server already initialized; skipping re-bind");
-            return Ok("WebSocket server already running".to_string());
+    let mut server_guard = ws_state.lock().await;
+    if let Some(ref server) = *server_guard {
+        info!("WebSocket server already initialized; skipping re-bind");
+        return Ok(match server.port() {
+            Some(port) => format!("WebSocket server already running on port {}", port),
+            None => "WebSocket server already running".to_string(),
+        });
+    }
+
+    let settings = settings::current(&app_handle).await;
+    let config = WebSocketConfig::from_settings(&settings.websocket);
+    let port = start_server(&app_handle, &mut server_guard, config).await?;
+    Ok(format!("WebSocket server started on port {}", port))
+}
+
+/// Close every client with a close frame and free the port
+#[tauri::command]
+pub async fn stop_websocket_server(ws_state: State<'_, WebSocketState>) -> Result<bool, String> {
+    let server = ws_state.lock().await.take();
+    match server {
+        Some(server) => {
+            stop_server(&server).await;
+            Ok(true)
         }
+        None => Ok(false),
+    }
+}
 
-        // Create server and register callbacks
-        let mut ws_server = create_websocket_server();
-        let app_handle_clone = app_handle.clone();
-        let track_state_clone = track_state.inner().clone();
-        ws_server.set_track_callback(move |track_update: TrackUpdate| {
-            let track_info = TrackInfo {
-                title: track_update.title.clone(),
-                artist: track_update.artist.clone(),
-                album: None,
-                duration: track_update.duration,
-                thumbnail: track_update.thumbnail.clone(),
-            };
+/// Stop the server and bind again with the current settings, optionally preferring
+/// another port for this run. Clients reconnect and pair with the current token.
+#[tauri::command]
+pub async fn restart_websocket_server(
+    port: Option<u16>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    let port = restart_extension_server(&app_handle, port).await?;
+    Ok(format!("WebSocket server restarted on port {}", port))
+}
 
-            // Check for track changes and emit track-updated only when track actually changes
-            let track_state_for_callback = track_state_clone.clone();
-            let app_handle_for_track = app_handle_clone.clone();
-            let track_info_for_check = track_info.clone();
-            tokio::spawn(async move {
-                if track_update.current_time.is_none() || track_update.current_time == Some(0.0) {
-                    let mut current_track_guard = track_state_for_callback.lock().await;
-                    let should_emit = match &*current_track_guard {
-                        Some(existing_track) => {
-                            // Only emit if track title or artist changed
-                            existing_track.title != track_info_for_check.title
-                                || existing_track.artist != track_info_for_check.artist
-                        }
-                        None => true, // First track
-                    };
-
-                    if should_emit {
-                        info!(
-                            "Track changed: '{}' by '{}'",
-                            track_info_for_check.title, track_info_for_check.artist
-                        );
-                        *current_track_guard = Some(track_info_for_check.clone());
-                        if let Err(e) =
-                            app_handle_for_track.emit("track-updated", &track_info_for_check)
-                        {
-                            error!("Failed to emit track-updated event: {}", e);
-                        }
-                    } else {
-                        debug!("Track duplicate detected, skipping emit");
-                    }
-                }
-            });
+pub(crate) async fn restart_extension_server(
+    app_handle: &tauri::AppHandle,
+    port: Option<u16>,
+) -> Result<u16, String> {
+    if port == Some(0) {
+        return Err("Port must be between 1 and 65535".to_string());
+    }
 
-            // Always emit playback state and time updates (they change frequently)
-            if let Err(e) = app_handle_clone.emit("playback-state", &track_update.is_playing) {
-                error!("Failed to emit playback-state event: {}", e);
-            }
+    let ws_state = app_handle.state::<WebSocketState>();
+    let mut server_guard = ws_state.lock().await;
+    if let Some(server) = server_guard.take() {
+        stop_server(&server).await;
+    }
 
-            if let Some(current_time) = track_update.current_time {
-                if let Err(e) = app_handle_clone.emit(
-                    "track-time-update",
-                    &serde_json::json!({
-                        "currentTime": current_time,
-                        "duration": track_update.duration.unwrap_or(0.0),
-                        "isPlaying": track_update.is_playing
-                    }),
-                ) {
-                    error!("Failed to emit track-time-update event: {}", e);
-                }
-            }
-        });
+    let settings = settings::current(app_handle).await;
+    let mut config = WebSocketConfig::from_settings(&settings.websocket);
+    if let Some(port) = port {
+        config.port = port;
+    }
+    start_server(app_handle, &mut server_guard, config).await
+}
+
+/// Disconnect extensions cleanly before the app exits
+pub(crate) async fn shutdown_extension_server(app_handle: &tauri::AppHandle) {
+    let server = app_handle.state::<WebSocketState>().lock().await.take();
+    if let Some(server) = server {
+        stop_server(&server).await;
+    }
+}
+
+async fn stop_server(server: &WebSocketServer) {
+    let port = server.port();
+    server.shutdown().await;
+    // The serve task owns the bridge listener; it has to be gone before a restart
+    server.join_serve_task().await;
+    let _ = discovery::publish(|d| {
+        if d.websocket_port == port {
+            d.websocket_port = None;
+        }
+    });
+}
+
+/// Create a server wired into the track pipeline, bind it and store it in `slot`
+async fn start_server(
+    app_handle: &tauri::AppHandle,
+    slot: &mut Option<Arc<WebSocketServer>>,
+    config: WebSocketConfig,
+) -> Result<u16, String> {
+    // Create server and route its callbacks into the shared track pipeline
+    let auth = app_handle.state::<AuthState>().inner().clone();
+    let mut ws_server = WebSocketServer::new(config, auth);
+    ws_server.set_recorder(app_handle.state::<RecorderState>().inner().clone());
+    let events_tx = app_handle.state::<TrackPipeline>().inner().clone();
+    let track_events = events_tx.clone();
+    ws_server.set_track_callback(move |client_id, message| {
+        let _ = track_events.send(PipelineEvent::Track(client_id.to_string(), message));
+    });
+    let app_handle_for_clients = app_handle.clone();
+    ws_server.set_client_event_callback(move |event| {
+        emit_client_event(&app_handle_for_clients, &event);
+        if let ClientEvent::Disconnected { client_id, .. } = event {
+            let _ = events_tx.send(PipelineEvent::ClientGone(client_id));
+        }
+    });
+
+    // Bind before storing the server so a failure leaves the state free for a retry
+    let listener = ws_server.bind().await?;
+    let server_arc = Arc::new(ws_server);
+    *slot = Some(server_arc.clone());
 
-        let server_arc = Arc::new(ws_server);
-        *server_guard = Some(server_arc.clone());
-        server_arc
+    let port = server_arc
+        .port()
+        .ok_or_else(|| "WebSocket server bound without a port".to_string())?;
+    if let Err(e) = discovery::publish(|d| d.websocket_port = Some(port)) {
+        warn!("Failed to write discovery file: {}", e);
+    }
+
+    // Native messaging hosts reach the same server over a local socket
+    let bridge = match BridgeListener::bind().await {
+        Ok(bridge) => Some(bridge),
+        Err(e) => {
+            warn!("Native messaging bridge unavailable: {}", e);
+            None
+        }
     };
 
-    // Clone state handle so we can clear it on bind failure
-    let ws_state_for_spawn = ws_state.inner().clone();
-    let server_for_spawn = server_arc.clone();
-    tokio::spawn(async move {
-        if let Err(e) = server_for_spawn.start().await {
-            error!("WebSocket server failed to start: {}", e);
-            // Clear stored server to allow retry on next init call
-            let mut guard = ws_state_for_spawn.lock().await;
-            *guard = None;
+    // Clone state handle so we can clear it if the server stops
+    let ws_state = app_handle.state::<WebSocketState>().inner().clone();
+    let serve_task = tokio::spawn({
+        let server_arc = server_arc.clone();
+        async move {
+            // The bridge lives as long as the TCP listener so a restart can bind it again
+            let serve_bridge = async {
+                if let Some(bridge) = bridge {
+                    bridge.serve(&server_arc).await;
+                }
+                std::future::pending::<()>().await
+            };
+            tokio::select! {
+                _ = server_arc.serve(listener) => {}
+                _ = serve_bridge => {}
+            }
+            if server_arc.is_shut_down() {
+                info!("WebSocket server on port {} stopped", port);
+                return;
+            }
+
+            error!("WebSocket server stopped");
+            // Clear stored server to allow retry on next init call, unless it was replaced.
+            // Done apart from this task: a restart may hold the lock while awaiting it.
+            tokio::spawn(async move {
+                let mut guard = ws_state.lock().await;
+                if guard
+                    .as_ref()
+                    .is_some_and(|current| Arc::ptr_eq(current, &server_arc))
+                {
+                    *guard = None;
+                    let _ = discovery::publish(|d| d.websocket_port = None);
+                }
+            });
         }
     });
+    server_arc.set_serve_task(serve_task);
 
-    Ok("WebSocket server started on port 8765".to_string())
+    Ok(port)
+}
+
+fn emit_client_event(app_handle: &tauri::AppHandle, event: &ClientEvent) {
+    let result = match event {
+        ClientEvent::Connected { client_id, hello } => app_handle.emit(
+            "client-connected",
+            &serde_json::json!({
+                "clientId": client_id,
+                "protocolVersion": hello.protocol_version,
+                "extensionVersion": hello.extension_version,
+                "browser": hello.browser,
+                "capabilities": hello.capabilities
+            }),
+        ),
+        ClientEvent::Disconnected { client_id, reason } => app_handle.emit(
+            "client-disconnected",
+            &serde_json::json!({
+                "clientId": client_id,
+                "reason": reason
+            }),
+        ),
+    };
+    if let Err(e) = result {
+        error!("Failed to emit client event: {}", e);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebSocketStatus {
+    pub running: bool,
+    pub port: Option<u16>,
+    pub clients: usize,
 }
 
 #[tauri::command]
-pub async fn get_websocket_status(ws_state: State<'_, WebSocketState>) -> Result<bool, String> {
+pub async fn get_websocket_status(
+    ws_state: State<'_, WebSocketState>,
+) -> Result<WebSocketStatus, String> {
     let server_guard = ws_state.lock().await;
-    Ok(server_guard.is_some())
+    match *server_guard {
+        Some(ref server) => Ok(WebSocketStatus {
+            running: server.port().is_some(),
+            port: server.port(),
+            clients: server.clients.lock().await.len(),
+        }),
+        None => Ok(WebSocketStatus {
+            running: false,
+            port: None,
+            clients: 0,
+        }),
+    }
 }
 
 #[tauri::command]
@@ -117,12 +244,7 @@ pub async fn get_websocket_clients_count(
     if let Some(ref server) = *server_guard {
         let clients = server.clients.lock().await;
         let count = clients.len();
-        info!("Current WebSocket clients count: {}", count);
-
-        // Debug: print client IDs
-        for (client_id, _) in clients.iter() {
-            info!("Connected client: {}", client_id);
-        }
+        debug!("Current WebSocket clients count: {}", count);
 
         Ok(count)
     } else {
@@ -136,13 +258,29 @@ pub async fn debug_websocket_server(ws_state: State<'_, WebSocketState>) -> Resu
     let server_guard = ws_state.lock().await;
     if let Some(ref server) = *server_guard {
         let clients = server.clients.lock().await;
-        let mut debug_info = format!("WebSocket Server Debug Info:\n");
-        debug_info.push_str(&format!("- Server exists: Yes\n"));
-        debug_info.push_str(&format!("- Port: 8765\n"));
+        let mut debug_info = "WebSocket Server Debug Info:\n".to_string();
+        debug_info.push_str("- Server exists: Yes\n");
+        match server.port() {
+            Some(port) => debug_info.push_str(&format!("- Port: {}\n", port)),
+            None => debug_info.push_str("- Port: not bound\n"),
+        }
         debug_info.push_str(&format!("- Connected clients: {}\n", clients.len()));
 
-        for (client_id, _) in clients.iter() {
-            debug_info.push_str(&format!("  - Client ID: {}\n", client_id));
+        let now = now_millis();
+        for (client_id, client) in clients.iter() {
+            let idle_ms = now.saturating_sub(client.activity.last_seen.load(Ordering::Relaxed));
+            let stats = client.sender.stats();
+            debug_info.push_str(&format!(
+                "  - Client ID: {} (protocol v{}, extension {}, capabilities: {}, last seen {}ms ago, sent {}, queued {}, dropped {})\n",
+                client_id,
+                client.hello.protocol_version,
+                client.hello.extension_version.as_deref().unwrap_or("unknown"),
+                client.hello.capabilities.join(", "),
+                idle_ms,
+                stats.sent,
+                stats.queued,
+                stats.dropped
+            ));
         }
 
         info!("Debug info requested: {}", debug_info);
@@ -154,28 +292,136 @@ pub async fn debug_websocket_server(ws_state: State<'_, WebSocketState>) -> Resu
     }
 }
 
+/// Paired extension clients with their handshake details, activity and players
 #[tauri::command]
-pub async fn control_playback(action: String, seek_time: Option<f64>) -> Result<String, String> {
-    info!("Playback control: {} {:?}", action, seek_time);
+pub async fn list_extension_clients(
+    ws_state: State<'_, WebSocketState>,
+    source_state: State<'_, SourceState>,
+) -> Result<Vec<ClientInfo>, String> {
+    let server = ws_state.lock().await.clone();
+    let mut clients = match server {
+        Some(server) => server.client_infos().await,
+        None => return Ok(Vec::new()),
+    };
+
+    let snapshot = source_state.lock().await.snapshot();
+    for client in &mut clients {
+        client.sources = snapshot
+            .sources
+            .iter()
+            .filter(|summary| summary.key.client_id == client.client_id)
+            .map(|summary| summary.key.source.clone())
+            .collect();
+        client.active_source = snapshot
+            .active
+            .as_ref()
+            .filter(|key| key.client_id == client.client_id)
+            .map(|key| key.source.clone());
+    }
+    Ok(clients)
+}
+
+/// Explicit client wins; otherwise the player reporting `source`, the active player,
+/// or, before any track was reported, the most recently active client
+async fn resolve_command_target(
+    server: Option<&WebSocketServer>,
+    source_state: &SourceState,
+    client_id: Option<String>,
+    source: Option<String>,
+) -> Result<CommandTarget, String> {
+    if let Some(client_id) = client_id {
+        return Ok(CommandTarget { client_id, source });
+    }
+
+    let key = {
+        let registry = source_state.lock().await;
+        match source.as_deref() {
+            Some(source) => registry.find(source),
+            None => registry.active().cloned(),
+        }
+    };
+    if let Some(key) = key {
+        return Ok(CommandTarget {
+            client_id: key.client_id,
+            source: Some(key.source),
+        });
+    }
+
+    let client_id = match server {
+        Some(server) => server.most_recent_client().await,
+        None => None,
+    };
+    client_id
+        .map(|client_id| CommandTarget { client_id, source })
+        .ok_or_else(|| "No extension connected".to_string())
+}
+
+pub(crate) async fn dispatch_playback_command(
+    command: PlaybackCommand,
+    client_id: Option<String>,
+    source: Option<String>,
+    ws_state: &WebSocketState,
+    source_state: &SourceState,
+    app_handle: &tauri::AppHandle,
+) -> Result<CommandOutcome, String> {
+    // Don't hold the state lock while waiting for the acknowledgement
+    let server = ws_state.lock().await.clone();
+    let target = resolve_command_target(server.as_deref(), source_state, client_id, source).await?;
+
+    #[cfg(target_os = "linux")]
+    if target.client_id == crate::mpris::CLIENT_ID {
+        return crate::mpris::execute_command(app_handle, target.source, command).await;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = app_handle;
+
+    server
+        .ok_or_else(|| "WebSocket server not available".to_string())?
+        .send_command(target, command)
+        .await
+}
 
-    // For now, return success - we'll implement browser control later
-    // This would need to send messages to the extension to control the browser
-    Ok(format!("Playback control '{}' executed", action))
+#[tauri::command]
+pub async fn control_playback(
+    command: PlaybackCommand,
+    client_id: Option<String>,
+    source: Option<String>,
+    ws_state: State<'_, WebSocketState>,
+    source_state: State<'_, SourceState>,
+    app_handle: tauri::AppHandle,
+) -> Result<CommandOutcome, String> {
+    info!("Playback control: {:?}", command);
+    dispatch_playback_command(
+        command,
+        client_id,
+        source,
+        &ws_state,
+        &source_state,
+        &app_handle,
+    )
+    .await
 }
 
+/// Older string-based entry point, kept for existing callers
 #[tauri::command]
 pub async fn send_playback_command(
     command: String,
     seek_time: Option<f64>,
+    client_id: Option<String>,
+    source: Option<String>,
     ws_state: State<'_, WebSocketState>,
-) -> Result<String, String> {
+    source_state: State<'_, SourceState>,
+    app_handle: tauri::AppHandle,
+) -> Result<CommandOutcome, String> {
     info!("Sending playback command: {} {:?}", command, seek_time);
-
-    let server_guard = ws_state.lock().await;
-    if let Some(ref server) = *server_guard {
-        server.send_playback_command(command, seek_time).await?;
-        Ok("Command sent to extension".to_string())
-    } else {
-        Err("WebSocket server not available".to_string())
-    }
+    let command = PlaybackCommand::from_legacy(&command, seek_time)?;
+    dispatch_playback_command(
+        command,
+        client_id,
+        source,
+        &ws_state,
+        &source_state,
+        &app_handle,
+    )
+    .await
 }
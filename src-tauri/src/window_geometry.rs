@@ -0,0 +1,946 @@
+//! Remembers where the lyrics bar sits, per monitor layout.
+//!
+//! Geometry is saved to `window-state.json` whenever the window is moved or resized,
+//! keyed by the set of connected monitors, so a docked and an undocked laptop each get
+//! their own placement back. Top and bottom anchors span their monitor's width and
+//! follow resolution changes; a custom anchor keeps the position relative to its
+//! monitor. When the remembered monitor is gone the window moves to the primary one.
+//!
+//! The monitors are polled so docking, undocking and scaling changes re-place the
+//! window; sizes taken at one scale factor are scaled for a monitor with another.
+//! The karaoke window gets the same treatment in `window-state-karaoke.json`, starting
+//! full screen on a secondary monitor.
+
+use crate::settings::{self, WindowSettings};
+use crate::types::WindowGeometryState;
+use crate::window_management::KARAOKE_WINDOW;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{
+    Emitter, Manager, PhysicalPosition, PhysicalSize, Position, Size, WebviewWindow, WindowEvent,
+};
+
+/// Moves and resizes arrive in bursts while dragging; save once they settle
+const SAVE_DEBOUNCE: Duration = Duration::from_millis(500);
+/// A window this close to its anchored placement keeps the anchor
+const ANCHOR_SNAP_PX: i32 = 2;
+/// The OS does not announce monitor changes, so they are polled
+const MONITOR_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Anchor {
+    /// Full monitor width along the top edge
+    #[default]
+    Top,
+    /// Full monitor width along the bottom edge
+    Bottom,
+    /// Wherever the user dragged it
+    Custom,
+    /// The whole monitor, as the karaoke window starts out on a TV or projector
+    Fullscreen,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorInfo {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f64,
+}
+
+impl MonitorInfo {
+    pub fn from_monitor(monitor: &tauri::Monitor) -> Self {
+        Self {
+            name: monitor
+                .name()
+                .cloned()
+                .unwrap_or_else(|| "unknown".to_string()),
+            x: monitor.position().x,
+            y: monitor.position().y,
+            width: monitor.size().width,
+            height: monitor.size().height,
+            scale_factor: monitor.scale_factor(),
+        }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x
+            && y >= self.y
+            && x < self.x + self.width as i32
+            && y < self.y + self.height as i32
+    }
+}
+
+/// Identifies a set of connected monitors regardless of the order the OS lists them in
+pub fn layout_key(monitors: &[MonitorInfo]) -> String {
+    let mut parts: Vec<String> = monitors
+        .iter()
+        .map(|m| {
+            format!(
+                "{}:{}x{}@{},{}*{:.2}",
+                m.name, m.width, m.height, m.x, m.y, m.scale_factor
+            )
+        })
+        .collect();
+    parts.sort();
+    parts.join("|")
+}
+
+/// Window position and size in physical pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowGeometry {
+    pub anchor: Anchor,
+    /// Name of the monitor the window was on
+    pub monitor: String,
+    /// Position relative to the monitor's top-left corner; only used by the custom anchor
+    pub x: i32,
+    pub y: i32,
+    /// Only used by the custom anchor; top and bottom span the monitor
+    pub width: u32,
+    pub height: u32,
+    /// Scale factor of the monitor the sizes were measured on; `None` keeps them as is
+    #[serde(default)]
+    pub scale_factor: Option<f64>,
+}
+
+impl WindowGeometry {
+    /// The lyrics bar as it looks before the user moves it
+    pub fn default_bar(height: u32) -> Self {
+        Self {
+            anchor: Anchor::Top,
+            monitor: String::new(),
+            x: 0,
+            y: 0,
+            width: 0,
+            height,
+            scale_factor: None,
+        }
+    }
+
+    /// Covering all of `monitor`
+    pub fn fullscreen(monitor: &str) -> Self {
+        Self {
+            anchor: Anchor::Fullscreen,
+            monitor: monitor.to_string(),
+            ..Self::default_bar(0)
+        }
+    }
+
+    /// Where this geometry puts the window on `monitor`, kept inside its bounds
+    pub fn place_on(&self, monitor: &MonitorInfo) -> Placement {
+        // Measured at another scale factor, e.g. on the laptop panel before docking
+        let scale = self
+            .scale_factor
+            .map_or(1.0, |factor| monitor.scale_factor / factor);
+        let scaled = |value: i64| (value as f64 * scale).round() as i64;
+
+        let height = (scaled(self.height as i64) as u32).clamp(1, monitor.height.max(1));
+        match self.anchor {
+            Anchor::Top => Placement {
+                x: monitor.x,
+                y: monitor.y,
+                width: monitor.width,
+                height,
+            },
+            Anchor::Bottom => Placement {
+                x: monitor.x,
+                y: monitor.y + monitor.height.saturating_sub(height) as i32,
+                width: monitor.width,
+                height,
+            },
+            Anchor::Custom => {
+                let width = (scaled(self.width as i64) as u32).clamp(1, monitor.width.max(1));
+                let x = scaled(self.x as i64) as i32;
+                let y = scaled(self.y as i64) as i32;
+                Placement {
+                    x: monitor.x + x.clamp(0, monitor.width.saturating_sub(width) as i32),
+                    y: monitor.y + y.clamp(0, monitor.height.saturating_sub(height) as i32),
+                    width,
+                    height,
+                }
+            }
+            Anchor::Fullscreen => Placement {
+                x: monitor.x,
+                y: monitor.y,
+                width: monitor.width,
+                height: monitor.height,
+            },
+        }
+    }
+
+    /// Geometry of a window at `placement`, on the monitor holding its centre. It keeps
+    /// `anchor` if it still sits where that anchor puts it, and becomes custom otherwise.
+    pub fn capture(placement: Placement, monitors: &[MonitorInfo], anchor: Anchor) -> Option<Self> {
+        let centre_x = placement.x + placement.width as i32 / 2;
+        let centre_y = placement.y + placement.height as i32 / 2;
+        let monitor = monitors
+            .iter()
+            .find(|m| m.contains(centre_x, centre_y))
+            .or_else(|| {
+                monitors
+                    .iter()
+                    .find(|m| m.contains(placement.x, placement.y))
+            })?;
+
+        let mut geometry = Self {
+            anchor,
+            monitor: monitor.name.clone(),
+            x: placement.x - monitor.x,
+            y: placement.y - monitor.y,
+            width: placement.width,
+            height: placement.height,
+            scale_factor: Some(monitor.scale_factor),
+        };
+        let anchored = geometry.place_on(monitor);
+        let snapped = (anchored.x - placement.x).abs() <= ANCHOR_SNAP_PX
+            && (anchored.y - placement.y).abs() <= ANCHOR_SNAP_PX
+            && anchored.width.abs_diff(placement.width) <= ANCHOR_SNAP_PX as u32
+            && (anchor != Anchor::Fullscreen
+                || anchored.height.abs_diff(placement.height) <= ANCHOR_SNAP_PX as u32);
+        if !snapped {
+            geometry.anchor = Anchor::Custom;
+        }
+        Some(geometry)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WindowGeometryStore {
+    /// Geometry per `layout_key`
+    pub layouts: BTreeMap<String, WindowGeometry>,
+    /// Most recently saved geometry, for layouts seen for the first time
+    pub last: Option<WindowGeometry>,
+    /// Bumped by every move so only the last one in a burst is saved
+    #[serde(skip)]
+    pending_save: u64,
+    /// Layout the window was last placed for; moves under another layout are the OS
+    /// shuffling windows around and are not saved until the window is placed again
+    #[serde(skip)]
+    placed_layout: Option<String>,
+}
+
+impl WindowGeometryStore {
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Failed to parse {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Saved geometry for this layout, else the latest one
+    pub fn remembered(&self, monitors: &[MonitorInfo]) -> Option<&WindowGeometry> {
+        self.layouts
+            .get(&layout_key(monitors))
+            .or(self.last.as_ref())
+    }
+
+    /// Where the window goes with `monitors` connected, `default` if it was never placed;
+    /// `None` without any monitor. A connected `follow` monitor wins over the one the
+    /// geometry was saved on.
+    pub fn resolve(
+        &self,
+        monitors: &[MonitorInfo],
+        primary: Option<&MonitorInfo>,
+        follow: Option<&str>,
+        default: WindowGeometry,
+    ) -> Option<Placement> {
+        let geometry = self.remembered(monitors).cloned().unwrap_or(default);
+        let named = |name: &str| monitors.iter().find(|m| m.name == name);
+        let monitor = follow
+            .and_then(named)
+            .or_else(|| named(&geometry.monitor))
+            // The remembered monitor is gone: same anchor and size on the primary one
+            .or(primary)
+            .or(monitors.first())?;
+        Some(geometry.place_on(monitor))
+    }
+
+    pub fn remember(&mut self, monitors: &[MonitorInfo], geometry: WindowGeometry) {
+        self.layouts.insert(layout_key(monitors), geometry.clone());
+        self.last = Some(geometry);
+    }
+
+    /// Forget this layout's geometry, so the next restore uses the latest or the default
+    pub fn forget(&mut self, monitors: &[MonitorInfo]) {
+        self.layouts.remove(&layout_key(monitors));
+        self.last = None;
+    }
+}
+
+/// Geometry of every window, each store loaded on first use
+#[derive(Debug, Default)]
+pub struct WindowGeometries {
+    stores: HashMap<String, WindowGeometryStore>,
+}
+
+impl WindowGeometries {
+    pub fn insert(&mut self, label: &str, store: WindowGeometryStore) {
+        self.stores.insert(label.to_string(), store);
+    }
+
+    fn store(
+        &mut self,
+        app_handle: &tauri::AppHandle,
+        label: &str,
+    ) -> Result<&mut WindowGeometryStore, String> {
+        Ok(match self.stores.entry(label.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(WindowGeometryStore::load(&geometry_file_path(
+                app_handle, label,
+            )?)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorSummary {
+    #[serde(flatten)]
+    pub monitor: MonitorInfo,
+    pub primary: bool,
+    /// The lyrics bar is on this monitor
+    pub current: bool,
+}
+
+/// `window-state.json` for the lyrics bar, `window-state-<label>.json` for other windows
+pub fn geometry_file_path(app_handle: &tauri::AppHandle, label: &str) -> Result<PathBuf, String> {
+    let file_name = match label {
+        "main" => "window-state.json".to_string(),
+        label => format!("window-state-{}.json", label),
+    };
+    app_handle
+        .path()
+        .app_config_dir()
+        .map(|dir| dir.join(file_name))
+        .map_err(|e| format!("Failed to resolve config directory: {}", e))
+}
+
+fn window_by_label(app_handle: &tauri::AppHandle, label: &str) -> Result<WebviewWindow, String> {
+    app_handle
+        .get_webview_window(label)
+        .ok_or_else(|| format!("Window '{}' not found", label))
+}
+
+fn monitors_of(window: &WebviewWindow) -> Result<(Vec<MonitorInfo>, Option<MonitorInfo>), String> {
+    let monitors = window
+        .available_monitors()
+        .map_err(|e| format!("Failed to list monitors: {}", e))?
+        .iter()
+        .map(MonitorInfo::from_monitor)
+        .collect();
+    let primary = window
+        .primary_monitor()
+        .map_err(|e| format!("Failed to get primary monitor: {}", e))?
+        .as_ref()
+        .map(MonitorInfo::from_monitor);
+    Ok((monitors, primary))
+}
+
+fn monitor_summaries(window: &WebviewWindow) -> Result<Vec<MonitorSummary>, String> {
+    let (monitors, primary) = monitors_of(window)?;
+    let current = window
+        .current_monitor()
+        .ok()
+        .flatten()
+        .map(|monitor| MonitorInfo::from_monitor(&monitor));
+    Ok(monitors
+        .into_iter()
+        .map(|monitor| MonitorSummary {
+            primary: primary.as_ref() == Some(&monitor),
+            current: current.as_ref() == Some(&monitor),
+            monitor,
+        })
+        .collect())
+}
+
+fn current_placement(window: &WebviewWindow) -> Result<Placement, String> {
+    let position = window
+        .outer_position()
+        .map_err(|e| format!("Failed to get position: {}", e))?;
+    let size = window
+        .inner_size()
+        .map_err(|e| format!("Failed to get size: {}", e))?;
+    Ok(Placement {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+fn apply_placement(window: &WebviewWindow, placement: Placement) -> Result<(), String> {
+    window
+        .set_position(Position::Physical(PhysicalPosition {
+            x: placement.x,
+            y: placement.y,
+        }))
+        .map_err(|e| format!("Failed to set position: {}", e))?;
+    window
+        .set_size(Size::Physical(PhysicalSize {
+            width: placement.width,
+            height: placement.height,
+        }))
+        .map_err(|e| format!("Failed to set size: {}", e))
+}
+
+/// Put the window where `store` says it belongs for the connected monitors
+pub fn restore(
+    window: &WebviewWindow,
+    store: &mut WindowGeometryStore,
+    window_settings: &WindowSettings,
+) -> Result<Placement, String> {
+    let (monitors, primary) = monitors_of(window)?;
+    let (default, follow) = if window.label() == KARAOKE_WINDOW {
+        // Meant for a TV or projector, so another monitor than the primary if there is one
+        let secondary = monitors.iter().find(|m| Some(*m) != primary.as_ref());
+        (
+            WindowGeometry::fullscreen(secondary.map_or("", |m| m.name.as_str())),
+            None,
+        )
+    } else {
+        (
+            WindowGeometry::default_bar(window_settings.height),
+            window_settings.follow_monitor.as_deref(),
+        )
+    };
+    let placement = store
+        .resolve(&monitors, primary.as_ref(), follow, default)
+        .ok_or_else(|| "No monitor found".to_string())?;
+    apply_placement(window, placement)?;
+    store.placed_layout = Some(layout_key(&monitors));
+    info!(
+        "Window '{}' placed at {}x{} ({}, {})",
+        window.label(),
+        placement.width,
+        placement.height,
+        placement.x,
+        placement.y
+    );
+    Ok(placement)
+}
+
+/// `restore` the window called `label` with the current settings
+pub async fn reapply(app_handle: &tauri::AppHandle, label: &str) -> Result<Placement, String> {
+    let window = window_by_label(app_handle, label)?;
+    let window_settings = settings::current(app_handle).await.window;
+    let geometry_state = app_handle.state::<WindowGeometryState>();
+    let mut geometries = geometry_state.lock().await;
+    restore(
+        &window,
+        geometries.store(app_handle, label)?,
+        &window_settings,
+    )
+}
+
+/// Move the window to `geometry` on `monitor` and remember it there
+fn place_and_remember(
+    app_handle: &tauri::AppHandle,
+    window: &WebviewWindow,
+    store: &mut WindowGeometryStore,
+    monitors: &[MonitorInfo],
+    geometry: &WindowGeometry,
+    monitor: &MonitorInfo,
+) -> Result<WindowGeometry, String> {
+    let placement = geometry.place_on(monitor);
+    apply_placement(window, placement)?;
+    // Stored as measured on the new monitor
+    let placed = WindowGeometry::capture(placement, std::slice::from_ref(monitor), geometry.anchor)
+        .unwrap_or_else(|| geometry.clone());
+    store.remember(monitors, placed.clone());
+    store.placed_layout = Some(layout_key(monitors));
+    store.save(&geometry_file_path(app_handle, window.label())?)?;
+    Ok(placed)
+}
+
+/// Save the geometry after moves and resizes, once they settle
+pub fn track(window: &WebviewWindow) {
+    let app_handle = window.app_handle().clone();
+    let label = window.label().to_string();
+    window.on_window_event(move |event| {
+        if !matches!(event, WindowEvent::Moved(_) | WindowEvent::Resized(_)) {
+            return;
+        }
+        let app_handle = app_handle.clone();
+        let label = label.clone();
+        tauri::async_runtime::spawn(async move {
+            let generation = {
+                let state = app_handle.state::<WindowGeometryState>();
+                let mut geometries = state.lock().await;
+                match geometries.store(&app_handle, &label) {
+                    Ok(store) => {
+                        store.pending_save += 1;
+                        store.pending_save
+                    }
+                    Err(e) => {
+                        warn!("Failed to save window geometry: {}", e);
+                        return;
+                    }
+                }
+            };
+            tokio::time::sleep(SAVE_DEBOUNCE).await;
+            if let Err(e) = save_current(&app_handle, &label, generation).await {
+                warn!("Failed to save window geometry: {}", e);
+            }
+        });
+    });
+}
+
+/// Remember the window's current geometry unless a later move came in
+async fn save_current(
+    app_handle: &tauri::AppHandle,
+    label: &str,
+    generation: u64,
+) -> Result<(), String> {
+    let window = window_by_label(app_handle, label)?;
+    // Minimizing reports a zero size on some platforms
+    if window.is_minimized().unwrap_or(false) || !window.is_visible().unwrap_or(true) {
+        return Ok(());
+    }
+    let placement = current_placement(&window)?;
+    if placement.width == 0 || placement.height == 0 {
+        return Ok(());
+    }
+    let (monitors, _) = monitors_of(&window)?;
+
+    let state = app_handle.state::<WindowGeometryState>();
+    let mut geometries = state.lock().await;
+    let store = geometries.store(app_handle, label)?;
+    let layout = layout_key(&monitors);
+    if generation != store.pending_save || store.placed_layout.as_ref() != Some(&layout) {
+        return Ok(());
+    }
+    let anchor = store
+        .remembered(&monitors)
+        .map(|geometry| geometry.anchor)
+        .unwrap_or_default();
+    let Some(geometry) = WindowGeometry::capture(placement, &monitors, anchor) else {
+        return Ok(());
+    };
+    if store.layouts.get(&layout) != Some(&geometry) {
+        store.remember(&monitors, geometry);
+        store.save(&geometry_file_path(app_handle, label)?)?;
+    }
+    Ok(())
+}
+
+/// Re-place the windows whenever monitors are connected, removed or rescaled
+pub async fn watch_monitors(app_handle: tauri::AppHandle) {
+    let mut interval = tokio::time::interval(MONITOR_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Ok(window) = window_by_label(&app_handle, "main") else {
+            continue;
+        };
+        // Displays briefly disappear while the OS reconfigures them
+        let monitors = match monitors_of(&window) {
+            Ok((monitors, _)) if !monitors.is_empty() => monitors,
+            _ => continue,
+        };
+        let layout = layout_key(&monitors);
+
+        let mut changed = false;
+        for label in ["main", KARAOKE_WINDOW] {
+            let placed_layout = {
+                let state = app_handle.state::<WindowGeometryState>();
+                let geometries = state.lock().await;
+                match geometries.stores.get(label) {
+                    Some(store) => store.placed_layout.clone(),
+                    None => continue, // never opened
+                }
+            };
+            if placed_layout.as_ref() == Some(&layout)
+                || app_handle.get_webview_window(label).is_none()
+            {
+                continue;
+            }
+            changed = true;
+            if let Err(e) = reapply(&app_handle, label).await {
+                warn!(
+                    "Failed to re-place window '{}' after a monitor change: {}",
+                    label, e
+                );
+            }
+        }
+
+        if changed {
+            info!("Monitor layout changed: {}", layout);
+            if let Ok(summaries) = monitor_summaries(&window) {
+                if let Err(e) = app_handle.emit("monitors-changed", summaries) {
+                    warn!("Failed to emit monitors-changed event: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn list_monitors(app_handle: tauri::AppHandle) -> Result<Vec<MonitorSummary>, String> {
+    monitor_summaries(&window_by_label(&app_handle, "main")?)
+}
+
+/// Move a window (the lyrics bar unless `window` names another) to the monitor called
+/// `name`, keeping its anchor unless one is given
+#[tauri::command]
+pub async fn move_to_monitor(
+    name: String,
+    anchor: Option<Anchor>,
+    window: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<WindowGeometry, String> {
+    let label = window.as_deref().unwrap_or("main");
+    info!("Moving window '{}' to monitor {}", label, name);
+    let window = window_by_label(&app_handle, label)?;
+    let default_height = settings::current(&app_handle).await.window.height;
+    let (monitors, _) = monitors_of(&window)?;
+    let monitor = monitors
+        .iter()
+        .find(|m| m.name == name)
+        .ok_or_else(|| format!("Monitor '{}' is not connected", name))?;
+
+    let state = app_handle.state::<WindowGeometryState>();
+    let mut geometries = state.lock().await;
+    let store = geometries.store(&app_handle, label)?;
+    let remembered = store.remembered(&monitors).cloned();
+    let current_anchor = remembered
+        .as_ref()
+        .map(|geometry| geometry.anchor)
+        .unwrap_or_default();
+    let mut geometry =
+        WindowGeometry::capture(current_placement(&window)?, &monitors, current_anchor)
+            .or(remembered)
+            .unwrap_or_else(|| WindowGeometry::default_bar(default_height));
+    geometry.anchor = anchor.unwrap_or(geometry.anchor);
+    geometry.monitor = name;
+    place_and_remember(&app_handle, &window, store, &monitors, &geometry, monitor)
+}
+
+/// Pin a window to the top or bottom edge of its monitor, fill the monitor, or leave it
+/// where it is
+#[tauri::command]
+pub async fn set_window_anchor(
+    anchor: Anchor,
+    window: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<WindowGeometry, String> {
+    let label = window.as_deref().unwrap_or("main");
+    info!("Setting anchor of window '{}' to {:?}", label, anchor);
+    let window = window_by_label(&app_handle, label)?;
+    let (monitors, _) = monitors_of(&window)?;
+    let mut geometry = WindowGeometry::capture(current_placement(&window)?, &monitors, anchor)
+        .ok_or_else(|| "The window is not on any monitor".to_string())?;
+    geometry.anchor = anchor;
+    let monitor = monitors
+        .iter()
+        .find(|m| m.name == geometry.monitor)
+        .ok_or_else(|| "The window is not on any monitor".to_string())?;
+
+    let state = app_handle.state::<WindowGeometryState>();
+    let mut geometries = state.lock().await;
+    let store = geometries.store(&app_handle, label)?;
+    place_and_remember(&app_handle, &window, store, &monitors, &geometry, monitor)
+}
+
+/// Forget a window's placement for the current monitor layout and go back to its default
+#[tauri::command]
+pub async fn reset_window_geometry(
+    window: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let label = window.as_deref().unwrap_or("main");
+    info!("Resetting geometry of window '{}'", label);
+    let window = window_by_label(&app_handle, label)?;
+    let (monitors, _) = monitors_of(&window)?;
+    let window_settings = settings::current(&app_handle).await.window;
+
+    let state = app_handle.state::<WindowGeometryState>();
+    let mut geometries = state.lock().await;
+    let store = geometries.store(&app_handle, label)?;
+    store.forget(&monitors);
+    store.save(&geometry_file_path(&app_handle, label)?)?;
+    restore(&window, store, &window_settings).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, x: i32, width: u32, height: u32) -> MonitorInfo {
+        MonitorInfo {
+            name: name.to_string(),
+            x,
+            y: 0,
+            width,
+            height,
+            scale_factor: 1.0,
+        }
+    }
+
+    #[test]
+    fn layouts_are_keyed_by_the_set_of_monitors() {
+        let laptop = monitor("eDP-1", 0, 1920, 1080);
+        let external = monitor("DP-2", 1920, 2560, 1440);
+        assert_eq!(
+            layout_key(&[laptop.clone(), external.clone()]),
+            layout_key(&[external, laptop.clone()])
+        );
+        assert_ne!(
+            layout_key(std::slice::from_ref(&laptop)),
+            layout_key(&[monitor("eDP-1", 0, 1280, 800)])
+        );
+    }
+
+    #[test]
+    fn anchors_follow_their_monitor() {
+        let external = monitor("DP-2", 1920, 2560, 1440);
+        let mut geometry = WindowGeometry::default_bar(40);
+        assert_eq!(
+            geometry.place_on(&external),
+            Placement {
+                x: 1920,
+                y: 0,
+                width: 2560,
+                height: 40
+            }
+        );
+        geometry.anchor = Anchor::Bottom;
+        assert_eq!(geometry.place_on(&external).y, 1400);
+
+        // A custom position that no longer fits is pulled back onto the monitor
+        let custom = WindowGeometry {
+            anchor: Anchor::Custom,
+            monitor: "DP-2".to_string(),
+            x: 2400,
+            y: 700,
+            width: 800,
+            height: 60,
+            scale_factor: Some(1.0),
+        };
+        let small = monitor("DP-2", 1920, 1280, 720);
+        assert_eq!(
+            custom.place_on(&small),
+            Placement {
+                x: 1920 + 480,
+                y: 660,
+                width: 800,
+                height: 60
+            }
+        );
+    }
+
+    #[test]
+    fn capture_keeps_anchors_until_dragged_away() {
+        let monitors = [
+            monitor("eDP-1", 0, 1920, 1080),
+            monitor("DP-2", 1920, 2560, 1440),
+        ];
+        // Taller bar, still on the bottom edge of the second monitor
+        let bottom = Placement {
+            x: 1920,
+            y: 1360,
+            width: 2560,
+            height: 80,
+        };
+        let geometry = WindowGeometry::capture(bottom, &monitors, Anchor::Bottom).unwrap();
+        assert_eq!(
+            (geometry.anchor, geometry.monitor.as_str()),
+            (Anchor::Bottom, "DP-2")
+        );
+
+        let dragged = Placement {
+            x: 300,
+            y: 500,
+            width: 900,
+            height: 50,
+        };
+        let geometry = WindowGeometry::capture(dragged, &monitors, Anchor::Bottom).unwrap();
+        assert_eq!(geometry.anchor, Anchor::Custom);
+        assert_eq!(
+            (geometry.monitor.as_str(), geometry.x, geometry.y),
+            ("eDP-1", 300, 500)
+        );
+    }
+
+    #[test]
+    fn fullscreen_covers_the_monitor_until_resized() {
+        let monitors = [
+            monitor("eDP-1", 0, 1920, 1080),
+            monitor("HDMI-1", 1920, 3840, 2160),
+        ];
+        let placement = WindowGeometry::fullscreen("HDMI-1").place_on(&monitors[1]);
+        assert_eq!(
+            placement,
+            Placement {
+                x: 1920,
+                y: 0,
+                width: 3840,
+                height: 2160
+            }
+        );
+        let geometry = WindowGeometry::capture(placement, &monitors, Anchor::Fullscreen).unwrap();
+        assert_eq!(geometry.anchor, Anchor::Fullscreen);
+
+        // Same top edge and width, but no longer the full height
+        let shorter = Placement {
+            height: 600,
+            ..placement
+        };
+        let geometry = WindowGeometry::capture(shorter, &monitors, Anchor::Fullscreen).unwrap();
+        assert_eq!(geometry.anchor, Anchor::Custom);
+    }
+
+    #[test]
+    fn falls_back_to_the_primary_monitor_when_one_disappears() {
+        let laptop = monitor("eDP-1", 0, 1920, 1080);
+        let docked = [laptop.clone(), monitor("DP-2", 1920, 2560, 1440)];
+        let mut store = WindowGeometryStore::default();
+        assert_eq!(
+            store.resolve(
+                &docked,
+                Some(&laptop),
+                None,
+                WindowGeometry::default_bar(40)
+            ),
+            Some(Placement {
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 40
+            })
+        );
+
+        let on_external = WindowGeometry {
+            anchor: Anchor::Custom,
+            monitor: "DP-2".to_string(),
+            x: 100,
+            y: 1200,
+            width: 1000,
+            height: 60,
+            scale_factor: Some(1.0),
+        };
+        store.remember(&docked, on_external);
+        assert_eq!(
+            store.resolve(
+                &docked,
+                Some(&laptop),
+                None,
+                WindowGeometry::default_bar(40)
+            ),
+            Some(Placement {
+                x: 2020,
+                y: 1200,
+                width: 1000,
+                height: 60
+            })
+        );
+
+        // Undocked: the same relative placement, clamped onto the laptop screen
+        assert_eq!(
+            store.resolve(
+                std::slice::from_ref(&laptop),
+                Some(&laptop),
+                None,
+                WindowGeometry::default_bar(40)
+            ),
+            Some(Placement {
+                x: 100,
+                y: 1020,
+                width: 1000,
+                height: 60
+            })
+        );
+        assert_eq!(
+            store.resolve(&[], None, None, WindowGeometry::default_bar(40)),
+            None
+        );
+    }
+
+    #[test]
+    fn follows_the_configured_monitor_and_its_scale() {
+        let laptop = monitor("eDP-1", 0, 1920, 1080);
+        let mut hidpi = monitor("DP-2", 1920, 3840, 2160);
+        hidpi.scale_factor = 2.0;
+        let docked = [laptop.clone(), hidpi];
+
+        let mut store = WindowGeometryStore::default();
+        let on_laptop = WindowGeometry {
+            anchor: Anchor::Custom,
+            monitor: "eDP-1".to_string(),
+            x: 100,
+            y: 200,
+            width: 800,
+            height: 40,
+            scale_factor: Some(1.0),
+        };
+        store.remember(std::slice::from_ref(&laptop), on_laptop);
+
+        // Docked to a 2x monitor it is configured to follow: same size on screen
+        assert_eq!(
+            store.resolve(
+                &docked,
+                Some(&laptop),
+                Some("DP-2"),
+                WindowGeometry::default_bar(40)
+            ),
+            Some(Placement {
+                x: 1920 + 200,
+                y: 400,
+                width: 1600,
+                height: 80
+            })
+        );
+        // An unplugged follow monitor is ignored
+        assert_eq!(
+            store.resolve(
+                std::slice::from_ref(&laptop),
+                Some(&laptop),
+                Some("DP-2"),
+                WindowGeometry::default_bar(40)
+            ),
+            Some(Placement {
+                x: 100,
+                y: 200,
+                width: 800,
+                height: 40
+            })
+        );
+        assert_ne!(
+            layout_key(&docked),
+            layout_key(&[laptop.clone(), monitor("DP-2", 1920, 3840, 2160)])
+        );
+    }
+}
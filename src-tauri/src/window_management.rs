@@ -1,57 +1,25 @@
+use crate::auto_hide;
+use crate::settings;
+use crate::types::ClickThroughState;
+use crate::window_geometry;
 use log::info;
-use tauri::{Emitter, Manager, PhysicalPosition, PhysicalSize, Position, Size};
+use tauri::{Emitter, Manager, WebviewUrl, WebviewWindow, WebviewWindowBuilder, WindowEvent};
 
+/// Put the window back where it was on the connected monitors, or make it the default bar
 #[tauri::command]
 pub async fn initialize_window_sizing(app_handle: tauri::AppHandle) -> Result<String, String> {
-    info!("Initializing window sizing to fit screen");
-
-    if let Some(window) = app_handle.get_webview_window("main") {
-        // Get primary monitor
-        let monitor = window
-            .primary_monitor()
-            .map_err(|e| format!("Failed to get primary monitor: {}", e))?;
-
-        if let Some(monitor) = monitor {
-            let size = monitor.size();
-            let screen_width = size.width as f64;
-            let _screen_height = size.height as f64;
-
-            // Set window to full screen width and minimal height (40px)
-            let window_width = screen_width;
-            let window_height = 40.0; // Ultra compact height
-
-            // Position at top-left corner
-            window
-                .set_position(Position::Physical(PhysicalPosition { x: 0, y: 0 }))
-                .map_err(|e| format!("Failed to set position: {}", e))?;
-
-            // Set size to full width and minimal height
-            window
-                .set_size(Size::Physical(PhysicalSize {
-                    width: window_width as u32,
-                    height: window_height as u32,
-                }))
-                .map_err(|e| format!("Failed to set size: {}", e))?;
-
-            info!(
-                "Window resized to {}x{} at position (0,0)",
-                window_width, window_height
-            );
-            Ok(format!(
-                "Window sized to {}x{} at top of screen",
-                window_width, window_height
-            ))
-        } else {
-            Err("No primary monitor found".to_string())
-        }
-    } else {
-        Err("Main window not found".to_string())
-    }
+    info!("Restoring window geometry");
+    let placement = window_geometry::reapply(&app_handle, "main").await?;
+    Ok(format!(
+        "Window sized to {}x{} at ({}, {})",
+        placement.width, placement.height, placement.x, placement.y
+    ))
 }
 
 #[tauri::command]
 pub async fn minimize_to_tray(app_handle: tauri::AppHandle) -> Result<String, String> {
     info!("Minimizing window to system tray");
+    auto_hide::manual_toggle(&app_handle).await;
 
     if let Some(window) = app_handle.get_webview_window("main") {
         // Hide the window (already not in taskbar by config)
@@ -69,6 +37,7 @@ pub async fn minimize_to_tray(app_handle: tauri::AppHandle) -> Result<String, St
 #[tauri::command]
 pub async fn restore_from_tray(app_handle: tauri::AppHandle) -> Result<String, String> {
     info!("Restoring window from system tray");
+    auto_hide::manual_toggle(&app_handle).await;
 
     if let Some(window) = app_handle.get_webview_window("main") {
         // Show the window (stays hidden from taskbar by config)
@@ -89,6 +58,7 @@ pub async fn restore_from_tray(app_handle: tauri::AppHandle) -> Result<String, S
 #[tauri::command]
 pub async fn toggle_window_visibility(app_handle: tauri::AppHandle) -> Result<String, String> {
     info!("Toggling window visibility");
+    auto_hide::manual_toggle(&app_handle).await;
 
     if let Some(window) = app_handle.get_webview_window("main") {
         let is_visible = window
@@ -116,9 +86,48 @@ pub async fn toggle_window_visibility(app_handle: tauri::AppHandle) -> Result<St
     }
 }
 
+/// Flip the main window between click-through and interactive; returns the new state
+pub async fn toggle_click_through(app_handle: &tauri::AppHandle) -> Result<bool, String> {
+    let window = app_handle
+        .get_webview_window("main")
+        .ok_or_else(|| "Main window not found".to_string())?;
+
+    let click_through_state = app_handle.state::<ClickThroughState>();
+    let mut state = click_through_state.lock().await;
+    let enabled = !*state;
+    window
+        .set_ignore_cursor_events(enabled)
+        .map_err(|e| format!("Failed to set click-through to {}: {}", enabled, e))?;
+    *state = enabled;
+    drop(state);
+
+    let event_name = if enabled {
+        "click-through-enabled"
+    } else {
+        "click-through-disabled"
+    };
+    app_handle
+        .emit(event_name, enabled)
+        .map_err(|e| format!("Failed to emit {} event: {}", event_name, e))?;
+
+    info!(
+        "Click-through toggled to: {} ({})",
+        if enabled { "enabled" } else { "disabled" },
+        if enabled {
+            "click-through active"
+        } else {
+            "draggable/interactive"
+        }
+    );
+    #[cfg(desktop)]
+    crate::tray::refresh(app_handle, false).await;
+    Ok(enabled)
+}
+
 #[tauri::command]
 pub async fn quit_app(app_handle: tauri::AppHandle) -> Result<(), String> {
     info!("Quitting application via command");
+    // Cleanup runs on `RunEvent::Exit`, which every way of quitting goes through
     app_handle.exit(0);
     Ok(())
 }
@@ -137,19 +146,104 @@ pub async fn enable_drag_mode(app_handle: tauri::AppHandle) -> Result<String, St
             .emit("drag-mode-enabled", true)
             .map_err(|e| format!("Failed to emit event: {}", e))?;
 
-        // Auto-disable after 5 seconds
+        // Auto-disable after the configured time
+        let drag_mode_secs = settings::current(&app_handle).await.window.drag_mode_secs;
         let app_handle_clone = app_handle.clone();
         tokio::spawn(async move {
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            tokio::time::sleep(tokio::time::Duration::from_secs(drag_mode_secs)).await;
             if let Some(window) = app_handle_clone.get_webview_window("main") {
                 let _ = window.set_ignore_cursor_events(true);
                 let _ = app_handle_clone.emit("drag-mode-disabled", false);
-                info!("Auto-disabled drag mode after 5 seconds");
+                info!("Auto-disabled drag mode after {} seconds", drag_mode_secs);
             }
         });
 
-        Ok("Drag mode enabled for 5 seconds".to_string())
+        Ok(format!("Drag mode enabled for {} seconds", drag_mode_secs))
     } else {
         Err("Main window not found".to_string())
     }
 }
+
+/// Label of the optional full-screen karaoke window
+pub const KARAOKE_WINDOW: &str = "karaoke";
+
+/// The karaoke window, created hidden on first use and placed by its own geometry
+async fn karaoke_window(app_handle: &tauri::AppHandle) -> Result<WebviewWindow, String> {
+    if let Some(window) = app_handle.get_webview_window(KARAOKE_WINDOW) {
+        return Ok(window);
+    }
+    info!("Creating karaoke window");
+    let window = WebviewWindowBuilder::new(
+        app_handle,
+        KARAOKE_WINDOW,
+        WebviewUrl::App("index.html".into()),
+    )
+    .title("Lyryc - Karaoke")
+    .decorations(false)
+    .visible(false)
+    .build()
+    .map_err(|e| format!("Failed to create karaoke window: {}", e))?;
+
+    // Closing only hides it, so the next show keeps the same webview
+    let hidden = window.clone();
+    window.on_window_event(move |event| {
+        if let WindowEvent::CloseRequested { api, .. } = event {
+            api.prevent_close();
+            let _ = hidden.hide();
+        }
+    });
+    window_geometry::reapply(app_handle, KARAOKE_WINDOW).await?;
+    window_geometry::track(&window);
+    Ok(window)
+}
+
+/// Show the karaoke window, on `monitor` if given
+#[tauri::command]
+pub async fn show_karaoke_window(
+    monitor: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    info!("Showing karaoke window");
+    let window = karaoke_window(&app_handle).await?;
+    if let Some(monitor) = monitor {
+        window_geometry::move_to_monitor(
+            monitor,
+            None,
+            Some(KARAOKE_WINDOW.to_string()),
+            app_handle.clone(),
+        )
+        .await?;
+    }
+    window
+        .show()
+        .map_err(|e| format!("Failed to show karaoke window: {}", e))?;
+    window
+        .set_focus()
+        .map_err(|e| format!("Failed to focus karaoke window: {}", e))
+}
+
+#[tauri::command]
+pub async fn hide_karaoke_window(app_handle: tauri::AppHandle) -> Result<(), String> {
+    info!("Hiding karaoke window");
+    if let Some(window) = app_handle.get_webview_window(KARAOKE_WINDOW) {
+        window
+            .hide()
+            .map_err(|e| format!("Failed to hide karaoke window: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Show or hide the karaoke window; returns whether it is now shown
+#[tauri::command]
+pub async fn toggle_karaoke_window(app_handle: tauri::AppHandle) -> Result<bool, String> {
+    let visible = app_handle
+        .get_webview_window(KARAOKE_WINDOW)
+        .map(|window| window.is_visible().unwrap_or(false))
+        .unwrap_or(false);
+    if visible {
+        hide_karaoke_window(app_handle).await?;
+    } else {
+        show_karaoke_window(None, app_handle).await?;
+    }
+    Ok(!visible)
+}
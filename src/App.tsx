@@ -28,6 +28,7 @@ function App() {
   const { getTotalOffset } = useOffsetStore();
   const { viewMode, setViewMode } = useViewModeStore();
   const [isConnected, setIsConnected] = useState(false);
+  const [pairingToken, setPairingToken] = useState<string | null>(null);
   const [isLoadingLyrics, setIsLoadingLyrics] = useState(false);
   const [connectionError, setConnectionError] = useState<string | null>(null);
   const [lyricsError, setLyricsError] = useState<string | null>(null);
@@ -44,6 +45,7 @@ function App() {
 
   const [browserTime, setBrowserTime] = useState(0);
   const [duration, setDuration] = useState(0);
+  const [isAutoFaded, setIsAutoFaded] = useState(false);
 
   // Use independent timer with periodic sync
   const { currentTime, syncWithBrowser } = useIndependentTimer(browserTime, {
@@ -70,6 +72,23 @@ function App() {
     }
   }, [currentTrack]);
 
+  // Share active lyrics with the backend so it can emit lyric-line-changed
+  useEffect(() => {
+    invoke("set_active_lyrics", {
+      lyrics: lyrics.map(({ time, text, duration }) => ({ time, text, duration })),
+    }).catch((error) => {
+      console.error("Failed to sync active lyrics with backend:", error);
+    });
+  }, [lyrics]);
+
+  // Backend line tracking (overlay, CLI, tray, karaoke) applies the same offset as the bar
+  const totalOffset = getTotalOffset(currentTrack?.artist || "", currentTrack?.title || "");
+  useEffect(() => {
+    invoke("set_lyrics_offset", { offset: totalOffset }).catch((error) => {
+      console.error("Failed to sync lyrics offset with backend:", error);
+    });
+  }, [totalOffset]);
+
   // Cleanup on unmount
   useEffect(() => {
     return () => {
@@ -212,13 +231,22 @@ function App() {
 
 
     // Initialize connection with browser extension
+    // Connected means at least one paired extension is talking to the server
+    const refreshConnectionStatus = async () => {
+      const wsStatus = await invoke<{ running: boolean; port: number | null; clients: number }>(
+        "get_websocket_status"
+      );
+      setIsConnected(wsStatus.running && wsStatus.clients > 0);
+    };
+
     const initializeConnection = async () => {
       try {
         await invoke("init_extension_connection");
 
-        // Check WebSocket status
-        const wsStatus = await invoke("get_websocket_status");
-        setIsConnected(wsStatus as boolean);
+        // Pairing code the extension popup asks for
+        setPairingToken(await invoke<string>("get_pairing_token"));
+
+        await refreshConnectionStatus();
         setConnectionError(null);
       } catch (error) {
         console.error("Failed to connect to extension:", error);
@@ -271,11 +299,48 @@ function App() {
         setIsPlaying(timeData.isPlaying);
       });
 
+      const unlistenClientConnected = await listen("client-connected", () => {
+        refreshConnectionStatus().catch(console.error);
+      });
+
+      const unlistenClientDisconnected = await listen("client-disconnected", (event) => {
+        const { clientId, reason } = event.payload as { clientId: string; reason: string };
+        console.log(`Extension client ${clientId} disconnected: ${reason}`);
+        refreshConnectionStatus().catch(console.error);
+      });
+
+      // Global shortcuts handled in Rust: nudge the current track's offset, search again
+      const unlistenOffsetAdjust = await listen("lyrics-offset-adjust", (event) => {
+        const { delta } = event.payload as { delta: number };
+        const track = useLyricsStore.getState().currentTrack;
+        if (!track) return;
+        const { getTrackOffset, setTrackOffset } = useOffsetStore.getState();
+        setTrackOffset(track.artist, track.title, getTrackOffset(track.artist, track.title) + delta);
+      });
+
+      const unlistenResearch = await listen("lyrics-research-requested", () => {
+        const track = useLyricsStore.getState().currentTrack;
+        if (track) {
+          fetchLyrics(track.title, track.artist);
+        }
+      });
+
+      // Backend auto-hide policy; in fade mode the window stays and only fades out
+      const unlistenAutoHide = await listen("auto-hide-changed", (event) => {
+        const { hidden, mode } = event.payload as { hidden: boolean; mode: "hide" | "fade" };
+        setIsAutoFaded(hidden && mode === "fade");
+      });
+
       // Return cleanup function
       return () => {
         unlistenTrack();
         unlistenPlayback();
         unlistenTimeUpdate();
+        unlistenClientConnected();
+        unlistenClientDisconnected();
+        unlistenOffsetAdjust();
+        unlistenResearch();
+        unlistenAutoHide();
       };
     };
 
@@ -400,7 +465,9 @@ function App() {
       className="h-screen relative flex items-center justify-center p-1 select-none"
       style={{ 
         background: 'transparent',
-        cursor: isClickThrough ? 'default' : 'move'
+        cursor: isClickThrough ? 'default' : 'move',
+        opacity: isAutoFaded ? 0 : 1,
+        transition: 'opacity 1s ease'
       }}
       onMouseEnter={handleMouseEnter}
       onMouseLeave={handleMouseLeave}
@@ -531,6 +598,26 @@ function App() {
               </span>
             </div>
 
+            {/* Pairing code for the browser extension */}
+            {pairingToken && (
+              <div
+                className="
+                  bg-black/20 backdrop-blur-xl border border-white/10 
+                  rounded-full px-2 py-1 shadow-lg no-drag select-text
+                "
+                style={{
+                  background: 'linear-gradient(135deg, rgba(255,255,255,0.1) 0%, rgba(255,255,255,0.05) 100%)',
+                  backdropFilter: 'blur(20px) saturate(180%)',
+                  boxShadow: '0 4px 16px rgba(0,0,0,0.2)'
+                }}
+                title="Enter this code in the Lyryc extension popup to pair"
+              >
+                <span className="text-xs text-white/60 font-mono">
+                  Pair: {pairingToken}
+                </span>
+              </div>
+            )}
+
             {/* Theme Selector */}
             <div className="
               bg-black/20 backdrop-blur-xl border border-white/10 
@@ -0,0 +1,138 @@
+import React, { useEffect, useState } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { LyricLine, TrackInfo } from '../stores/lyricsStore';
+import { useOffsetStore } from '../stores/offsetStore';
+
+// How many lines to show around the current one
+const LINES_BEFORE = 1;
+const LINES_AFTER = 2;
+
+// Full-screen lyrics for a TV or projector, fed by the same events as the lyrics bar
+const KaraokeView: React.FC = () => {
+  const [track, setTrack] = useState<TrackInfo | null>(null);
+  const [lyrics, setLyrics] = useState<LyricLine[]>([]);
+  const [currentTime, setCurrentTime] = useState(0);
+  const { getTotalOffset } = useOffsetStore();
+
+  useEffect(() => {
+    invoke<TrackInfo | null>('get_current_track').then(setTrack).catch(console.error);
+    invoke<LyricLine[]>('get_active_lyrics').then(setLyrics).catch(console.error);
+
+    const unlisteners = Promise.all([
+      listen<TrackInfo>('track-updated', (event) => setTrack(event.payload)),
+      listen<LyricLine[]>('active-lyrics-changed', (event) => setLyrics(event.payload)),
+      listen<{ currentTime: number }>('track-time-update', (event) =>
+        setCurrentTime(event.payload.currentTime)
+      ),
+    ]);
+
+    // Offsets are changed in the lyrics bar; pick them up from shared storage
+    const onStorage = (event: StorageEvent) => {
+      if (event.key === 'lyrics-offset-storage') {
+        useOffsetStore.persist.rehydrate();
+      }
+    };
+    window.addEventListener('storage', onStorage);
+
+    const onKeyDown = (event: KeyboardEvent) => {
+      if (event.key === 'Escape') {
+        invoke('hide_karaoke_window').catch(console.error);
+      }
+    };
+    window.addEventListener('keydown', onKeyDown);
+
+    return () => {
+      unlisteners.then((fns) => fns.forEach((unlisten) => unlisten()));
+      window.removeEventListener('storage', onStorage);
+      window.removeEventListener('keydown', onKeyDown);
+    };
+  }, []);
+
+  const adjustedTime = track
+    ? currentTime + getTotalOffset(track.artist, track.title)
+    : currentTime;
+
+  let currentIndex = -1;
+  for (let i = 0; i < lyrics.length && lyrics[i].time <= adjustedTime; i++) {
+    currentIndex = i;
+  }
+
+  // Progress through the current line, until its end or the next line
+  const currentLine = lyrics[currentIndex];
+  const lineEnd = currentLine
+    ? currentLine.duration
+      ? currentLine.time + currentLine.duration
+      : lyrics[currentIndex + 1]?.time
+    : undefined;
+  const progress =
+    currentLine && lineEnd && lineEnd > currentLine.time
+      ? Math.min(1, (adjustedTime - currentLine.time) / (lineEnd - currentLine.time))
+      : 0;
+
+  const first = Math.max(0, currentIndex - LINES_BEFORE);
+  const visible = lyrics.slice(first, Math.max(0, currentIndex) + LINES_AFTER + 1);
+
+  return (
+    <div className="relative w-screen h-screen overflow-hidden bg-black text-white select-none">
+      {track?.thumbnail && (
+        <div
+          className="absolute inset-0 bg-cover bg-center scale-110 blur-3xl opacity-40"
+          style={{ backgroundImage: `url(${track.thumbnail})` }}
+        />
+      )}
+
+      <div className="relative h-full flex flex-col">
+        <div className="flex items-center gap-6 p-10">
+          {track?.thumbnail && (
+            <img
+              src={track.thumbnail}
+              alt=""
+              className="w-28 h-28 rounded-xl object-cover shadow-2xl"
+            />
+          )}
+          {track && (
+            <div>
+              <div className="text-4xl font-bold">{track.title}</div>
+              <div className="text-2xl text-white/60 mt-2">{track.artist}</div>
+            </div>
+          )}
+        </div>
+
+        <div className="flex-1 flex flex-col justify-center items-center gap-8 px-16 text-center">
+          {!lyrics.length && (
+            <div className="text-4xl text-white/40">
+              {track ? '🎵 No lyrics available' : '🎵 Waiting for music...'}
+            </div>
+          )}
+          {visible.map((line, offset) => {
+            const index = first + offset;
+            if (index !== currentIndex) {
+              return (
+                <div
+                  key={index}
+                  className={`text-5xl font-semibold ${index < currentIndex ? 'text-white/25' : 'text-white/50'}`}
+                >
+                  {line.text || '♪'}
+                </div>
+              );
+            }
+            return (
+              <div
+                key={index}
+                className="text-7xl font-bold bg-clip-text text-transparent transition-all duration-300"
+                style={{
+                  backgroundImage: `linear-gradient(to right, #ffffff ${progress * 100}%, rgba(255, 255, 255, 0.5) ${progress * 100}%)`,
+                }}
+              >
+                {line.text || '♪'}
+              </div>
+            );
+          })}
+        </div>
+      </div>
+    </div>
+  );
+};
+
+export default KaraokeView;
@@ -1,10 +1,15 @@
 import React from "react";
 import ReactDOM from "react-dom/client";
+import { getCurrentWindow } from "@tauri-apps/api/window";
 import App from "./App";
+import KaraokeView from "./components/KaraokeView";
 import "./index.css";
 
+// The karaoke window loads the same page but only shows the lyrics
+const isKaraoke = getCurrentWindow().label === "karaoke";
+
 ReactDOM.createRoot(document.getElementById("root") as HTMLElement).render(
   <React.StrictMode>
-    <App />
+    {isKaraoke ? <KaraokeView /> : <App />}
   </React.StrictMode>,
-);
\ No newline at end of file
+);